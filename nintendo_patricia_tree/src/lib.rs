@@ -1,14 +1,24 @@
 use bitvec::prelude::*;
-use binread::BinRead;
+use binread::{BinRead, BinResult, ReadOptions};
+use binread::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
 
 // TODO: file read/write code
 #[derive(BinRead)]
 pub struct PatriciaTree<T: BinRead<Args=()>> {
+    #[br(parse_with = position)]
+    start: u64,
     root_index: u32,
     #[allow(unused)]
     node_count: u32,
     #[br(count = node_count)]
-    nodes: Vec<Node<T>>
+    nodes: Vec<Node<T>>,
+    #[br(parse_with = position)]
+    end: u64
+}
+
+fn position<R: Read + Seek>(reader: &mut R, _: &ReadOptions, _: ()) -> BinResult<u64> {
+    Ok(reader.seek(SeekFrom::Current(0))?)
 }
 
 fn null_ffffffff(input: [u32; 2]) -> [Option<u32>; 2] {
@@ -24,13 +34,24 @@ fn null_ffffffff(input: [u32; 2]) -> [Option<u32>; 2] {
 }
 
 #[derive(BinRead)]
-struct Node<T: BinRead<Args=()>> {
+pub struct Node<T: BinRead<Args=()>> {
+    #[br(parse_with = position)]
+    start: u64,
     #[br(map = |x: u16| x != 0)]
-    is_leaf: bool,
-    bit_index: u16,
+    pub is_leaf: bool,
+    pub bit_index: u16,
     #[br(map = null_ffffffff)]
-    next_index: [Option<u32>; 2],
-    data: T
+    pub next_index: [Option<u32>; 2],
+    pub data: T,
+    #[br(parse_with = position)]
+    end: u64
+}
+
+impl<T: BinRead<Args=()>> Node<T> {
+    /// Where in the file the node was read from.
+    pub fn position(&self) -> Range<u64> {
+        self.start..self.end
+    }
 }
 
 impl<T: BinRead<Args=()>> PatriciaTree<T> {
@@ -55,6 +76,19 @@ impl<T: BinRead<Args=()>> PatriciaTree<T> {
     pub fn get(&self, idx: usize) -> Option<&T> {
         self.nodes.get(idx).map(|node| &node.data)
     }
+
+    pub fn nodes(&self) -> &[Node<T>] {
+        &self.nodes
+    }
+
+    pub fn nodes_mut(&mut self) -> &mut [Node<T>] {
+        &mut self.nodes
+    }
+
+    /// Where in the file the tree was read from, the header and all of the nodes.
+    pub fn position(&self) -> Range<u64> {
+        self.start..self.end
+    }
 }
 
 #[cfg(test)]
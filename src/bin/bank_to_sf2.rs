@@ -0,0 +1,72 @@
+use brsar_rs::brsar::BRSAR;
+use brsar_rs::brsar::id::BankId;
+use brsar_rs::rbnk::Rbnk;
+use brsar_rs::sf2::export::export_bank;
+use binread::BinReaderExt;
+use binread::io::Cursor;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+use std::fs::File;
+use std::ops::Deref;
+use std::error::Error;
+use std::io::BufWriter;
+
+/// Exports sound banks (and the waves they use) as SoundFont 2 files.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "bank_to_sf2")]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    /// Names of the banks to export, every bank is exported if none are given
+    banks: Vec<String>,
+    #[structopt(parse(from_os_str), default_value="output/", short="o", long="output")]
+    output_folder: PathBuf
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+    let mut input_file = File::open(&opt.input)?;
+
+    let brsar: BRSAR = input_file.read_be()?;
+    let symbol = brsar.symbol.block.deref();
+    let info = brsar.info.block.deref();
+
+    let banks: Vec<(String, BankId)> = if opt.banks.is_empty() {
+        info.bank_table.ids()
+            .map(|id| (symbol.name(info.bank_table[id].string_id).unwrap_or_else(|| format!("bank_{}", id)), id))
+            .collect()
+    } else {
+        let mut banks = Vec::new();
+        for name in &opt.banks {
+            match symbol.find_bank(name) {
+                Some(id) => banks.push((name.clone(), id)),
+                None => println!("{}: no bank with that name", name)
+            }
+        }
+        banks
+    };
+
+    for (name, id) in banks {
+        let bank = match info.bank_table.get(id) {
+            Some(bank) => bank,
+            None => continue
+        };
+        let entry = match info.file_entry(bank.file_id) {
+            Some(entry) => entry,
+            None => {
+                println!("{}: external files aren't supported", name);
+                continue;
+            }
+        };
+
+        let rbnk: Rbnk = Cursor::new(entry.read_file(&mut input_file)?).read_be()?;
+        let wave_data = entry.read_archive_bytes(&mut input_file)?.unwrap_or_default();
+
+        let font = export_bank(&rbnk, &wave_data, &name);
+        println!("{}: {} presets, {} samples", name, font.presets.len(), font.samples.len());
+        font.write(&mut BufWriter::new(File::create(opt.output_folder.join(format!("{}.sf2", name)))?))?;
+    }
+
+    Ok(())
+}
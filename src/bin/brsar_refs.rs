@@ -0,0 +1,100 @@
+use brsar_rs::brsar::BRSAR;
+use brsar_rs::brsar::id::{SoundId, BankId, PlayerId, FileId, GroupId};
+use binread::BinReaderExt;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+use std::fs::File;
+use std::error::Error;
+use std::io::BufReader;
+
+/// Shows what refers to a file, bank or player of a BRSAR, and which files nothing refers to.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "brsar_refs")]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    /// Index of a file, lists the sounds, banks and groups using it
+    #[structopt(long="file")]
+    file: Option<u32>,
+    /// Name or index of a bank, lists the sequences using it
+    #[structopt(long="bank")]
+    bank: Option<String>,
+    /// Name or index of a player, lists the sounds played on it
+    #[structopt(long="player")]
+    player: Option<String>,
+    /// Lists the files no sound, bank or group refers to
+    #[structopt(long="unreferenced")]
+    unreferenced: bool
+}
+
+fn sound_name(brsar: &BRSAR, id: SoundId) -> String {
+    brsar.sound(id).and_then(|sound| sound.name()).unwrap_or_else(|| format!("sound_{}", id))
+}
+
+fn bank_name(brsar: &BRSAR, id: BankId) -> String {
+    brsar.bank(id).and_then(|bank| bank.name()).unwrap_or_else(|| format!("bank_{}", id))
+}
+
+fn group_name(brsar: &BRSAR, id: GroupId) -> String {
+    brsar.group(id).and_then(|group| group.name()).unwrap_or_else(|| format!("group_{}", id))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+
+    let brsar: BRSAR = BufReader::new(File::open(&opt.input)?).read_be()?;
+    let symbol = &brsar.symbol.block;
+    let refs = brsar.cross_references();
+
+    if let Some(file) = opt.file.map(FileId) {
+        if brsar.file(file).is_none() {
+            println!("file {}: no file with that index", file);
+        } else {
+            println!("file {}:", file);
+            for &sound in refs.sounds_using_file(file) {
+                println!("    sound {}: {}", sound, sound_name(&brsar, sound));
+            }
+            for &bank in refs.banks_using_file(file) {
+                println!("    bank {}: {}", bank, bank_name(&brsar, bank));
+            }
+            for &group in refs.groups_containing_file(file) {
+                println!("    group {}: {}", group, group_name(&brsar, group));
+            }
+        }
+    }
+
+    if let Some(name) = &opt.bank {
+        match symbol.find_bank(name).or_else(|| name.parse().ok().map(BankId)).filter(|&bank| brsar.bank(bank).is_some()) {
+            Some(bank) => {
+                println!("bank {}: {}", bank, bank_name(&brsar, bank));
+                for &sound in refs.sequences_using_bank(bank) {
+                    println!("    sequence {}: {}", sound, sound_name(&brsar, sound));
+                }
+            }
+            None => println!("{}: no bank with that name or index", name)
+        }
+    }
+
+    if let Some(name) = &opt.player {
+        match symbol.find_player(name).or_else(|| name.parse().ok().map(PlayerId)).filter(|&player| brsar.player(player).is_some()) {
+            Some(player) => {
+                let player_name = brsar.player(player).and_then(|player| player.name()).unwrap_or_default();
+                println!("player {}: {}", player, player_name);
+                for &sound in refs.sounds_on_player(player) {
+                    println!("    sound {}: {}", sound, sound_name(&brsar, sound));
+                }
+            }
+            None => println!("{}: no player with that name or index", name)
+        }
+    }
+
+    if opt.unreferenced {
+        println!("unreferenced files:");
+        for file in refs.unreferenced_files() {
+            println!("    file {}", file);
+        }
+    }
+
+    Ok(())
+}
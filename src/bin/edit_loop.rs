@@ -0,0 +1,83 @@
+use brsar_rs::rstm::Rstm;
+use brsar_rs::rstm::encode::EncodedStream;
+use brsar_rs::rwav::{EncodedWave, Rwav};
+use brsar_rs::wav::LoopPoints;
+use binread::BinReaderExt;
+use binread::io::Cursor;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+use std::fs::{self, File};
+use std::error::Error;
+use std::io::Write;
+
+/// Shows or changes the loop of a BRSTM or RWAV, without re-encoding the audio.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "edit_loop")]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    /// Where to write the edited file, only shows the current loop if not given
+    #[structopt(parse(from_os_str), short="o", long="output")]
+    output: Option<PathBuf>,
+    /// Loop start in samples
+    #[structopt(long="loop-start")]
+    loop_start: Option<u32>,
+    /// Loop end in samples, this is also the end of the sound. Defaults to the current end
+    #[structopt(long="loop-end")]
+    loop_end: Option<u32>,
+    /// Removes the loop
+    #[structopt(long="no-loop", conflicts_with_all = &["loop-start", "loop-end"])]
+    no_loop: bool
+}
+
+fn describe(loop_points: Option<LoopPoints>, sample_count: u32) -> String {
+    match loop_points {
+        Some(points) => format!("loop: {} - {} ({} samples)", points.start, points.end, sample_count),
+        None => format!("no loop ({} samples)", sample_count)
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+    let bytes = fs::read(&opt.input)?;
+
+    let new_loop = |current: Option<LoopPoints>, sample_count: u32| -> Result<Option<LoopPoints>, Box<dyn Error>> {
+        if opt.no_loop {
+            return Ok(None);
+        }
+        let start = opt.loop_start.or_else(|| current.map(|points| points.start))
+            .ok_or("--loop-start is needed to add a loop")?;
+        Ok(Some(LoopPoints { start, end: opt.loop_end.unwrap_or(sample_count) }))
+    };
+
+    let output = if bytes.starts_with(b"RSTM") {
+        let rstm: Rstm = Cursor::new(&bytes).read_be()?;
+        println!("{}", describe(rstm.loop_points(), rstm.info().sample_count));
+        if opt.output.is_none() {
+            return Ok(());
+        }
+        let mut stream = EncodedStream::from_rstm(&rstm)?;
+        stream.set_loop(new_loop(rstm.loop_points(), stream.sample_count)?)?;
+        println!("new {}", describe(stream.loop_start.map(|start| LoopPoints { start, end: stream.sample_count }), stream.sample_count));
+        stream.to_rstm()
+    } else if bytes.starts_with(b"RWAV") {
+        let rwav: Rwav = Cursor::new(&bytes).read_be()?;
+        let wave = rwav.wave();
+        println!("{}", describe(wave.loop_points(), wave.sample_count()));
+        if opt.output.is_none() {
+            return Ok(());
+        }
+        let mut encoded = EncodedWave::from_rwav(&rwav);
+        encoded.set_loop(new_loop(wave.loop_points(), encoded.sample_count)?)?;
+        println!("new {}", describe(encoded.loop_points, encoded.sample_count));
+        encoded.to_rwav()
+    } else {
+        return Err("not a BRSTM or RWAV file".into());
+    };
+
+    if let Some(path) = &opt.output {
+        File::create(path)?.write_all(&output)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use brsar_rs::rstm::Rstm;
+use brsar_rs::rstm::encode::EncodedStream;
+use binread::BinReaderExt;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+use std::fs::File;
+use std::error::Error;
+use std::io::BufWriter;
+
+/// Combines BRSTMs into one multi-track BRSTM, keeping the tracks of each input in order.
+///
+/// The inputs aren't re-encoded, so they need the same encoding, sample rate, length and loop.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "mux_rstm")]
+struct Opt {
+    #[structopt(parse(from_os_str), required = true)]
+    inputs: Vec<PathBuf>,
+    #[structopt(parse(from_os_str), short="o", long="output")]
+    output: PathBuf
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+
+    let mut streams = Vec::new();
+    for input in &opt.inputs {
+        let rstm: Rstm = File::open(input)?.read_be()?;
+        streams.push(EncodedStream::from_rstm(&rstm)?);
+    }
+
+    let stream = EncodedStream::mux(streams)?;
+    stream.write_rstm(&mut BufWriter::new(File::create(&opt.output)?))?;
+
+    // what the owning sound's StreamDetails need to be updated with, see BrsarEditor::set_stream_layout
+    println!("channels: {}, tracks: {}", stream.channels.len(), stream.tracks.len());
+    Ok(())
+}
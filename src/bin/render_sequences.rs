@@ -0,0 +1,103 @@
+use brsar_rs::brsar::BRSAR;
+use brsar_rs::brsar::block::info::{SoundDetails, SoundType};
+use brsar_rs::rbnk::Rbnk;
+use brsar_rs::rseq::Rseq;
+use brsar_rs::synth::player::RenderOptions;
+use brsar_rs::synth::sequence::render_sequence;
+use binread::BinReaderExt;
+use binread::io::Cursor;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+use std::fs::File;
+use std::ops::Deref;
+use std::error::Error;
+use std::io::BufWriter;
+
+/// Renders sequenced music in a BRSAR to WAV, using the instruments of each sequence's bank.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "render_sequences")]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    #[structopt(parse(from_os_str), default_value="output/", short="o", long="output")]
+    output_folder: PathBuf,
+    /// Only render sounds whose name contains this
+    #[structopt(short="f", long="filter")]
+    filter: Option<String>,
+    /// How many times the looping part is played
+    #[structopt(default_value="2", short="l", long="loops")]
+    loop_count: u32,
+    #[structopt(default_value="32000", short="r", long="rate")]
+    sample_rate: u32,
+    /// Maximum length of a rendered sequence, in seconds
+    #[structopt(default_value="600", long="max-length")]
+    max_seconds: f32
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+    let mut input_file = File::open(&opt.input)?;
+
+    let brsar: BRSAR = input_file.read_be()?;
+    let symbol = brsar.symbol.block.deref();
+    let info = brsar.info.block.deref();
+
+    for sound in info.sound_table.0.iter() {
+        let details = match (&sound.sound_type, sound.details.deref()) {
+            (SoundType::Sequence, SoundDetails::Sequence(details)) => details,
+            _ => continue
+        };
+
+        let name = match symbol.name(sound.string_id) {
+            Some(name) => name,
+            None => {
+                println!("string {}: no such name, skipping", sound.string_id);
+                continue;
+            }
+        };
+        if let Some(filter) = &opt.filter {
+            if !name.contains(filter.as_str()) {
+                continue;
+            }
+        }
+
+        let bank = match info.bank_table.get(details.soundbank_index) {
+            Some(bank) => bank,
+            None => {
+                println!("{}: bank {} is missing", name, details.soundbank_index);
+                continue;
+            }
+        };
+        let (seq_entry, bank_entry) = match (info.file_entry(sound.file_id), info.file_entry(bank.file_id)) {
+            (Some(seq_entry), Some(bank_entry)) => (seq_entry, bank_entry),
+            _ => {
+                println!("{}: external files aren't supported", name);
+                continue;
+            }
+        };
+
+        let rseq: Rseq = Cursor::new(seq_entry.read_file(&mut input_file)?).read_be()?;
+        let rbnk: Rbnk = Cursor::new(bank_entry.read_file(&mut input_file)?).read_be()?;
+        let wave_data = bank_entry.read_archive_bytes(&mut input_file)?.unwrap_or_default();
+
+        let options = RenderOptions {
+            output_rate: opt.sample_rate,
+            loop_count: opt.loop_count,
+            max_seconds: opt.max_seconds
+        };
+        let wav = match render_sequence(&rseq, details.seq_label_entry as usize, &rbnk, &wave_data, sound.volume, options) {
+            Some(wav) => wav,
+            None => {
+                println!("{}: label {} is missing", name, details.seq_label_entry);
+                continue;
+            }
+        };
+
+        let path = opt.output_folder.join(format!("{}.wav", name));
+        println!("{}: {:.1}s", name, wav.sample_count() as f32 / wav.sample_rate as f32);
+        wav.write(&mut BufWriter::new(File::create(path)?))?;
+    }
+
+    Ok(())
+}
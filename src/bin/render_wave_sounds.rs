@@ -0,0 +1,83 @@
+use brsar_rs::brsar::BRSAR;
+use brsar_rs::brsar::block::info::{SoundDetails, SoundType};
+use brsar_rs::rwsd::Rwsd;
+use brsar_rs::synth::wave_sound::render_wave_sound;
+use binread::BinReaderExt;
+use binread::io::Cursor;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+use std::fs::File;
+use std::ops::Deref;
+use std::error::Error;
+use std::io::BufWriter;
+
+/// Renders every wave sound (sound effect) in a BRSAR to WAV, with its note parameters applied.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "render_wave_sounds")]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    #[structopt(parse(from_os_str), default_value="output/", short="o", long="output")]
+    output_folder: PathBuf,
+    /// Only render sounds whose name contains this
+    #[structopt(short="f", long="filter")]
+    filter: Option<String>,
+    #[structopt(default_value="32000", short="r", long="rate")]
+    sample_rate: u32
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+    let mut input_file = File::open(&opt.input)?;
+
+    let brsar: BRSAR = input_file.read_be()?;
+    let symbol = brsar.symbol.block.deref();
+    let info = brsar.info.block.deref();
+
+    for sound in info.sound_table.0.iter() {
+        let details = match (&sound.sound_type, sound.details.deref()) {
+            (SoundType::Wave, SoundDetails::Wave(details)) => details,
+            _ => continue
+        };
+
+        let name = match symbol.name(sound.string_id) {
+            Some(name) => name,
+            None => {
+                println!("string {}: no such name, skipping", sound.string_id);
+                continue;
+            }
+        };
+        if let Some(filter) = &opt.filter {
+            if !name.contains(filter.as_str()) {
+                continue;
+            }
+        }
+
+        let entry = match info.file_entry(sound.file_id) {
+            Some(entry) => entry,
+            None => {
+                println!("{}: external files aren't supported", name);
+                continue;
+            }
+        };
+
+        let rwsd: Rwsd = Cursor::new(entry.read_file(&mut input_file)?).read_be()?;
+        let wave_data = entry.read_archive_bytes(&mut input_file)?.unwrap_or_default();
+
+        let wave_sound = match rwsd.sound(details.sound_data_node as usize) {
+            Some(wave_sound) => wave_sound,
+            None => {
+                println!("{}: sound data {} is missing", name, details.sound_data_node);
+                continue;
+            }
+        };
+
+        let wav = render_wave_sound(&rwsd, wave_sound, sound.volume, &wave_data, opt.sample_rate);
+        let path = opt.output_folder.join(format!("{}.wav", name));
+        println!("{}: {} samples", name, wav.sample_count());
+        wav.write(&mut BufWriter::new(File::create(path)?))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use brsar_rs::rseq::Rseq;
+use brsar_rs::rseq::text::assemble;
+use binread::BinReaderExt;
+use binread::io::Cursor;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+use std::fs::{self, File};
+use std::error::Error;
+use std::io::Write;
+
+/// Disassembles an RSEQ to text, or assembles text back into an RSEQ.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "rseq_text")]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    #[structopt(parse(from_os_str), short="o", long="output")]
+    output: PathBuf,
+    /// Assemble the input text instead of disassembling an RSEQ
+    #[structopt(short="a", long="assemble")]
+    assemble: bool
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+
+    let output = if opt.assemble {
+        let assembled = assemble(&fs::read_to_string(&opt.input)?)?;
+        println!("{} bytes of commands, {} labels", assembled.commands.len(), assembled.labels.len());
+        assembled.to_rseq()
+    } else {
+        let rseq: Rseq = Cursor::new(fs::read(&opt.input)?).read_be()?;
+        rseq.disassemble().into_bytes()
+    };
+
+    File::create(&opt.output)?.write_all(&output)?;
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use brsar_rs::rstm::stream::StreamReader;
+use brsar_rs::wav::WavWriter;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+use std::fs::File;
+use std::error::Error;
+use std::io::{BufReader, BufWriter};
+
+/// Converts a BRSTM to WAV one block at a time, writing one WAV per track if there are several.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "rstm_to_wav")]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    #[structopt(parse(from_os_str), default_value="output/", short="o", long="output")]
+    output_folder: PathBuf
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+    let mut reader = StreamReader::new(BufReader::new(File::open(&opt.input)?))?;
+    let stem = opt.input.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+
+    let info = reader.info().clone();
+    println!("{}: {:?}, {} channels, {} Hz, {} samples, loop: {} (start: {})",
+             stem, info.encoding, info.channel_count, info.sample_rate, info.sample_count, info.looping, info.loop_start);
+
+    let tracks: Vec<Vec<u8>> = if reader.tracks().len() > 1 {
+        reader.tracks().iter().map(|track| track.channels.clone()).collect()
+    } else {
+        vec![(0..info.channel_count).collect()]
+    };
+
+    let mut writers = Vec::new();
+    for (idx, channels) in tracks.iter().enumerate() {
+        let name = if tracks.len() > 1 { format!("{}_track{}.wav", stem, idx) } else { format!("{}.wav", stem) };
+        let file = BufWriter::new(File::create(opt.output_folder.join(name))?);
+        writers.push(WavWriter::new(file, info.sample_rate as u32, channels.len() as u16, info.sample_count as usize, reader.loop_points())?);
+    }
+
+    loop {
+        let chunk = reader.read_samples(info.block_samples as usize)?;
+        if chunk.iter().all(Vec::is_empty) {
+            break;
+        }
+        for (writer, channels) in writers.iter_mut().zip(&tracks) {
+            let track: Vec<Vec<i16>> = channels.iter()
+                .map(|&ch| chunk.get(ch as usize).cloned().unwrap_or_default())
+                .collect();
+            writer.write_samples(&track)?;
+        }
+    }
+    for writer in writers {
+        writer.finish()?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use brsar_rs::rwav::Rwav;
+use brsar_rs::rwar::Rwar;
+use brsar_rs::wav::Wav;
+use binread::BinReaderExt;
+
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use std::fs::File;
+use std::error::Error;
+use std::io::{BufWriter, Read, Seek, SeekFrom};
+
+/// Converts an RWAV, or every RWAV inside of an RWAR, to WAV.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "rwav_to_wav")]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    #[structopt(parse(from_os_str), default_value="output/", short="o", long="output")]
+    output_folder: PathBuf
+}
+
+fn write_wav(wav: &Wav, path: &Path) -> Result<(), Box<dyn Error>> {
+    println!("{}: {} channels, {} Hz, {} samples, loop: {:?}",
+             path.display(), wav.channels.len(), wav.sample_rate, wav.sample_count(), wav.loop_points);
+    wav.write(&mut BufWriter::new(File::create(path)?))?;
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+    let mut input_file = File::open(&opt.input)?;
+    let stem = opt.input.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+
+    let mut magic = [0u8; 4];
+    input_file.read_exact(&mut magic)?;
+    input_file.seek(SeekFrom::Start(0))?;
+
+    match &magic {
+        b"RWAR" => {
+            let rwar: Rwar = input_file.read_be()?;
+            for idx in 0..rwar.wave_count() {
+                match rwar.read_wave(idx) {
+                    Some(Ok(rwav)) => write_wav(&rwav.to_wav()?, &opt.output_folder.join(format!("{}_{}.wav", stem, idx)))?,
+                    Some(Err(err)) => println!("Failed to parse wave {}: {}", idx, err),
+                    None => println!("Entry {} lies outside of the DATA block!", idx)
+                }
+            }
+        }
+        _ => {
+            let rwav: Rwav = input_file.read_be()?;
+            write_wav(&rwav.to_wav()?, &opt.output_folder.join(format!("{}.wav", stem)))?;
+        }
+    }
+
+    Ok(())
+}
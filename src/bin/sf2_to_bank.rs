@@ -0,0 +1,51 @@
+use brsar_rs::brsar::edit::BrsarEditor;
+use brsar_rs::sf2::SoundFont;
+use brsar_rs::sf2::import::import_bank;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+use std::fs::{self, File};
+use std::error::Error;
+use std::io::Write;
+
+/// Converts a SoundFont 2 file to a sound bank (RBNK) and its wave archive (RWAR),
+/// optionally adding them to a BRSAR as a new bank.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "sf2_to_bank")]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    /// Output path without extension, .rbnk and .rwar are appended
+    #[structopt(parse(from_os_str), short="o", long="output")]
+    output: Option<PathBuf>,
+    /// BRSAR to register the bank in
+    #[structopt(parse(from_os_str), long="brsar", requires="output-brsar")]
+    brsar: Option<PathBuf>,
+    /// Where to write the updated BRSAR
+    #[structopt(parse(from_os_str), long="output-brsar")]
+    output_brsar: Option<PathBuf>
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+
+    let font = SoundFont::read(&fs::read(&opt.input)?)?;
+    let imported = import_bank(&font)?;
+    let rbnk = imported.bank.to_rbnk();
+    let rwar = imported.waves.to_bytes();
+    println!("{}: {} programs, {} waves", font.name, imported.bank.instruments.len(), imported.waves.wave_count());
+
+    if let Some(output) = &opt.output {
+        File::create(output.with_extension("rbnk"))?.write_all(&rbnk)?;
+        File::create(output.with_extension("rwar"))?.write_all(&rwar)?;
+    }
+
+    if let (Some(brsar), Some(output_brsar)) = (&opt.brsar, &opt.output_brsar) {
+        let mut editor = BrsarEditor::new(fs::read(brsar)?)?;
+        let added = editor.add_bank(&rbnk, Some(&rwar))?;
+        println!("bank index: {}, file index: {}, group index: {}", added.bank_index, added.file_index, added.group_index);
+        File::create(output_brsar)?.write_all(&editor.into_bytes())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use brsar_rs::rstm::Rstm;
+use brsar_rs::rstm::encode::EncodedStream;
+use binread::BinReaderExt;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+use std::fs::File;
+use std::error::Error;
+use std::io::BufWriter;
+
+/// Splits a multi-track BRSTM into one file per track, without re-encoding.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "split_rstm")]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    #[structopt(parse(from_os_str), default_value="output/", short="o", long="output")]
+    output_folder: PathBuf,
+    /// Write decoded WAVs instead of single-track BRSTMs
+    #[structopt(long="wav")]
+    wav: bool
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+    let mut input_file = File::open(&opt.input)?;
+
+    let rstm: Rstm = input_file.read_be()?;
+    let stem = opt.input.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+
+    if opt.wav {
+        for (idx, wav) in rstm.track_wavs()?.into_iter().enumerate() {
+            let path = opt.output_folder.join(format!("{}_track{}.wav", stem, idx));
+            wav.write(&mut BufWriter::new(File::create(path)?))?;
+        }
+    } else {
+        for (idx, track) in EncodedStream::from_rstm(&rstm)?.split_tracks().into_iter().enumerate() {
+            println!("track {}: {} channels, volume: {}, pan: {}", idx, track.channels.len(), track.tracks[0].volume, track.tracks[0].pan);
+            let path = opt.output_folder.join(format!("{}_track{}.brstm", stem, idx));
+            track.write_rstm(&mut BufWriter::new(File::create(path)?))?;
+        }
+    }
+
+    Ok(())
+}
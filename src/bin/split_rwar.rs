@@ -0,0 +1,38 @@
+use brsar_rs::rwar::Rwar;
+use binread::BinReaderExt;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+use std::fs::File;
+use std::error::Error;
+use std::io::Write;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "split_rwar")]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    #[structopt(parse(from_os_str), default_value="output/", short="o", long="output")]
+    output_folder: PathBuf
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+    let mut input_file = File::open(&opt.input)?;
+
+    let rwar: Rwar = input_file.read_be()?;
+    let stem = opt.input.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+
+    for (idx, entry) in rwar.entries().iter().enumerate() {
+        println!("{}: offset: 0x{:X}, size: 0x{:X}", idx, entry.offset, entry.size);
+        match rwar.wave(idx) {
+            Some(bytes) => {
+                let path = opt.output_folder.join(format!("{}_{}.rwav", stem, idx));
+                File::create(path)?.write_all(bytes)?;
+            }
+            None => println!("Entry {} lies outside of the DATA block!", idx)
+        }
+    }
+
+    Ok(())
+}
@@ -3,7 +3,7 @@ use std::path::PathBuf;
 use structopt::StructOpt;
 use std::error::Error;
 use std::fs::File;
-use binread::{BinReaderExt, BinRead};
+use binread::BinReaderExt;
 use std::ops::Deref;
 
 #[derive(Debug, StructOpt)]
@@ -43,8 +43,8 @@ fn main() -> Result<(), Box<dyn Error>> {
         println!("{}: (external: {:?})", idx, external);
         for pos in file.file_positions.deref().0.iter() {
             let group_name = brsar.symbol.block.group_tree
-                .get(pos.group_index as usize)
-                .and_then(|group| (brsar.symbol.block.string_table.0).get(group.string_index as usize))
+                .get(pos.group_index.index())
+                .and_then(|group| brsar.symbol.block.string_table.get(group.string_index))
                 .map(|name| name.to_string())
                 .unwrap_or_else(|| pos.group_index.to_string());
             println!("    {}: {}", group_name, pos.item_index);
@@ -1,4 +1,5 @@
 use brsar_rs::common::*;
+use brsar_rs::file::NintendoFile;
 
 use binread::BinReaderExt;
 use structopt::StructOpt;
@@ -16,7 +17,7 @@ struct Opt {
 fn main() -> Result<(), Box<dyn Error>> {
     let opt = Opt::from_args();
 
-    let mut file = File::open(opt.input)?;
+    let mut file = File::open(&opt.input)?;
 
     let file: GenericFile = file.read_be()?;
 
@@ -33,5 +34,16 @@ fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
+    match NintendoFile::open(&opt.input)? {
+        NintendoFile::Rsar(_) => println!("sound archive"),
+        NintendoFile::Rseq(rseq) => println!("sequence, {} labels", rseq.labels().count()),
+        NintendoFile::Rbnk(rbnk) => println!("bank, {} programs", rbnk.instruments().len()),
+        NintendoFile::Rwsd(rwsd) => println!("wave sounds, {} sounds", rwsd.data.block.sounds.0.len()),
+        NintendoFile::Rwar(rwar) => println!("wave archive, {} waves", rwar.wave_count()),
+        NintendoFile::Rwav(rwav) => println!("wave, {} samples", rwav.wave().sample_count()),
+        NintendoFile::Rstm(rstm) => println!("stream, {} tracks, {} samples", rstm.tracks().count(), rstm.info().sample_count),
+        NintendoFile::Unknown(_) => println!("unknown format"),
+    }
+
     Ok(())
 }
\ No newline at end of file
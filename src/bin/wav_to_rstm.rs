@@ -0,0 +1,61 @@
+use brsar_rs::brsar::edit::BrsarEditor;
+use brsar_rs::brsar::id::SoundId;
+use brsar_rs::common::SoundEncoding;
+use brsar_rs::rstm::encode::EncodedStream;
+use brsar_rs::wav::{Wav, LoopPoints};
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+use std::fs::File;
+use std::error::Error;
+use std::io::{BufReader, BufWriter};
+
+/// Encodes one or more WAVs as a BRSTM, one track per input.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "wav_to_rstm")]
+struct Opt {
+    #[structopt(parse(from_os_str), required = true)]
+    inputs: Vec<PathBuf>,
+    #[structopt(parse(from_os_str), short="o", long="output")]
+    output: PathBuf,
+    /// Store as PCM16 instead of DSP-ADPCM
+    #[structopt(long="pcm16")]
+    pcm16: bool,
+    /// Loop start in samples, overrides any smpl chunk
+    #[structopt(long="loop-start")]
+    loop_start: Option<u32>,
+    /// BRSAR whose stream sound gets updated with the new channel count and tracks, in place
+    #[structopt(parse(from_os_str), long="brsar", requires="sound")]
+    brsar: Option<PathBuf>,
+    /// Index of the stream sound, with --brsar
+    #[structopt(long="sound")]
+    sound: Option<u32>
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+
+    let mut wavs = Vec::new();
+    for input in &opt.inputs {
+        wavs.push(Wav::read(&mut BufReader::new(File::open(input)?))?);
+    }
+    if let Some(start) = opt.loop_start {
+        for wav in wavs.iter_mut() {
+            wav.loop_points = None;
+        }
+        let end = wavs[0].sample_count() as u32;
+        wavs[0].loop_points = Some(LoopPoints { start, end });
+    }
+
+    let encoding = if opt.pcm16 { SoundEncoding::SPcm16 } else { SoundEncoding::DspAdpcm };
+    let stream = EncodedStream::encode(&wavs, encoding)?;
+    stream.write_rstm(&mut BufWriter::new(File::create(&opt.output)?))?;
+
+    println!("channels: {}, tracks: {}", stream.channels.len(), stream.tracks.len());
+    if let (Some(path), Some(sound)) = (&opt.brsar, opt.sound) {
+        let mut editor = BrsarEditor::new(std::fs::read(path)?)?;
+        editor.set_stream_layout(SoundId(sound), stream.channels.len() as u16, stream.tracks.len() as u8)?;
+        std::fs::write(path, editor.into_bytes())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use brsar_rs::brsar::BRSAR;
+use brsar_rs::brsar::edit::BrsarEditor;
+use brsar_rs::brsar::id::GroupId;
+use brsar_rs::common::SoundEncoding;
+use brsar_rs::rwav::EncodedWave;
+use brsar_rs::rwar::{Rwar, RwarBuilder};
+use brsar_rs::wav::Wav;
+use binread::BinReaderExt;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+use std::fs::File;
+use std::error::Error;
+use std::io::{BufReader, Cursor, Write};
+
+/// Encodes a WAV as an RWAV, optionally replacing (or appending) a wave in an RWAR, either
+/// standalone or the one belonging to an entry of a BRSAR group.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "wav_to_rwav")]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    #[structopt(parse(from_os_str), short="o", long="output")]
+    output: PathBuf,
+    /// Store as PCM16 instead of DSP-ADPCM
+    #[structopt(long="pcm16")]
+    pcm16: bool,
+    /// Wave archive to insert the wave into, the output will be the updated RWAR
+    #[structopt(parse(from_os_str), long="rwar")]
+    rwar: Option<PathBuf>,
+    /// BRSAR to insert the wave into, the output will be the updated BRSAR
+    #[structopt(parse(from_os_str), long="brsar", conflicts_with="rwar")]
+    brsar: Option<PathBuf>,
+    /// Group holding the entry whose wave archive is updated, with --brsar
+    #[structopt(long="group", default_value="0")]
+    group: u32,
+    /// Index of the entry in the group, with --brsar
+    #[structopt(long="entry", default_value="0")]
+    entry: usize,
+    /// Index of the wave to replace, appends a new wave if not given
+    #[structopt(long="index")]
+    index: Option<usize>
+}
+
+fn insert(builder: &mut RwarBuilder, rwav: Vec<u8>, index: Option<usize>) -> Result<(), Box<dyn Error>> {
+    let index = match index {
+        Some(index) => {
+            builder.replace(index, rwav).ok_or("wave index out of range")?;
+            index
+        }
+        None => builder.push(rwav)
+    };
+    println!("wave index: {}", index);
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+
+    let wav = Wav::read(&mut BufReader::new(File::open(&opt.input)?))?;
+    let encoding = if opt.pcm16 { SoundEncoding::SPcm16 } else { SoundEncoding::DspAdpcm };
+    let rwav = EncodedWave::encode(&wav, encoding).to_rwav();
+
+    let output = if let Some(path) = &opt.brsar {
+        let data = std::fs::read(path)?;
+        let mut builder = {
+            let brsar: BRSAR = Cursor::new(&data).read_be()?;
+            let group = brsar.info.block.group_table.get(GroupId(opt.group)).ok_or("group index out of range")?;
+            let entry = group.entries.0.get(opt.entry).ok_or("group entry index out of range")?;
+            entry.archive_builder(&mut Cursor::new(&data))?
+        };
+        insert(&mut builder, rwav, opt.index)?;
+
+        let mut editor = BrsarEditor::new(data)?;
+        editor.replace_archive(GroupId(opt.group), opt.entry, &builder.to_bytes())?;
+        editor.into_bytes()
+    } else if let Some(path) = &opt.rwar {
+        let rwar: Rwar = File::open(path)?.read_be()?;
+        let mut builder = RwarBuilder::from_rwar(&rwar);
+        insert(&mut builder, rwav, opt.index)?;
+        builder.to_bytes()
+    } else {
+        rwav
+    };
+
+    File::create(&opt.output)?.write_all(&output)?;
+    Ok(())
+}
@@ -1,27 +1,55 @@
 #![allow(unused)]
 
+use crate::brsar::id::{BankId, PlayerId, FileId, GroupId, StringId};
+use crate::brsar::source::{ArchiveSource, ReaderSource};
 use crate::common::*;
-use binread::{BinRead, FilePtr32};
+use crate::common::binread_utils::CurPos;
+use crate::rwar::{Rwar, RwarBuilder};
+use binread::{BinRead, BinReaderExt, BinResult, FilePtr32};
+use binread::io::{Cursor, Read, Seek, SeekFrom};
+use std::borrow::Cow;
 use std::convert::TryFrom;
+use std::io;
+use std::ops::{Deref, Range};
 
+/// In `Lazy` mode, the tables and their entries are only read when resolved.
 #[derive(BinRead)]
-pub struct InfoBlock {
+pub struct InfoBlock<M: Mode = Eager> {
     pub header: BlockHeader,
-    pub sound_table: Reference<Table<Reference<SoundInfo>>>,
-    pub bank_table: Reference<Table<Reference<() /* BankInfo*/>>>,
-    pub player_table: Reference<Table<Reference<() /*PlayerInfo*/>>>,
-    pub file_table: Reference<Table<Reference<FileInfo>>>,
-    pub group_table: Reference<Table<Reference<GroupInfo>>>,
+    pub sound_table: Reference<Table<Reference<SoundInfo, M>>, M>,
+    pub bank_table: Reference<Table<Reference<BankInfo, M>>, M>,
+    pub player_table: Reference<Table<Reference<PlayerInfo, M>>, M>,
+    pub file_table: Reference<Table<Reference<FileInfo, M>>, M>,
+    pub group_table: Reference<Table<Reference<GroupInfo, M>>, M>,
     //#[br(align_after = 0x20)]
-    pub sound_archive_info: Reference<SoundArchiveInfo>
+    pub sound_archive_info: Reference<SoundArchiveInfo, M>
+}
+
+/// The whole block, including everything the tables point to.
+impl<M: Mode> Spanned for InfoBlock<M> {
+    fn span(&self) -> Span {
+        Span { offset: self.header.span().offset, len: self.header.size as u64 }
+    }
+}
+
+impl InfoBlock {
+    /// Finds the (first) group entry holding a file, which gives access to its data and wave archive.
+    pub fn file_entry(&self, file_id: FileId) -> Option<&GroupEntry> {
+        let file = self.file_table.get(file_id)?;
+        let pos = file.file_positions.0.first()?;
+        let group = self.group_table.get(pos.group_index)?;
+        group.entries.0.get(pos.item_index as usize).map(Deref::deref)
+    }
 }
 
 // TODO: version differences
 #[derive(BinRead)]
 pub struct SoundInfo {
-    pub string_id: u32, //TypedId,
-    pub file_id: u32, // TypedId,
-    pub player_id: TypedId,
+    span_start: CurPos,
+    pub string_id: StringId, //TypedId,
+    pub file_id: FileId, // TypedId,
+    #[br(map = |id: TypedId| PlayerId::from(id))]
+    pub player_id: PlayerId,
     pub sound_info_3d: Reference<()>,
     pub volume: u8,
     pub player_priority: u8,
@@ -32,7 +60,9 @@ pub struct SoundInfo {
     pub pan_mode: PanMode,
     pub pan_curve: PanCurve,
     pub actor_player_id: u8,
-    pub reserved: u8
+    pub reserved: u8,
+    #[br(args(span_start.0))]
+    span: Span
 }
 
 #[derive(BinRead)]
@@ -56,30 +86,48 @@ pub enum SoundDetails {
 // from tockdom wiki
 #[derive(BinRead)]
 pub struct SeqDetails {
-    seq_label_entry: u32,
-    soundbank_index: u32,
-    unknown: [u8; 3], // part of alloc_track?
-    alloc_track: u8, // not u16?
-    priority: u8,
-    unknown2: [u8; 7] // unknown
+    span_start: CurPos,
+    pub seq_label_entry: u32, // index into the RSEQ's LABL block
+    pub soundbank_index: BankId,
+    pub unknown: [u8; 3], // part of alloc_track?
+    pub alloc_track: u8, // not u16?
+    pub priority: u8,
+    pub unknown2: [u8; 7], // unknown
+    #[br(args(span_start.0))]
+    span: Span
 }
 
 #[derive(BinRead)]
 pub struct StreamDetails {
-    start_pos: u32,
-    unknown: u8,
-    channel_count: u8, // maybe in older versions this + unknown was bitmask, as mentioned by gota?
-    alloc_track: u8, // *shrug*
-    unknown2: [u8; 5]
+    span_start: CurPos,
+    pub start_pos: u32,
+    pub channel_count: u16,
+    pub alloc_track: u16, // bitmask of the allocated tracks
+    pub reserved: u32,
+    #[br(args(span_start.0))]
+    span: Span
+}
+
+impl Spanned for SoundDetails {
+    fn span(&self) -> Span {
+        match self {
+            SoundDetails::Sequence(details) => details.span(),
+            SoundDetails::Stream(details) => details.span(),
+            SoundDetails::Wave(details) => details.span()
+        }
+    }
 }
 
 #[derive(BinRead)]
 pub struct WaveDetails {
-    sound_data_node: u32,
-    unknown: [u8; 3], // part of alloc_track?
-    alloc_track: u8,
-    priority: u8,
-    unknown2: [u8; 7]
+    span_start: CurPos,
+    pub sound_data_node: u32, // index into the RWSD's DATA block
+    pub unknown: [u8; 3], // part of alloc_track?
+    pub alloc_track: u8,
+    pub priority: u8,
+    pub unknown2: [u8; 7],
+    #[br(args(span_start.0))]
+    span: Span
 }
 
 #[derive(BinRead)]
@@ -105,22 +153,30 @@ pub enum PanCurve {
 
 #[derive(BinRead)]
 pub struct BankInfo {
-    string_id: TypedId,
-    file_id: TypedId,
-    reserved: u32
+    span_start: CurPos,
+    pub string_id: StringId, //TypedId,
+    pub file_id: FileId, //TypedId,
+    reserved: u32,
+    #[br(args(span_start.0))]
+    span: Span
 }
 
 #[derive(BinRead)]
 pub struct PlayerInfo {
-    string_id: TypedId,
-    max_sounds: u8, // maybe u32?
+    span_start: CurPos,
+    #[br(map = |id: TypedId| StringId::from(id))]
+    pub string_id: StringId,
+    pub max_sounds: u8, // maybe u32?
     padding: [u8; 3],
-    heap_space: u32,
-    reserved: u32
+    pub heap_space: u32,
+    reserved: u32,
+    #[br(args(span_start.0))]
+    span: Span
 }
 
 #[derive(BinRead)]
 pub struct FileInfo {
+    span_start: CurPos,
     pub file_size: u32,
     pub archive_size: u32, // "length of audio data, null for external or rseq"
     pub file_id: s32, // RhythmRevolution not clear on what this is, maybe an entry number? always 0xFFFFFFFF according to tockdom
@@ -130,17 +186,24 @@ pub struct FileInfo {
     pub file_positions: Reference<Table<Reference<FilePosition>>>,
     // tockdom places a single FilePosition here:
     // file_position: FilePosition
+    #[br(args(span_start.0))]
+    span: Span
 }
 
 #[derive(BinRead)]
 pub struct FilePosition {
-    pub group_index: u32,
-    pub item_index: u32
+    span_start: CurPos,
+    pub group_index: GroupId,
+    pub item_index: u32, // index into GroupInfo::entries
+    #[br(args(span_start.0))]
+    span: Span
 }
 
 #[derive(BinRead)]
 pub struct GroupInfo {
-    string_id: TypedId, // file name index
+    span_start: CurPos,
+    #[br(map = |id: TypedId| StringId::from(id))]
+    pub string_id: StringId, // file name index
     group_id: s32, // actually unknown, always 0xFFFFFFFF?
     external_file: u64 /*Reference<NullString>*/,
     pub file_base: u32,
@@ -150,34 +213,105 @@ pub struct GroupInfo {
     #[br(args(file_base as u64, archive_base as u64))]
     pub entries: Reference<Table<Reference<GroupEntry>>>,
     // the table itself usually follows immediately afterward
+    #[br(args(span_start.0))]
+    span: Span
 }
 
-#[derive(BinRead)]
-pub struct File(#[br(parse_with = binread::helpers::read_bytes)] pub Vec<u8>);
-
 #[derive(BinRead)]
 #[br(import(file_base: u64, archive_base: u64))]
 pub struct GroupEntry {
-    file_id: TypedId, // file_table index? sound index?
+    span_start: CurPos,
+    #[br(map = |id: TypedId| FileId::from(id))]
+    pub file_id: FileId,
     // nintendo, why do you have to put size after the offsets :(
     // this is probably temporary until Vec<u8> gets replaced with a more appropriate type?
     // #[br(restore_position, map = |(_, size): (u32, u32)| size)]
     // pub file_size: u32,
     // #[br(offset = file_base, count = file_size)]
-    pub file_offset: binread::PosValue<u32>,//FilePtr32<File>, // type? // TODO: this is inefficient, since multiple groups can refer to the same file.
+    pub file_offset: binread::PosValue<u32>, // type?
     pub file_size: u32,
     // nintendo, why do you have to put size after the offsets :(
     // this is probably temporary until Vec<u8> gets replaced with a more appropriate type?
     // #[br(restore_position, map = |(_, size): (u32, u32)| size)]
     // archive_size: u32,
-    #[br(offset = archive_base)]
-    archive_offset: FilePtr32<()>, // type? file or subsection?
-    archive_size: u32, // file or subsection?
-    reserved: u32
+    pub archive_offset: u32, // from GroupInfo::archive_base, 0 if this file has no wave archive
+    pub archive_size: u32,
+    reserved: u32,
+    #[br(args(span_start.0))]
+    span: Span,
+    #[br(calc = file_base)]
+    pub file_base: u64,
+    #[br(calc = archive_base)]
+    pub archive_base: u64
+}
+
+impl GroupEntry {
+    /// Where the file this entry refers to is, from the start of the BRSAR.
+    pub fn file_range(&self) -> Range<usize> {
+        let start = (self.file_base + self.file_offset.val as u64) as usize;
+        start..start + self.file_size as usize
+    }
+
+    /// Where the wave archive belonging to this entry is, if it has one.
+    pub fn archive_range(&self) -> Option<Range<usize>> {
+        let start = self.archive_position()? as usize;
+        Some(start..start + self.archive_size as usize)
+    }
+
+    /// The raw bytes of the file (RSEQ, RWSD, RBNK, ...) this entry refers to.
+    pub fn file_bytes<'s, S: ArchiveSource>(&self, source: &'s mut S) -> io::Result<Cow<'s, [u8]>> {
+        source.read_at(self.file_range().start as u64, self.file_size as usize)
+    }
+
+    /// The raw bytes of the wave data belonging to this entry, if any.
+    pub fn archive_bytes<'s, S: ArchiveSource>(&self, source: &'s mut S) -> io::Result<Option<Cow<'s, [u8]>>> {
+        match self.archive_position() {
+            Some(pos) => source.read_at(pos, self.archive_size as usize).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    /// Reads the raw bytes of the file (RSEQ, RWSD, RBNK, ...) this entry refers to.
+    pub fn read_file<R: io::Read + io::Seek>(&self, reader: &mut R) -> BinResult<Vec<u8>> {
+        Ok(self.file_bytes(&mut ReaderSource(reader))?.into_owned())
+    }
+
+    /// Absolute position of the wave archive (RWAR) belonging to this RWSD or RBNK, if any.
+    pub fn archive_position(&self) -> Option<u64> {
+        if self.archive_size == 0 {
+            None
+        } else {
+            Some(self.archive_base + self.archive_offset as u64)
+        }
+    }
+
+    /// Reads the raw bytes of the wave data belonging to this entry, if any.
+    pub fn read_archive_bytes<R: io::Read + io::Seek>(&self, reader: &mut R) -> BinResult<Option<Vec<u8>>> {
+        Ok(self.archive_bytes(&mut ReaderSource(reader))?.map(Cow::into_owned))
+    }
+
+    /// Reads the wave archive belonging to this entry from the BRSAR it was parsed from.
+    pub fn read_archive<R: io::Read + io::Seek>(&self, reader: &mut R) -> BinResult<Option<Rwar>> {
+        // the RWAR's block pointers are relative to its own start, so parse it from its own buffer
+        match self.read_archive_bytes(reader)? {
+            Some(bytes) => Ok(Some(Cursor::new(bytes).read_be()?)),
+            None => Ok(None)
+        }
+    }
+
+    /// Starts a new wave archive for this entry from its current contents, or from scratch if it has none.
+    /// Write it back with `BrsarEditor::replace_archive`.
+    pub fn archive_builder<R: io::Read + io::Seek>(&self, reader: &mut R) -> BinResult<RwarBuilder> {
+        Ok(match self.read_archive(reader)? {
+            Some(rwar) => RwarBuilder::from_rwar(&rwar),
+            None => RwarBuilder::new()
+        })
+    }
 }
 
 #[derive(BinRead)]
 pub struct SoundArchiveInfo {
+    span_start: CurPos,
     max_sequences: u16,
     max_seq_tracks: u16,
     max_streams: u16,
@@ -186,5 +320,57 @@ pub struct SoundArchiveInfo {
     max_waves: u16,
     max_wave_tracks: u16,
     padding: u16,
-    reserved: u32
-}
\ No newline at end of file
+    reserved: u32,
+    #[br(args(span_start.0))]
+    span: Span
+}
+
+macro_rules! spanned {
+    ($($ty:ty),*) => {
+        $(
+            impl Spanned for $ty {
+                fn span(&self) -> Span {
+                    self.span
+                }
+            }
+        )*
+    };
+}
+
+spanned!(SoundInfo, SeqDetails, StreamDetails, WaveDetails, BankInfo, PlayerInfo, FileInfo, FilePosition, GroupInfo, GroupEntry, SoundArchiveInfo);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brsar::BRSAR;
+    use crate::brsar::test_data::test_brsar_with_bank;
+
+    #[test]
+    fn spans() {
+        let data = test_brsar_with_bank();
+        let brsar: BRSAR = Cursor::new(&data).read_be().unwrap();
+        assert_eq!(brsar.header.span(), Span { offset: 0, len: 0x10 });
+        let info: &InfoBlock = &brsar.info.block;
+        assert_eq!(info.span(), brsar.info.span());
+        assert_eq!(info.header.span(), Span { offset: brsar.info.offset(), len: 8 });
+
+        let info_base = brsar.info.offset() + 8;
+        let groups = &info.group_table;
+        assert_eq!(groups.span(), Span { offset: info_base + 0x20, len: 8 }); // the reference to the table
+        assert_eq!((**groups).span().len, 4 + 2 * 8);
+        for group in groups.0.iter() {
+            assert_eq!(group.span().len, 8);
+            assert_eq!(group.deref().span(), Span { offset: group.pos(info_base), len: 0x28 });
+        }
+
+        let group = &groups[GroupId(1)];
+        let entry: &GroupEntry = &group.entries.0[0];
+        assert_eq!(entry.span().len, 0x18);
+        assert_eq!(&data[entry.span().offset as usize + 8..][..4], &0x24u32.to_be_bytes()); // file size
+
+        let file = &info.file_table[FileId(0)];
+        assert_eq!(file.span().len, 0x1C);
+        assert_eq!(file.file_positions.0[0].deref().span().len, 8);
+        assert_eq!(info.bank_table[BankId(0)].span().len, 0x0C);
+    }
+}
@@ -1,7 +1,9 @@
 #![allow(unused)]
 
 use crate::common::*;
+use crate::common::binread_utils::CurPos;
 use super::info::{SoundInfo, PlayerInfo, GroupInfo, BankInfo};
+use crate::brsar::id::{SoundId, PlayerId, GroupId, BankId, StringId};
 
 use std::convert::TryFrom;
 use std::marker::PhantomData;
@@ -28,23 +30,127 @@ use binread::FilePtr32;
 //     const MAGIC: [u8; 4] = *b"SYMB";
 // }
 
-type PatriciaTree/*<T>*/ = nintendo_patricia_tree::PatriciaTree<TreeData/*<T>*/>;
+type PatriciaTree<Id> = nintendo_patricia_tree::PatriciaTree<TreeData<Id>>;
 
+/// In `Lazy` mode, the string table, the names and the trees are only read when resolved.
 #[derive(BinRead)]
-pub struct SymbolBlock {
+pub struct SymbolBlock<M: Mode = Eager> {
     pub header: BlockHeader,
-    pub string_table: r32<Table<r32<NullString>>>,
-    pub sound_tree: r32<PatriciaTree/*<SoundInfo>*/>,
-    pub player_tree: r32<PatriciaTree/*<PlayerInfo>*/>,
-    pub group_tree: r32<PatriciaTree/*<GroupInfo>*/>,
-    pub bank_tree: r32<PatriciaTree/*<BankInfo>*/>,
+    pub string_table: r32<Table<r32<NullString, M>>, M>,
+    pub sound_tree: r32<PatriciaTree<SoundId>, M>,
+    pub player_tree: r32<PatriciaTree<PlayerId>, M>,
+    pub group_tree: r32<PatriciaTree<GroupId>, M>,
+    pub bank_tree: r32<PatriciaTree<BankId>, M>,
     //name_table: Table<r32<CString>>, location coincidence.
 }
 
 // TODO: rename to something to do with indices?
 #[derive(BinRead)]
-pub struct TreeData/*<T>*/ {
-    pub string_index: u32,
-    pub item_index: u32, // in info
-    /*_phantom: PhantomData<T>*/
-}
\ No newline at end of file
+pub struct TreeData<Id: BinRead<Args = ()>> {
+    span_start: CurPos,
+    pub string_index: StringId,
+    pub item_index: Id, // in info
+    #[br(args(span_start.0))]
+    span: Span
+}
+
+impl<Id: BinRead<Args = ()>> Spanned for TreeData<Id> {
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+/// The header and every node.
+impl<Id: BinRead<Args = ()>> Spanned for PatriciaTree<Id> {
+    fn span(&self) -> Span {
+        let position = self.position();
+        Span { offset: position.start, len: position.end - position.start }
+    }
+}
+
+impl<Id: BinRead<Args = ()>> Spanned for nintendo_patricia_tree::Node<TreeData<Id>> {
+    fn span(&self) -> Span {
+        let position = self.position();
+        Span { offset: position.start, len: position.end - position.start }
+    }
+}
+
+/// The whole block, including the strings and trees.
+impl<M: Mode> Spanned for SymbolBlock<M> {
+    fn span(&self) -> Span {
+        Span { offset: self.header.span().offset, len: self.header.size as u64 }
+    }
+}
+
+impl SymbolBlock {
+    pub fn name(&self, string_id: StringId) -> Option<String> {
+        self.string_table.get(string_id).map(|name| name.to_string())
+    }
+
+    /// Looks up the info block index of an item by name.
+    fn find<Id: BinRead<Args = ()> + Copy>(&self, tree: &PatriciaTree<Id>, name: &str) -> Option<Id> {
+        let data = tree.search(name.as_bytes())?;
+        // the search always ends up at a leaf, check that it's the one we were looking for
+        if self.name(data.string_index)? == name {
+            Some(data.item_index)
+        } else {
+            None
+        }
+    }
+
+    pub fn find_sound(&self, name: &str) -> Option<SoundId> {
+        self.find(&self.sound_tree, name)
+    }
+
+    pub fn find_player(&self, name: &str) -> Option<PlayerId> {
+        self.find(&self.player_tree, name)
+    }
+
+    pub fn find_group(&self, name: &str) -> Option<GroupId> {
+        self.find(&self.group_tree, name)
+    }
+
+    pub fn find_bank(&self, name: &str) -> Option<BankId> {
+        self.find(&self.bank_tree, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binread::ReadOptions;
+    use binread::io::Cursor;
+
+    #[test]
+    fn spans() {
+        // string table at 0x14, one tree at 0x1C shared by all four, its name at 0x38
+        let mut data = Vec::new();
+        data.extend_from_slice(b"SYMB");
+        for value in &[0x44u32, 0x14, 0x1C, 0x1C, 0x1C, 0x1C, 1, 0x38, 0, 1, 0x0001_0000, 0xFFFF_FFFF, 0xFFFF_FFFF, 0, 0] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(b"BGM\0");
+
+        let mut options = ReadOptions::default();
+        options.endian = binread::Endian::Big;
+        options.offset = 8;
+        let mut reader = Cursor::new(&data);
+        let mut symbol: SymbolBlock = SymbolBlock::read_options(&mut reader, &options, ()).unwrap();
+        symbol.after_parse(&mut reader, &options, ()).unwrap();
+
+        assert_eq!(symbol.span(), Span { offset: 0, len: 0x44 });
+        assert_eq!(symbol.header.span(), Span { offset: 0, len: 8 });
+        assert_eq!(symbol.string_table.span(), Span { offset: 8, len: 4 });
+        assert_eq!((*symbol.string_table).span(), Span { offset: 0x1C, len: 8 });
+        assert_eq!(symbol.string_table.0[0].span(), Span { offset: 0x20, len: 4 });
+        assert_eq!((*symbol.string_table.0[0]).span(), Span { offset: 0x40, len: 4 });
+
+        assert_eq!(symbol.bank_tree.span(), Span { offset: 0x18, len: 4 });
+        let tree: &PatriciaTree<BankId> = &symbol.bank_tree;
+        assert_eq!(tree.span(), Span { offset: 0x24, len: 0x1C });
+        let node = &tree.nodes()[0];
+        assert_eq!(node.span(), Span { offset: 0x2C, len: 0x14 });
+        assert_eq!(node.data.span(), Span { offset: 0x38, len: 8 });
+        assert_eq!(symbol.find_bank("BGM"), Some(BankId(0)));
+    }
+}
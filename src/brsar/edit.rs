@@ -0,0 +1,495 @@
+//! In-place edits of a BRSAR, working directly on its bytes.
+//!
+//! Tables in the INFO block can't grow in place, so edits append new copies of the tables to the
+//! end of the INFO block and repoint the references to them. The FILE block moves back by the
+//! amount the INFO block grew, so the (absolute) group offsets are shifted to match. New files are
+//! appended to the end of the FILE block, which has to be the last block of the archive.
+
+use super::id::{BankId, FileId, GroupId, SoundId};
+use crate::common::align_up;
+use std::io;
+
+const INFO_BLOCK: usize = 1;
+const FILE_BLOCK: usize = 2;
+
+// offsets of the references at the start of the INFO block body
+const SOUND_TABLE: usize = 0x00;
+const BANK_TABLE: usize = 0x08;
+const FILE_TABLE: usize = 0x18;
+const GROUP_TABLE: usize = 0x20;
+
+const BANK_INFO_LEN: usize = 0x0C;
+const FILE_INFO_LEN: usize = 0x1C;
+const FILE_POSITION_LEN: usize = 0x08;
+const GROUP_INFO_LEN: usize = 0x28;
+
+/// Index of a bank registered with `BrsarEditor::add_bank`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AddedBank {
+    pub bank_index: BankId,
+    pub file_index: FileId,
+    pub group_index: GroupId,
+}
+
+pub struct BrsarEditor {
+    data: Vec<u8>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(super) fn reference(offset: usize) -> [u8; 8] {
+    let mut reference = [1, 0, 0, 0, 0, 0, 0, 0];
+    reference[4..].copy_from_slice(&(offset as u32).to_be_bytes());
+    reference
+}
+
+impl BrsarEditor {
+    pub fn new(data: Vec<u8>) -> io::Result<BrsarEditor> {
+        if data.len() < 0x28 || &data[0..4] != b"RSAR" || data[4..6] != [0xFE, 0xFF] {
+            return Err(invalid("not a big endian BRSAR"));
+        }
+        let editor = BrsarEditor { data };
+        let (info_offset, info_size) = editor.block(INFO_BLOCK)?;
+        let (file_offset, file_size) = editor.block(FILE_BLOCK)?;
+        if info_size < 8 || info_offset + info_size > file_offset {
+            return Err(invalid("the INFO block has to come before the FILE block"));
+        }
+        if file_offset + file_size != editor.data.len() {
+            return Err(invalid("the FILE block has to be the last block"));
+        }
+        Ok(editor)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    fn bytes(&self, offset: usize, len: usize) -> io::Result<&[u8]> {
+        offset.checked_add(len)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or_else(|| invalid("offset out of bounds"))
+    }
+
+    fn set_bytes(&mut self, offset: usize, bytes: &[u8]) -> io::Result<()> {
+        offset.checked_add(bytes.len())
+            .and_then(|end| self.data.get_mut(offset..end))
+            .ok_or_else(|| invalid("offset out of bounds"))?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn u32_at(&self, offset: usize) -> io::Result<u32> {
+        let bytes = self.bytes(offset, 4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn set_u32(&mut self, offset: usize, value: u32) -> io::Result<()> {
+        self.set_bytes(offset, &value.to_be_bytes())
+    }
+
+    fn block(&self, idx: usize) -> io::Result<(usize, usize)> {
+        let (offset, size) = (self.u32_at(0x10 + idx * 8)? as usize, self.u32_at(0x14 + idx * 8)? as usize);
+        self.bytes(offset, size)?;
+        Ok((offset, size))
+    }
+
+    /// Start of the INFO block body, which references are relative to.
+    fn info_base(&self) -> io::Result<usize> {
+        Ok(self.block(INFO_BLOCK)?.0 + 8)
+    }
+
+    /// The raw references of the table an INFO block reference points to.
+    fn info_table(&self, reference: usize) -> io::Result<Vec<[u8; 8]>> {
+        self.table(self.info_base()? + reference)
+    }
+
+    /// The raw references of the table the reference at `reference` (in the INFO block) points to.
+    fn table(&self, reference: usize) -> io::Result<Vec<[u8; 8]>> {
+        let table = self.info_base()? + self.u32_at(reference + 4)? as usize;
+        let count = self.u32_at(table)? as usize;
+        let entries = count.checked_mul(8).ok_or_else(|| invalid("table too large"))?;
+        Ok(self.bytes(table + 4, entries)?
+            .chunks(8)
+            .map(|chunk| {
+                let mut entry = [0; 8];
+                entry.copy_from_slice(chunk);
+                entry
+            })
+            .collect())
+    }
+
+    /// Where the entry a raw reference points to is.
+    fn entry(&self, reference: &[u8; 8]) -> io::Result<usize> {
+        let offset = u32::from_be_bytes([reference[4], reference[5], reference[6], reference[7]]) as usize;
+        let entry = self.info_base()? + offset;
+        if entry >= self.data.len() {
+            return Err(invalid("reference out of bounds"));
+        }
+        Ok(entry)
+    }
+
+    /// Adds a bank file and its wave archive as a new group, and registers it in the bank table.
+    ///
+    /// The bank has no name in the SYMB block, it can only be referred to by its index.
+    pub fn add_bank(&mut self, rbnk: &[u8], rwar: Option<&[u8]>) -> io::Result<AddedBank> {
+        let banks = self.info_table(BANK_TABLE)?;
+        let files = self.info_table(FILE_TABLE)?;
+        let groups = self.info_table(GROUP_TABLE)?;
+        let rwar = rwar.unwrap_or(&[]);
+
+        let (info_offset, info_size) = self.block(INFO_BLOCK)?;
+        let info_base = self.info_base()?;
+        if info_size < 8 + GROUP_TABLE + 8 {
+            return Err(invalid("INFO block too small"));
+        }
+        let tail_start = info_size - 8; // relative to the info base
+
+        // check the existing groups before anything is changed
+        let group_offsets = groups.iter()
+            .map(|group| {
+                let group = self.entry(group)?;
+                self.bytes(group, 0x1C)?;
+                Ok(group)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        // the new tables don't depend on where the files end up, so their size is known up front
+        let tail_len = Self::info_tail(&banks, &files, &groups, tail_start, [0; 4]).len();
+        let growth = align_up(tail_len, 0x20);
+
+        let file_start = self.data.len() + growth;
+        let rbnk_position = align_up(file_start, 0x20);
+        let rwar_position = align_up(rbnk_position + rbnk.len(), 0x20);
+        let end = if rwar.is_empty() { rbnk_position + align_up(rbnk.len(), 0x20) } else { align_up(rwar_position + rwar.len(), 0x20) };
+        let archive_base = if rwar.is_empty() { 0 } else { rwar_position };
+        if end > u32::MAX as usize {
+            return Err(invalid("BRSAR too large"));
+        }
+
+        let mut tail = Self::info_tail(&banks, &files, &groups, tail_start,
+            [rbnk_position as u32, rbnk.len() as u32, archive_base as u32, rwar.len() as u32]);
+        tail.resize(growth, 0);
+
+        // move the existing groups along with the FILE block
+        for group in group_offsets {
+            let file_base = self.u32_at(group + 0x10)?;
+            self.set_u32(group + 0x10, file_base.wrapping_add(growth as u32))?;
+            let archive_base = self.u32_at(group + 0x18)?;
+            if archive_base != 0 {
+                self.set_u32(group + 0x18, archive_base.wrapping_add(growth as u32))?;
+            }
+        }
+
+        // repoint the tables, in the order info_tail writes them
+        let bank_table = tail_start;
+        let file_table = bank_table + 4 + (banks.len() + 1) * 8 + BANK_INFO_LEN;
+        let group_table = file_table + 4 + (files.len() + 1) * 8 + FILE_INFO_LEN + 12 + FILE_POSITION_LEN;
+        self.set_bytes(info_base + BANK_TABLE, &reference(bank_table))?;
+        self.set_bytes(info_base + FILE_TABLE, &reference(file_table))?;
+        self.set_bytes(info_base + GROUP_TABLE, &reference(group_table))?;
+
+        let info_end = info_offset + info_size;
+        self.data.splice(info_end..info_end, tail);
+        self.set_u32(info_offset + 4, (info_size + growth) as u32)?;
+        self.set_u32(0x14 + INFO_BLOCK * 8, (info_size + growth) as u32)?;
+        let file_offset = self.u32_at(0x10 + FILE_BLOCK * 8)? as usize;
+        let file_size = self.u32_at(0x14 + FILE_BLOCK * 8)? as usize;
+        self.set_u32(0x10 + FILE_BLOCK * 8, (file_offset + growth) as u32)?;
+
+        self.data.resize(rbnk_position, 0);
+        self.data.extend_from_slice(rbnk);
+        if !rwar.is_empty() {
+            self.data.resize(rwar_position, 0);
+            self.data.extend_from_slice(rwar);
+        }
+        self.data.resize(end, 0);
+
+        let file_size = file_size + (end - file_start);
+        self.set_u32(file_offset + growth + 4, file_size as u32)?;
+        self.set_u32(0x14 + FILE_BLOCK * 8, file_size as u32)?;
+        self.set_u32(0x08, end as u32)?;
+
+        Ok(AddedBank {
+            bank_index: BankId(banks.len() as u32),
+            file_index: FileId(files.len() as u32),
+            group_index: GroupId(groups.len() as u32),
+        })
+    }
+
+    /// Replaces the wave archive of one of a group's entries, e.g. with one built from
+    /// `GroupEntry::archive_builder`.
+    ///
+    /// The archive is appended to the FILE block and the entry is repointed to it. The old archive
+    /// is left where it is, since other entries can share it.
+    pub fn replace_archive(&mut self, group: GroupId, entry: usize, rwar: &[u8]) -> io::Result<()> {
+        let groups = self.info_table(GROUP_TABLE)?;
+        let group = self.entry(groups.get(group.index()).ok_or_else(|| invalid("no group with that index"))?)?;
+        let entries = self.table(group + 0x20)?;
+        let entry = self.entry(entries.get(entry).ok_or_else(|| invalid("no group entry with that index"))?)?;
+        let file_id = self.u32_at(entry)? & 0xFF_FFFF;
+        let file = match self.info_table(FILE_TABLE)?.get(file_id as usize) {
+            Some(file) => Some(self.entry(file)?),
+            None => None
+        };
+        // check everything that's written below, before anything is
+        self.bytes(group, 0x20)?;
+        self.bytes(entry, 0x14)?;
+        if let Some(file) = file {
+            self.bytes(file, 8)?;
+        }
+
+        let position = align_up(self.data.len(), 0x20);
+        self.data.resize(position, 0);
+        self.data.extend_from_slice(rwar);
+        let end = align_up(self.data.len(), 0x20);
+        self.data.resize(end, 0);
+
+        // groups without wave data have no archive base yet
+        let archive_base = match self.u32_at(group + 0x18)? as usize {
+            0 => position,
+            base => base
+        };
+        self.set_u32(group + 0x18, archive_base as u32)?;
+        self.set_u32(group + 0x1C, (position + rwar.len() - archive_base) as u32)?;
+        self.set_u32(entry + 0x0C, (position - archive_base) as u32)?;
+        self.set_u32(entry + 0x10, rwar.len() as u32)?;
+
+        // the file info has the size of the archive as well
+        if let Some(file) = file {
+            self.set_u32(file + 4, rwar.len() as u32)?;
+        }
+
+        let file_offset = self.u32_at(0x10 + FILE_BLOCK * 8)? as usize;
+        let file_size = (end - file_offset) as u32;
+        self.set_u32(file_offset + 4, file_size)?;
+        self.set_u32(0x14 + FILE_BLOCK * 8, file_size)?;
+        self.set_u32(0x08, end as u32)
+    }
+
+    /// Updates the channel count and allocated tracks of a stream sound, after its BRSTM was replaced.
+    pub fn set_stream_layout(&mut self, sound: SoundId, channel_count: u16, track_count: u8) -> io::Result<()> {
+        let sounds = self.info_table(SOUND_TABLE)?;
+        let sound = self.entry(sounds.get(sound.index()).ok_or_else(|| invalid("no sound with that index"))?)?;
+        if self.bytes(sound + 0x16, 1)?[0] != 2 {
+            return Err(invalid("not a stream sound"));
+        }
+        let mut details = [0; 8];
+        details.copy_from_slice(self.bytes(sound + 0x18, 8)?);
+        let details = self.entry(&details)?;
+        self.bytes(details, 0x0C)?;
+
+        // a u16 channel count followed by a u16 bitmask of the allocated tracks
+        let mask = ((1u32 << track_count.min(16)) - 1) as u16;
+        self.set_bytes(details + 4, &channel_count.to_be_bytes())?;
+        self.set_bytes(details + 6, &mask.to_be_bytes())
+    }
+
+    /// New bank, file and group tables (with one entry added to each) and the new entries.
+    ///
+    /// `layout` is the absolute position and size of the bank file, then of its wave archive.
+    fn info_tail(banks: &[[u8; 8]], files: &[[u8; 8]], groups: &[[u8; 8]], start: usize, layout: [u32; 4]) -> Vec<u8> {
+        let [file_position, file_size, archive_position, archive_size] = layout;
+        let mut tail = Vec::new();
+        let position = |tail: &Vec<u8>| start + tail.len();
+
+        let table = |tail: &mut Vec<u8>, entries: &[[u8; 8]]| {
+            let new_entry = start + tail.len() + 4 + (entries.len() + 1) * 8;
+            tail.extend_from_slice(&(entries.len() as u32 + 1).to_be_bytes());
+            for entry in entries {
+                tail.extend_from_slice(entry);
+            }
+            tail.extend_from_slice(&reference(new_entry));
+        };
+
+        // bank table and BankInfo
+        table(&mut tail, banks);
+        tail.extend_from_slice(&u32::MAX.to_be_bytes()); // no name
+        tail.extend_from_slice(&(files.len() as u32).to_be_bytes());
+        tail.extend_from_slice(&[0; 4]);
+
+        // file table and FileInfo, followed by its single file position
+        table(&mut tail, files);
+        let positions = position(&tail) + FILE_INFO_LEN;
+        tail.extend_from_slice(&file_size.to_be_bytes());
+        tail.extend_from_slice(&archive_size.to_be_bytes());
+        tail.extend_from_slice(&u32::MAX.to_be_bytes());
+        tail.extend_from_slice(&[0; 8]); // not external
+        tail.extend_from_slice(&reference(positions));
+        tail.extend_from_slice(&1u32.to_be_bytes());
+        tail.extend_from_slice(&reference(positions + 12));
+        tail.extend_from_slice(&(groups.len() as u32).to_be_bytes());
+        tail.extend_from_slice(&0u32.to_be_bytes());
+
+        // group table and GroupInfo, followed by its single entry
+        table(&mut tail, groups);
+        let entries = position(&tail) + GROUP_INFO_LEN;
+        tail.extend_from_slice(&u32::MAX.to_be_bytes()); // no name
+        tail.extend_from_slice(&u32::MAX.to_be_bytes());
+        tail.extend_from_slice(&[0; 8]); // not external
+        tail.extend_from_slice(&file_position.to_be_bytes());
+        tail.extend_from_slice(&(align_up(file_size as usize, 0x20) as u32).to_be_bytes());
+        tail.extend_from_slice(&archive_position.to_be_bytes());
+        tail.extend_from_slice(&archive_size.to_be_bytes());
+        tail.extend_from_slice(&reference(entries));
+        tail.extend_from_slice(&1u32.to_be_bytes());
+        tail.extend_from_slice(&reference(entries + 12));
+
+        // GroupEntry, relative to the group's file and archive bases
+        tail.extend_from_slice(&(files.len() as u32).to_be_bytes());
+        tail.extend_from_slice(&0u32.to_be_bytes());
+        tail.extend_from_slice(&file_size.to_be_bytes());
+        tail.extend_from_slice(&0u32.to_be_bytes());
+        tail.extend_from_slice(&archive_size.to_be_bytes());
+        tail.extend_from_slice(&[0; 4]);
+        tail
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::BRSAR;
+    use super::super::block::info::SoundDetails;
+    use super::super::test_data::{test_brsar, test_brsar_with_bank, test_brsar_with_file, test_brsar_with_stream};
+    use crate::rwar::RwarBuilder;
+    use binread::BinReaderExt;
+    use std::io::Cursor;
+
+    #[test]
+    fn add_bank() {
+        let mut editor = BrsarEditor::new(test_brsar()).unwrap();
+        let added = editor.add_bank(&[1; 0x24], Some(&[2; 0x10])).unwrap();
+        assert_eq!(added, AddedBank { bank_index: BankId(0), file_index: FileId(0), group_index: GroupId(1) });
+
+        let banks = editor.info_table(BANK_TABLE).unwrap();
+        let files = editor.info_table(FILE_TABLE).unwrap();
+        let groups = editor.info_table(GROUP_TABLE).unwrap();
+        assert_eq!((banks.len(), files.len(), groups.len()), (1, 1, 2));
+
+        let (info_offset, info_size) = editor.block(INFO_BLOCK).unwrap();
+        let (file_offset, file_size) = editor.block(FILE_BLOCK).unwrap();
+        assert_eq!(info_offset + info_size, file_offset);
+        assert_eq!(file_offset + file_size, editor.data.len());
+        assert_eq!(editor.u32_at(info_offset + 4).unwrap() as usize, info_size);
+        assert_eq!(editor.u32_at(file_offset + 4).unwrap() as usize, file_size);
+        assert_eq!(editor.u32_at(0x08).unwrap() as usize, editor.data.len());
+        assert_eq!(&editor.data[file_offset..file_offset + 4], b"FILE");
+
+        // the old group still points at the same data
+        let old_group = editor.entry(&groups[0]).unwrap();
+        assert_eq!(editor.u32_at(old_group + 0x10).unwrap() as usize, file_offset + 0x20);
+
+        let bank = editor.entry(&banks[0]).unwrap();
+        assert_eq!(editor.u32_at(bank + 4).unwrap(), 0);
+        let group = editor.entry(&groups[1]).unwrap();
+        let rbnk = editor.u32_at(group + 0x10).unwrap() as usize;
+        let rwar = editor.u32_at(group + 0x18).unwrap() as usize;
+        assert_eq!(&editor.data[rbnk..rbnk + 0x24], &[1; 0x24][..]);
+        assert_eq!(&editor.data[rwar..rwar + 0x10], &[2; 0x10][..]);
+        assert_eq!(editor.u32_at(group + 0x1C).unwrap(), 0x10);
+
+        let file = editor.entry(&files[0]).unwrap();
+        assert_eq!(editor.u32_at(file).unwrap(), 0x24);
+        let base = editor.info_base().unwrap();
+        let positions = base + editor.u32_at(file + 0x18).unwrap() as usize;
+        let position = base + editor.u32_at(positions + 8).unwrap() as usize;
+        assert_eq!(editor.u32_at(position).unwrap(), 1);
+
+        // the bank's archive can be replaced like any other
+        let data = editor.into_bytes();
+        let mut editor = BrsarEditor::new(data.clone()).unwrap();
+        editor.replace_archive(GroupId(1), 0, &[3; 0x20]).unwrap();
+        let brsar: BRSAR = Cursor::new(&editor.into_bytes()).read_be().unwrap();
+        assert_eq!(brsar.info.block.group_table.0[1].entries.0[0].archive_size, 0x20);
+        let brsar: BRSAR = Cursor::new(&test_brsar_with_bank()).read_be().unwrap();
+        assert_eq!(brsar.info.block.bank_table.0.len(), 1);
+        assert_eq!(brsar.header.file_size as usize, data.len());
+    }
+
+    #[test]
+    fn replace_archive() {
+        let mut editor = BrsarEditor::new(test_brsar_with_file()).unwrap();
+        let mut builder = RwarBuilder::new();
+        builder.push(vec![3; 0x30]);
+        editor.replace_archive(GroupId(0), 0, &builder.to_bytes()).unwrap();
+        assert!(editor.replace_archive(GroupId(1), 0, &[]).is_err());
+        assert!(editor.replace_archive(GroupId(0), 1, &[]).is_err());
+        let data = editor.into_bytes();
+
+        // add a wave to the archive that's there now
+        let brsar: BRSAR = Cursor::new(&data).read_be().unwrap();
+        let entry = &brsar.info.block.group_table.0[0].entries.0[0];
+        let mut builder = entry.archive_builder(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(builder.wave_count(), 1);
+        builder.push(vec![4; 0x10]);
+        let mut editor = BrsarEditor::new(data).unwrap();
+        editor.replace_archive(GroupId(0), 0, &builder.to_bytes()).unwrap();
+        let data = editor.into_bytes();
+
+        let brsar: BRSAR = Cursor::new(&data).read_be().unwrap();
+        assert_eq!(brsar.header.file_size as usize, data.len());
+        let entry = &brsar.info.block.group_table.0[0].entries.0[0];
+        let rwar = entry.read_archive(&mut Cursor::new(&data)).unwrap().unwrap();
+        assert_eq!(rwar.wave_count(), 2);
+        assert_eq!(rwar.wave(0), Some(&[3; 0x30][..]));
+        assert_eq!(rwar.wave(1), Some(&[4; 0x10][..]));
+        assert_eq!(brsar.info.block.file_table.0[0].archive_size, entry.archive_size);
+        assert_eq!(entry.file_size, 0x20);
+    }
+
+    #[test]
+    fn malformed_info() {
+        // the group table reference points past the end of the file
+        let mut data = test_brsar_with_file();
+        data[0x68 + GROUP_TABLE + 4..0x68 + GROUP_TABLE + 8].copy_from_slice(&0xFFFF_FF00u32.to_be_bytes());
+        let mut editor = BrsarEditor::new(data).unwrap();
+        assert!(editor.replace_archive(GroupId(0), 0, &[]).is_err());
+
+        // a group entry table that's larger than the file
+        let mut data = test_brsar();
+        data[0x68 + 0x6C..0x68 + 0x70].copy_from_slice(&0x1000_0000u32.to_be_bytes());
+        let mut editor = BrsarEditor::new(data).unwrap();
+        assert!(editor.replace_archive(GroupId(0), 0, &[]).is_err());
+
+        // a group that's past the end of the file, nothing is changed
+        let mut data = test_brsar();
+        data[0x68 + 0x40..0x68 + 0x44].copy_from_slice(&0xFFFF_FF00u32.to_be_bytes());
+        let mut editor = BrsarEditor::new(data.clone()).unwrap();
+        assert!(editor.add_bank(&[1; 0x24], None).is_err());
+        assert_eq!(editor.into_bytes(), data);
+
+        // blocks outside of the file
+        let mut data = test_brsar();
+        data[0x1C..0x20].copy_from_slice(&0xFFFF_FFFFu32.to_be_bytes());
+        assert!(BrsarEditor::new(data).is_err());
+    }
+
+    #[test]
+    fn set_stream_layout() {
+        let mut editor = BrsarEditor::new(test_brsar_with_stream()).unwrap();
+        editor.set_stream_layout(SoundId(0), 6, 3).unwrap();
+        assert!(editor.set_stream_layout(SoundId(1), 2, 1).is_err());
+        let data = editor.into_bytes();
+
+        let brsar: BRSAR = Cursor::new(&data).read_be().unwrap();
+        match &*brsar.info.block.sound_table.0[0].details {
+            SoundDetails::Stream(details) => {
+                assert_eq!(details.channel_count, 6);
+                assert_eq!(details.alloc_track, 0b111);
+            }
+            _ => panic!("not a stream sound")
+        }
+
+        // no sounds at all
+        let mut editor = BrsarEditor::new(test_brsar()).unwrap();
+        assert!(editor.set_stream_layout(SoundId(0), 2, 1).is_err());
+
+        // stream details past the end of the file
+        let mut data = test_brsar_with_stream();
+        data[0x68 + 0xA4 + 0x1C..0x68 + 0xA4 + 0x20].copy_from_slice(&0xFFFF_FFF0u32.to_be_bytes());
+        let mut editor = BrsarEditor::new(data).unwrap();
+        assert!(editor.set_stream_layout(SoundId(0), 2, 1).is_err());
+    }
+}
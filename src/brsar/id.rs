@@ -0,0 +1,89 @@
+//! Indices into the tables of the info and symbol blocks. Each table has its own index type, so
+//! an index can only be used with the table it belongs to.
+
+use super::block::info::{SoundInfo, BankInfo, PlayerInfo, FileInfo, GroupInfo};
+use crate::common::*;
+use binread::BinRead;
+use std::fmt;
+use std::ops::Index;
+
+macro_rules! id {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(BinRead, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+        pub struct $name(pub u32);
+
+        impl $name {
+            pub fn index(self) -> usize {
+                self.0 as usize
+            }
+        }
+
+        // some ids are stored with a type in the top byte
+        impl From<TypedId> for $name {
+            fn from(id: TypedId) -> $name {
+                $name(id.index())
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+id!(
+    /// Index into `InfoBlock::sound_table`.
+    SoundId
+);
+id!(
+    /// Index into `InfoBlock::bank_table`.
+    BankId
+);
+id!(
+    /// Index into `InfoBlock::player_table`.
+    PlayerId
+);
+id!(
+    /// Index into `InfoBlock::file_table`.
+    FileId
+);
+id!(
+    /// Index into `InfoBlock::group_table`.
+    GroupId
+);
+id!(
+    /// Index into `SymbolBlock::string_table`.
+    StringId
+);
+
+macro_rules! table_index {
+    ($id:ident, $entry:ty => $output:ty) => {
+        impl Table<$entry> {
+            pub fn get(&self, id: $id) -> Option<&$output> {
+                self.0.get(id.index()).map(|entry| &**entry)
+            }
+
+            pub fn ids(&self) -> impl Iterator<Item = $id> {
+                (0..self.0.len() as u32).map($id)
+            }
+        }
+
+        impl Index<$id> for Table<$entry> {
+            type Output = $output;
+
+            fn index(&self, id: $id) -> &$output {
+                &self.0[id.index()]
+            }
+        }
+    };
+}
+
+table_index!(SoundId, Reference<SoundInfo> => SoundInfo);
+table_index!(BankId, Reference<BankInfo> => BankInfo);
+table_index!(PlayerId, Reference<PlayerInfo> => PlayerInfo);
+table_index!(FileId, Reference<FileInfo> => FileInfo);
+table_index!(GroupId, Reference<GroupInfo> => GroupInfo);
+table_index!(StringId, r32<NullString> => NullString);
@@ -1,17 +1,126 @@
 pub mod block;
+pub mod edit;
+pub mod id;
+pub mod slice;
+pub mod source;
+pub mod view;
+pub mod visit;
+pub mod xref;
+#[cfg(test)]
+mod test_data;
 
 use crate::common::*;
 use block::{SymbolBlock, InfoBlock, FileBlock};
-use binread::BinRead;
+use block::info::{SoundInfo, BankInfo, FileInfo, GroupInfo, GroupEntry};
+use id::{SoundId, BankId, FileId, GroupId, StringId};
+use binread::{BinRead, BinResult};
 
+/// A sound archive. By default everything is read while parsing; in `Lazy` mode only the header
+/// and the block table are, and tables and entries are read the first time they're looked up.
 #[derive(BinRead)]
-pub struct BRSAR {
+pub struct BRSAR<M: Mode = Eager> {
     #[br(assert(header.block_count == 3), assert(header.version == 0x0104), assert(&header.magic == b"RSAR"))]
     pub header: FileHeader,
-    #[br(deref_now, is_big = header.endian == Endian::Big)]
-    pub symbol: BlockPtr<SymbolBlock>,
     #[br(is_big = header.endian == Endian::Big)]
-    pub info: BlockPtr<InfoBlock>,
+    pub symbol: BlockPtr<SymbolBlock<M>, M>,
+    #[br(is_big = header.endian == Endian::Big)]
+    pub info: BlockPtr<InfoBlock<M>, M>,
     #[br(is_big = header.endian == Endian::Big, align_after = 0x20)]
-    pub file: BlockPtr<FileBlock>
+    pub file: BlockPtr<FileBlock, M>
+}
+
+type LazyTable<T> = Reference<Table<Reference<T, Lazy>>, Lazy>;
+
+fn lazy_entry<'b, T: BinRead<Args = ()>>(data: &[u8], table: &'b LazyTable<T>, index: usize) -> BinResult<Option<&'b T>> {
+    table.get(data)?.0.get(index).map(|entry| entry.get(data)).transpose()
+}
+
+/// Lookups in a lazily parsed archive, `data` has to be the whole file it was parsed from.
+impl BRSAR<Lazy> {
+    pub fn symbol_block(&self, data: &[u8]) -> BinResult<&SymbolBlock<Lazy>> {
+        self.symbol.block.get(data)
+    }
+
+    pub fn info_block(&self, data: &[u8]) -> BinResult<&InfoBlock<Lazy>> {
+        self.info.block.get(data)
+    }
+
+    pub fn sound(&self, data: &[u8], id: SoundId) -> BinResult<Option<&SoundInfo>> {
+        lazy_entry(data, &self.info_block(data)?.sound_table, id.index())
+    }
+
+    pub fn bank(&self, data: &[u8], id: BankId) -> BinResult<Option<&BankInfo>> {
+        lazy_entry(data, &self.info_block(data)?.bank_table, id.index())
+    }
+
+    pub fn file(&self, data: &[u8], id: FileId) -> BinResult<Option<&FileInfo>> {
+        lazy_entry(data, &self.info_block(data)?.file_table, id.index())
+    }
+
+    pub fn group(&self, data: &[u8], id: GroupId) -> BinResult<Option<&GroupInfo>> {
+        lazy_entry(data, &self.info_block(data)?.group_table, id.index())
+    }
+
+    pub fn name(&self, data: &[u8], string_id: StringId) -> BinResult<Option<String>> {
+        let table = self.symbol_block(data)?.string_table.get(data)?;
+        table.0.get(string_id.index()).map(|name| name.get(data).map(|name| name.to_string())).transpose()
+    }
+
+    /// Id of a sound, by name.
+    pub fn find_sound(&self, data: &[u8], name: &str) -> BinResult<Option<SoundId>> {
+        let found = match self.symbol_block(data)?.sound_tree.get(data)?.search(name.as_bytes()) {
+            Some(found) => found,
+            None => return Ok(None)
+        };
+        // the search always ends up at a leaf, check that it's the one we were looking for
+        Ok(if self.name(data, found.string_index)?.as_deref() == Some(name) { Some(found.item_index) } else { None })
+    }
+
+    /// The (first) group entry holding a file.
+    pub fn file_entry(&self, data: &[u8], file_id: FileId) -> BinResult<Option<&GroupEntry>> {
+        let pos = match self.file(data, file_id)?.and_then(|file| file.file_positions.0.first()) {
+            Some(pos) => pos,
+            None => return Ok(None)
+        };
+        Ok(self.group(data, pos.group_index)?.and_then(|group| group.entries.0.get(pos.item_index as usize)).map(|entry| &**entry))
+    }
+
+    /// The data of a file in the archive, as a slice of the buffer.
+    pub fn file_data<'a>(&self, data: &'a [u8], file_id: FileId) -> BinResult<Option<&'a [u8]>> {
+        Ok(self.file_entry(data, file_id)?.and_then(|entry| data.get(entry.file_range())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_data::{test_brsar, test_brsar_with_bank};
+    use binread::BinReaderExt;
+    use binread::io::Cursor;
+
+    #[test]
+    fn parse_on_demand() {
+        let data = test_brsar();
+        let brsar: BRSAR<Lazy> = Cursor::new(&data).read_be().unwrap();
+        assert!(!brsar.info.block.is_resolved());
+
+        let group = brsar.group(&data, GroupId(0)).unwrap().unwrap();
+        assert_eq!(group.file_base, 0x120);
+        assert!(brsar.group(&data, GroupId(1)).unwrap().is_none());
+        assert!(brsar.file(&data, FileId(0)).unwrap().is_none());
+        assert!(brsar.file_data(&data, FileId(0)).unwrap().is_none());
+
+        let info = brsar.info_block(&data).unwrap();
+        assert!(info.group_table.is_resolved());
+        assert!(!info.sound_table.is_resolved());
+        assert!(!brsar.symbol.block.is_resolved());
+
+        let data = test_brsar_with_bank();
+        let brsar: BRSAR<Lazy> = Cursor::new(&data).read_be().unwrap();
+        assert_eq!(brsar.file_data(&data, FileId(0)).unwrap(), Some(&[1; 0x24][..]));
+        assert!(brsar.bank(&data, BankId(0)).unwrap().is_some());
+        // the eager parse reads the same archive in one go
+        let eager: BRSAR = Cursor::new(&data).read_be().unwrap();
+        assert_eq!(eager.info.block.file_table.0.len(), 1);
+    }
 }
@@ -0,0 +1,234 @@
+//! Access to an archive held in memory (or memory mapped), handing out slices of it instead of
+//! copying files out.
+//!
+//! Nothing is parsed up front apart from the block table: the SYMB and INFO blocks are read
+//! where they lie, one lookup at a time.
+
+use super::id::{FileId, SoundId, StringId};
+use crate::common::*;
+use std::io;
+use std::ops::Range;
+
+// offsets of the references at the start of the INFO block body
+const SOUND_TABLE: usize = 0x00;
+const FILE_TABLE: usize = 0x18;
+const GROUP_TABLE: usize = 0x20;
+
+/// An archive borrowed from the bytes it is stored in.
+pub struct BrsarSlice<'a> {
+    data: &'a [u8],
+    raw: RawFile<'a>,
+}
+
+/// The fields of a sound needed to find its name and data.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SoundSlice {
+    pub string_id: StringId,
+    pub file_id: FileId,
+    /// 1 for sequences, 2 for streams and 3 for waves, like `SoundType`.
+    pub sound_type: u8,
+}
+
+impl<'a> BrsarSlice<'a> {
+    pub fn new(data: &'a [u8]) -> io::Result<BrsarSlice<'a>> {
+        let raw = RawFile::parse(data)?;
+        if &raw.magic != b"RSAR" || raw.endian != Endian::Big {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a big endian BRSAR"));
+        }
+        Ok(BrsarSlice { data, raw })
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The blocks of the archive, without their headers.
+    pub fn blocks(&self) -> &[RawBlock<'a>] {
+        &self.raw.blocks
+    }
+
+    /// A name from the symbol block's string table, without the terminator.
+    pub fn name(&self, string_id: StringId) -> Option<&'a str> {
+        symbol_name(self.raw.block(b"SYMB")?.body, string_id)
+    }
+
+    pub fn sound_count(&self) -> usize {
+        self.info().and_then(|info| info.table_len(SOUND_TABLE)).unwrap_or(0)
+    }
+
+    pub fn sound(&self, id: SoundId) -> Option<SoundSlice> {
+        let info = self.info()?;
+        let sound = info.table_entry(SOUND_TABLE, id.index())?;
+        Some(SoundSlice {
+            string_id: StringId(info.u32_at(sound)?),
+            file_id: FileId(info.u32_at(sound + 4)?),
+            sound_type: *info.body.get(sound + 0x16)?,
+        })
+    }
+
+    /// The name of a file stored outside the archive, `None` for files inside it.
+    pub fn external_name(&self, file_id: FileId) -> Option<&'a str> {
+        let info = self.info()?;
+        let file = info.table_entry(FILE_TABLE, file_id.index())?;
+        let name = info.deref(file + 0x0C)?;
+        let name = info.body.get(name..)?;
+        let len = name.iter().position(|&byte| byte == 0)?;
+        std::str::from_utf8(&name[..len]).ok()
+    }
+
+    /// Where a file in the file table is, from the first group that holds it.
+    /// External files aren't in the archive, so they have none.
+    pub fn file_range(&self, file_id: FileId) -> Option<Range<usize>> {
+        let info = self.info()?;
+        let (group, entry) = info.file_entry(file_id)?;
+        let start = info.u32_at(group + 0x10)? as usize + info.u32_at(entry + 4)? as usize;
+        Some(start..start + info.u32_at(entry + 8)? as usize)
+    }
+
+    /// Where the wave archive of a file in the file table is, if it has one.
+    pub fn archive_range(&self, file_id: FileId) -> Option<Range<usize>> {
+        let info = self.info()?;
+        let (group, entry) = info.file_entry(file_id)?;
+        let size = info.u32_at(entry + 0x10)? as usize;
+        if size == 0 {
+            return None;
+        }
+        let start = info.u32_at(group + 0x18)? as usize + info.u32_at(entry + 0x0C)? as usize;
+        Some(start..start + size)
+    }
+
+    /// The data of a file in the file table.
+    pub fn file_data(&self, file_id: FileId) -> Option<&'a [u8]> {
+        self.data.get(self.file_range(file_id)?)
+    }
+
+    /// The wave archive of a file in the file table.
+    pub fn file_archive(&self, file_id: FileId) -> Option<&'a [u8]> {
+        self.data.get(self.archive_range(file_id)?)
+    }
+
+    fn info(&self) -> Option<InfoSlice<'a>> {
+        self.raw.block(b"INFO").map(|block| InfoSlice { body: block.body })
+    }
+}
+
+// offsets in the INFO block are relative to its body
+#[derive(Clone, Copy)]
+struct InfoSlice<'a> {
+    body: &'a [u8],
+}
+
+impl InfoSlice<'_> {
+    fn u32_at(&self, pos: usize) -> Option<u32> {
+        let bytes = self.body.get(pos..pos.checked_add(4)?)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Where the reference at `pos` points to, `None` for null references.
+    fn deref(&self, pos: usize) -> Option<usize> {
+        match self.u32_at(pos + 4)? as usize {
+            0 => None,
+            offset if offset < self.body.len() => Some(offset),
+            _ => None
+        }
+    }
+
+    fn table_len(&self, reference: usize) -> Option<usize> {
+        self.u32_at(self.deref(reference)?).map(|len| len as usize)
+    }
+
+    /// Where entry `index` of the table the reference at `reference` points to is.
+    fn table_entry(&self, reference: usize, index: usize) -> Option<usize> {
+        let table = self.deref(reference)?;
+        if index >= self.u32_at(table)? as usize {
+            return None;
+        }
+        self.deref(table + 4 + index * 8)
+    }
+
+    /// The group and group entry holding a file, through its first file position.
+    fn file_entry(&self, file_id: FileId) -> Option<(usize, usize)> {
+        let file = self.table_entry(FILE_TABLE, file_id.index())?;
+        let position = self.table_entry(file + 0x14, 0)?;
+        let group = self.table_entry(GROUP_TABLE, self.u32_at(position)? as usize)?;
+        let entry = self.table_entry(group + 0x20, self.u32_at(position + 4)? as usize)?;
+        Some((group, entry))
+    }
+}
+
+// offsets in the symbol block are relative to its body
+fn symbol_name(symb: &[u8], string_id: StringId) -> Option<&str> {
+    let u32_at = |pos: usize| symb.get(pos..pos + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize);
+
+    let table = u32_at(0)?;
+    if string_id.index() >= u32_at(table)? {
+        return None;
+    }
+    let start = u32_at(table + 4 + string_id.index() * 4)?;
+    let name = symb.get(start..)?;
+    let len = name.iter().position(|&byte| byte == 0)?;
+    std::str::from_utf8(&name[..len]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_data::{test_brsar_with_bank, test_brsar_with_stream};
+    use std::io::Write;
+
+    #[test]
+    fn names() {
+        // string table at 0x18, two names after it
+        let mut symb = vec![0; 0x18];
+        symb[0..4].copy_from_slice(&0x18u32.to_be_bytes());
+        for value in &[2u32, 0x24, 0x2C] {
+            symb.extend_from_slice(&value.to_be_bytes());
+        }
+        symb.extend_from_slice(b"SE_JUMP\0BGM\0");
+
+        assert_eq!(symbol_name(&symb, StringId(0)), Some("SE_JUMP"));
+        assert_eq!(symbol_name(&symb, StringId(1)), Some("BGM"));
+        assert_eq!(symbol_name(&symb, StringId(2)), None);
+        assert_eq!(symbol_name(&symb[..0x2E], StringId(1)), None);
+    }
+
+    #[test]
+    fn files() {
+        let data = test_brsar_with_bank();
+        let brsar = BrsarSlice::new(&data).unwrap();
+        let file = brsar.file_data(FileId(0)).unwrap();
+        assert_eq!(file, &[1; 0x24][..]);
+        assert_eq!(file.as_ptr(), data[brsar.file_range(FileId(0)).unwrap()].as_ptr());
+        assert_eq!(brsar.file_archive(FileId(0)), Some(&[2; 0x10][..]));
+        assert_eq!(brsar.external_name(FileId(0)), None);
+        assert_eq!(brsar.file_data(FileId(1)), None);
+
+        let data = test_brsar_with_stream();
+        let brsar = BrsarSlice::new(&data).unwrap();
+        assert_eq!(brsar.sound_count(), 1);
+        assert_eq!(brsar.sound(SoundId(0)), Some(SoundSlice { string_id: StringId(0), file_id: FileId(0), sound_type: 2 }));
+        assert_eq!(brsar.sound(SoundId(1)), None);
+        assert_eq!(brsar.file_data(FileId(0)), None);
+    }
+
+    #[test]
+    fn raw_blocks() {
+        let mut file = ContainerWriter::new(*b"RTST", 0x0100);
+        let first = file.add_block(*b"ONE ", 3);
+        let second = file.add_block(*b"TWO ", 0x20);
+        let mut data = Vec::new();
+        file.write_header(&mut data).unwrap();
+        file.write_block(&mut data, first, |body| body.write_all(&[1, 2, 3])).unwrap();
+        file.write_block(&mut data, second, |body| body.write_all(&[4; 0x20])).unwrap();
+
+        let raw = RawFile::parse(&data).unwrap();
+        assert_eq!((&raw.magic, raw.endian == Endian::Big, raw.version), (b"RTST", true, 0x0100));
+        let one = raw.block(b"ONE ").unwrap();
+        assert_eq!((one.offset, &one.body[..4]), (0x20, &[1, 2, 3, 0][..]));
+        assert_eq!(one.body.as_ptr(), data[0x28..].as_ptr());
+        let two = raw.block(b"TWO ").unwrap();
+        assert_eq!((two.offset, two.body.len()), (0x40, 0x38));
+
+        assert!(RawFile::parse(&data[..0x70]).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+//! Positioned reads from wherever an archive is stored, so that extracting files works the same
+//! for files on disk, readers and buffers on every platform.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub trait ArchiveSource {
+    /// Reads `len` bytes at `pos` from the start of the archive, failing if they aren't all there.
+    fn read_at(&mut self, pos: u64, len: usize) -> io::Result<Cow<'_, [u8]>>;
+}
+
+impl<S: ArchiveSource + ?Sized> ArchiveSource for &mut S {
+    fn read_at(&mut self, pos: u64, len: usize) -> io::Result<Cow<'_, [u8]>> {
+        (**self).read_at(pos, len)
+    }
+}
+
+/// Any seekable reader, every read is copied out of it.
+pub struct ReaderSource<R: Read + Seek>(pub R);
+
+impl ReaderSource<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<ReaderSource<BufReader<File>>> {
+        Ok(ReaderSource(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: Read + Seek> ReaderSource<R> {
+    /// The reader itself, for parsing the archive.
+    pub fn reader(&mut self) -> &mut R {
+        &mut self.0
+    }
+}
+
+impl<R: Read + Seek> ArchiveSource for ReaderSource<R> {
+    fn read_at(&mut self, pos: u64, len: usize) -> io::Result<Cow<'_, [u8]>> {
+        let mut bytes = vec![0; len];
+        self.0.seek(SeekFrom::Start(pos))?;
+        self.0.read_exact(&mut bytes)?;
+        Ok(Cow::Owned(bytes))
+    }
+}
+
+/// An archive that's already in memory (or memory mapped), reads borrow from it.
+pub struct SliceSource<'a>(pub &'a [u8]);
+
+impl ArchiveSource for SliceSource<'_> {
+    fn read_at(&mut self, pos: u64, len: usize) -> io::Result<Cow<'_, [u8]>> {
+        (pos as usize).checked_add(len)
+            .and_then(|end| self.0.get(pos as usize..end))
+            .map(Cow::Borrowed)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "read past the end of the archive"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn sources_agree() {
+        let data: Vec<u8> = (0..64).collect();
+        let mut reader = ReaderSource(Cursor::new(data.clone()));
+        let mut slice = SliceSource(&data);
+
+        for &(pos, len) in &[(0, 4), (10, 20), (60, 4), (64, 0)] {
+            let expected = &data[pos as usize..pos as usize + len];
+            assert_eq!(&*reader.read_at(pos, len).unwrap(), expected);
+            assert_eq!(&*slice.read_at(pos, len).unwrap(), expected);
+        }
+        assert!(matches!(slice.read_at(0, 1).unwrap(), Cow::Borrowed(_)));
+
+        assert!(reader.read_at(60, 5).is_err());
+        assert!(slice.read_at(60, 5).is_err());
+        assert!(slice.read_at(u64::MAX, 2).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+//! Small hand-built archives shared by the tests of the BRSAR modules.
+
+use super::edit::{BrsarEditor, reference};
+
+// header, empty SYMB block, INFO block with one empty group, FILE block with no files
+pub(crate) fn test_brsar() -> Vec<u8> {
+    build(&[], &[])
+}
+
+// test_brsar with one 0x20 byte file, the only entry of the group
+pub(crate) fn test_brsar_with_file() -> Vec<u8> {
+    let mut extra = Vec::new();
+    extra.extend_from_slice(&1u32.to_be_bytes()); // file table at 0x98
+    extra.extend_from_slice(&reference(0xA4));
+    extra.extend_from_slice(&0x20u32.to_be_bytes()); // file size
+    extra.extend_from_slice(&0u32.to_be_bytes()); // no archive
+    extra.extend_from_slice(&[0xFF; 4]);
+    extra.extend_from_slice(&[0; 8]); // not external
+    extra.extend_from_slice(&reference(0xC0));
+    extra.extend_from_slice(&1u32.to_be_bytes()); // file positions at 0xC0
+    extra.extend_from_slice(&reference(0xCC));
+    extra.extend_from_slice(&[0; 8]); // group 0, item 0
+    extra.extend_from_slice(&1u32.to_be_bytes()); // group entry table at 0xD4
+    extra.extend_from_slice(&reference(0xE0));
+    extra.extend_from_slice(&[0; 4]); // file 0
+    extra.extend_from_slice(&0u32.to_be_bytes()); // file offset
+    extra.extend_from_slice(&0x20u32.to_be_bytes());
+    extra.resize(0x60, 0);
+    build(&extra, &[(0x18, 0x98), (0x64, 0xD4)])
+}
+
+// test_brsar with one stream sound, of 2 channels in 1 track
+pub(crate) fn test_brsar_with_stream() -> Vec<u8> {
+    let mut sounds = Vec::new();
+    sounds.extend_from_slice(&1u32.to_be_bytes()); // sound table at 0x98
+    sounds.extend_from_slice(&reference(0xA4));
+    sounds.extend_from_slice(&[0; 0x0C]); // string, file and player id
+    sounds.extend_from_slice(&reference(0x70)); // 3D info, unused
+    sounds.extend_from_slice(&[0x7F, 0x40, 2, 0]); // volume, priority, stream type
+    sounds.extend_from_slice(&[1, 2, 0, 0]);
+    sounds.extend_from_slice(&0xD0u32.to_be_bytes()); // stream details
+    sounds.extend_from_slice(&[0; 0x0C]);
+    sounds.extend_from_slice(&[0; 4]); // start position
+    sounds.extend_from_slice(&[0, 2, 0, 1]); // channel count, track mask
+    sounds.extend_from_slice(&[0; 4]);
+    sounds.resize(0x60, 0);
+    build(&sounds, &[(0x00, 0x98)])
+}
+
+// `extra` goes at the end of the INFO block, `references` repoints the references at the given
+// offsets of the INFO block to it
+fn build(extra: &[u8], references: &[(usize, usize)]) -> Vec<u8> {
+    let mut info = Vec::new();
+    let empty_table = 0x30;
+    for reference_offset in [empty_table, empty_table, empty_table, 0x34, 0x38, 0x70] {
+        info.extend_from_slice(&reference(reference_offset));
+    }
+    info.extend_from_slice(&0u32.to_be_bytes()); // empty table
+    info.extend_from_slice(&0u32.to_be_bytes()); // empty file table
+    info.extend_from_slice(&1u32.to_be_bytes()); // group table
+    info.extend_from_slice(&reference(0x44));
+    info.extend_from_slice(&[0xFF; 8]);
+    info.extend_from_slice(&[0; 8]);
+    let file_offset = 0x100 + extra.len() as u32;
+    info.extend_from_slice(&(file_offset + 0x20).to_be_bytes()); // file base
+    info.extend_from_slice(&0x20u32.to_be_bytes());
+    info.extend_from_slice(&0u32.to_be_bytes()); // no archive
+    info.extend_from_slice(&0u32.to_be_bytes());
+    info.extend_from_slice(&reference(0x6C));
+    info.extend_from_slice(&0u32.to_be_bytes()); // empty group entry table
+    info.resize(0x98, 0); // zeroed SoundArchiveInfo at 0x70
+    info.extend_from_slice(extra);
+    for &(at, to) in references {
+        info[at..at + 8].copy_from_slice(&reference(to));
+    }
+
+    let mut data = vec![0; 0x40];
+    data[0..4].copy_from_slice(b"RSAR");
+    data[4..6].copy_from_slice(&[0xFE, 0xFF]);
+    data[6..8].copy_from_slice(&0x0104u16.to_be_bytes());
+    data[0xE..0x10].copy_from_slice(&3u16.to_be_bytes());
+    data.extend_from_slice(b"SYMB");
+    data.extend_from_slice(&0x20u32.to_be_bytes());
+    data.resize(0x60, 0);
+    data.extend_from_slice(b"INFO");
+    data.extend_from_slice(&(info.len() as u32 + 8).to_be_bytes());
+    data.extend_from_slice(&info);
+    data.extend_from_slice(b"FILE");
+    data.extend_from_slice(&0x40u32.to_be_bytes());
+    data.resize(file_offset as usize + 0x40, 0);
+    for (idx, block) in [(0x40u32, 0x20u32), (0x60, file_offset - 0x60), (file_offset, 0x40)].iter().enumerate() {
+        data[0x10 + idx * 8..0x14 + idx * 8].copy_from_slice(&block.0.to_be_bytes());
+        data[0x14 + idx * 8..0x18 + idx * 8].copy_from_slice(&block.1.to_be_bytes());
+    }
+    let len = data.len() as u32;
+    data[8..0xC].copy_from_slice(&len.to_be_bytes());
+    data
+}
+
+// test_brsar with a 0x24 byte bank and a 0x10 byte wave archive added as group 1
+pub(crate) fn test_brsar_with_bank() -> Vec<u8> {
+    let mut editor = BrsarEditor::new(test_brsar()).unwrap();
+    editor.add_bank(&[1; 0x24], Some(&[2; 0x10])).unwrap();
+    editor.into_bytes()
+}
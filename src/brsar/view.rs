@@ -0,0 +1,228 @@
+//! Borrowed handles to the entries of an archive, for following the links between them without
+//! indexing the tables by hand.
+//!
+//! Every lookup returns `None` for indices that are out of range instead of panicking.
+
+use super::BRSAR;
+use super::id::{SoundId, FileId, GroupId, BankId, PlayerId};
+use super::block::{SymbolBlock, InfoBlock};
+use super::block::info::{SoundInfo, SoundDetails, FileInfo, GroupInfo, GroupEntry, BankInfo, PlayerInfo};
+use super::source::ArchiveSource;
+use std::borrow::Cow;
+use std::io;
+use std::ops::Deref;
+
+macro_rules! handle {
+    ($name:ident, $id:ty, $info:ty) => {
+        #[derive(Clone, Copy)]
+        pub struct $name<'a> {
+            brsar: &'a BRSAR,
+            id: $id,
+            info: &'a $info,
+        }
+
+        impl<'a> $name<'a> {
+            pub fn id(&self) -> $id {
+                self.id
+            }
+
+            pub fn info(&self) -> &'a $info {
+                self.info
+            }
+        }
+
+        impl Deref for $name<'_> {
+            type Target = $info;
+
+            fn deref(&self) -> &Self::Target {
+                self.info
+            }
+        }
+    };
+}
+
+handle!(SoundRef, SoundId, SoundInfo);
+handle!(FileRef, FileId, FileInfo);
+handle!(GroupRef, GroupId, GroupInfo);
+handle!(BankRef, BankId, BankInfo);
+handle!(PlayerRef, PlayerId, PlayerInfo);
+
+impl BRSAR {
+    fn symbol_block(&self) -> &SymbolBlock {
+        &self.symbol.block
+    }
+
+    fn info_block(&self) -> &InfoBlock {
+        &self.info.block
+    }
+
+    pub fn sound(&self, id: SoundId) -> Option<SoundRef<'_>> {
+        let info = self.info_block().sound_table.get(id)?;
+        Some(SoundRef { brsar: self, id, info })
+    }
+
+    pub fn sounds(&self) -> impl Iterator<Item = SoundRef<'_>> {
+        self.info_block().sound_table.ids().filter_map(move |id| self.sound(id))
+    }
+
+    pub fn find_sound(&self, name: &str) -> Option<SoundRef<'_>> {
+        self.sound(self.symbol_block().find_sound(name)?)
+    }
+
+    pub fn file(&self, id: FileId) -> Option<FileRef<'_>> {
+        let info = self.info_block().file_table.get(id)?;
+        Some(FileRef { brsar: self, id, info })
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = FileRef<'_>> {
+        self.info_block().file_table.ids().filter_map(move |id| self.file(id))
+    }
+
+    pub fn group(&self, id: GroupId) -> Option<GroupRef<'_>> {
+        let info = self.info_block().group_table.get(id)?;
+        Some(GroupRef { brsar: self, id, info })
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = GroupRef<'_>> {
+        self.info_block().group_table.ids().filter_map(move |id| self.group(id))
+    }
+
+    pub fn bank(&self, id: BankId) -> Option<BankRef<'_>> {
+        let info = self.info_block().bank_table.get(id)?;
+        Some(BankRef { brsar: self, id, info })
+    }
+
+    pub fn banks(&self) -> impl Iterator<Item = BankRef<'_>> {
+        self.info_block().bank_table.ids().filter_map(move |id| self.bank(id))
+    }
+
+    pub fn find_bank(&self, name: &str) -> Option<BankRef<'_>> {
+        self.bank(self.symbol_block().find_bank(name)?)
+    }
+
+    pub fn player(&self, id: PlayerId) -> Option<PlayerRef<'_>> {
+        let info = self.info_block().player_table.get(id)?;
+        Some(PlayerRef { brsar: self, id, info })
+    }
+
+    pub fn players(&self) -> impl Iterator<Item = PlayerRef<'_>> {
+        self.info_block().player_table.ids().filter_map(move |id| self.player(id))
+    }
+}
+
+impl<'a> SoundRef<'a> {
+    pub fn name(&self) -> Option<String> {
+        self.brsar.symbol_block().name(self.info.string_id)
+    }
+
+    pub fn file(&self) -> Option<FileRef<'a>> {
+        self.brsar.file(self.info.file_id)
+    }
+
+    pub fn player(&self) -> Option<PlayerRef<'a>> {
+        self.brsar.player(self.info.player_id)
+    }
+
+    /// The bank a sequence plays its notes with, `None` for other kinds of sounds.
+    pub fn bank(&self) -> Option<BankRef<'a>> {
+        match &*self.info.details {
+            SoundDetails::Sequence(details) => self.brsar.bank(details.soundbank_index),
+            _ => None
+        }
+    }
+}
+
+impl<'a> FileRef<'a> {
+    /// Name of the file on disk, for files that aren't stored in the archive.
+    pub fn external_name(&self) -> Option<String> {
+        self.info.external_file.as_ref().map(|name| name.0.to_string())
+    }
+
+    /// Every group holding this file, along with its entry in that group.
+    pub fn groups(&self) -> impl Iterator<Item = (GroupRef<'a>, &'a GroupEntry)> + 'a {
+        let brsar = self.brsar;
+        self.info.file_positions.0.iter().filter_map(move |pos| {
+            let group = brsar.group(pos.group_index)?;
+            let entry = group.info.entries.0.get(pos.item_index as usize)?;
+            Some((group, &**entry))
+        })
+    }
+
+    /// The entry of the first group holding this file, which is where its data is read from.
+    pub fn entry(&self) -> Option<&'a GroupEntry> {
+        self.groups().next().map(|(_, entry)| entry)
+    }
+
+    /// The raw file, `None` for external files.
+    pub fn data<'s, S: ArchiveSource>(&self, source: &'s mut S) -> io::Result<Option<Cow<'s, [u8]>>> {
+        match self.entry() {
+            Some(entry) => entry.file_bytes(source).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    /// The wave archive belonging to the file, if it has one.
+    pub fn archive<'s, S: ArchiveSource>(&self, source: &'s mut S) -> io::Result<Option<Cow<'s, [u8]>>> {
+        match self.entry() {
+            Some(entry) => entry.archive_bytes(source),
+            None => Ok(None)
+        }
+    }
+}
+
+impl<'a> GroupRef<'a> {
+    pub fn name(&self) -> Option<String> {
+        self.brsar.symbol_block().name(self.info.string_id)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &'a GroupEntry> {
+        self.info.entries.0.iter().map(|entry| &**entry)
+    }
+
+    /// The files in this group, in the order they're stored.
+    pub fn files(&self) -> impl Iterator<Item = FileRef<'a>> + 'a {
+        let brsar = self.brsar;
+        self.entries().filter_map(move |entry| brsar.file(entry.file_id))
+    }
+}
+
+impl<'a> BankRef<'a> {
+    pub fn name(&self) -> Option<String> {
+        self.brsar.symbol_block().name(self.info.string_id)
+    }
+
+    pub fn file(&self) -> Option<FileRef<'a>> {
+        self.brsar.file(self.info.file_id)
+    }
+}
+
+impl<'a> PlayerRef<'a> {
+    pub fn name(&self) -> Option<String> {
+        self.brsar.symbol_block().name(self.info.string_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_data::test_brsar;
+    use binread::BinReaderExt;
+    use binread::io::Cursor;
+
+    #[test]
+    fn out_of_range() {
+        let brsar: BRSAR = Cursor::new(test_brsar()).read_be().unwrap();
+
+        let group = brsar.group(GroupId(0)).unwrap();
+        assert_eq!((group.id(), group.file_base), (GroupId(0), 0x120));
+        assert_eq!(group.entries().count(), 0);
+        assert!(group.name().is_none());
+        assert_eq!(brsar.groups().count(), 1);
+
+        assert!(brsar.group(GroupId(1)).is_none());
+        assert!(brsar.sound(SoundId(0)).is_none());
+        assert!(brsar.file(FileId(u32::MAX)).is_none());
+        assert!(brsar.find_sound("SE_JUMP").is_none());
+        assert_eq!(brsar.banks().count(), 0);
+    }
+}
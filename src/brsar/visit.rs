@@ -0,0 +1,278 @@
+//! Walking every table of a parsed archive, so that analyses and bulk edits only have to say what
+//! they do with each kind of entry.
+//!
+//! Every callback gets the path to the entry (see `Step`) and the span it was read from. All of
+//! them do nothing by default.
+
+use super::BRSAR;
+use super::block::info::{SoundInfo, SoundDetails, SeqDetails, StreamDetails, WaveDetails, BankInfo, PlayerInfo, FileInfo, FilePosition, GroupInfo, GroupEntry};
+use super::block::symbol::TreeData;
+use super::id::{SoundId, BankId, PlayerId, FileId, GroupId, StringId};
+use crate::common::{NullString, Span, Spanned};
+use binread::BinRead;
+use nintendo_patricia_tree::{Node, PatriciaTree};
+use std::fmt;
+
+/// One level of the path to an entry.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Step {
+    String(StringId),
+    SoundTree,
+    PlayerTree,
+    GroupTree,
+    BankTree,
+    Node(u32),
+    Sound(SoundId),
+    Details,
+    Bank(BankId),
+    Player(PlayerId),
+    File(FileId),
+    ExternalName,
+    Position(u32),
+    Group(GroupId),
+    Entry(u32),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::String(id) => write!(f, "string[{}]", id),
+            Step::SoundTree => write!(f, "sound_tree"),
+            Step::PlayerTree => write!(f, "player_tree"),
+            Step::GroupTree => write!(f, "group_tree"),
+            Step::BankTree => write!(f, "bank_tree"),
+            Step::Node(index) => write!(f, "node[{}]", index),
+            Step::Sound(id) => write!(f, "sound[{}]", id),
+            Step::Details => write!(f, "details"),
+            Step::Bank(id) => write!(f, "bank[{}]", id),
+            Step::Player(id) => write!(f, "player[{}]", id),
+            Step::File(id) => write!(f, "file[{}]", id),
+            Step::ExternalName => write!(f, "external_name"),
+            Step::Position(index) => write!(f, "position[{}]", index),
+            Step::Group(id) => write!(f, "group[{}]", id),
+            Step::Entry(index) => write!(f, "entry[{}]", index),
+        }
+    }
+}
+
+/// A path as text, like `group[1]/entry[0]`.
+pub fn format_path(path: &[Step]) -> String {
+    path.iter().map(Step::to_string).collect::<Vec<_>>().join("/")
+}
+
+macro_rules! visitor {
+    ($(#[$attr:meta])* $visitor:ident, $($mut:tt)?) => {
+        $(#[$attr])*
+        pub trait $visitor {
+            fn visit_string(&mut self, _path: &[Step], _span: Span, _name: &$($mut)? NullString) {}
+            fn visit_tree_node<Id: BinRead<Args = ()>>(&mut self, _path: &[Step], _span: Span, _node: &$($mut)? Node<TreeData<Id>>) {}
+            fn visit_sound(&mut self, _path: &[Step], _span: Span, _sound: &$($mut)? SoundInfo) {}
+            fn visit_seq_details(&mut self, _path: &[Step], _span: Span, _details: &$($mut)? SeqDetails) {}
+            fn visit_stream_details(&mut self, _path: &[Step], _span: Span, _details: &$($mut)? StreamDetails) {}
+            fn visit_wave_details(&mut self, _path: &[Step], _span: Span, _details: &$($mut)? WaveDetails) {}
+            fn visit_bank(&mut self, _path: &[Step], _span: Span, _bank: &$($mut)? BankInfo) {}
+            fn visit_player(&mut self, _path: &[Step], _span: Span, _player: &$($mut)? PlayerInfo) {}
+            fn visit_file(&mut self, _path: &[Step], _span: Span, _file: &$($mut)? FileInfo) {}
+            fn visit_file_position(&mut self, _path: &[Step], _span: Span, _position: &$($mut)? FilePosition) {}
+            fn visit_group(&mut self, _path: &[Step], _span: Span, _group: &$($mut)? GroupInfo) {}
+            fn visit_group_entry(&mut self, _path: &[Step], _span: Span, _entry: &$($mut)? GroupEntry) {}
+        }
+    };
+}
+
+visitor!(
+    /// Callbacks for `BRSAR::walk`.
+    Visitor,
+);
+visitor!(
+    /// Callbacks for `BRSAR::walk_mut`, which can change the entries they're given.
+    ///
+    /// Only the parsed structs change, spans are those of the original entries.
+    VisitorMut, mut
+);
+
+macro_rules! walker {
+    ($(#[$attr:meta])* $walk:ident, $visitor:ident, $nodes:ident, $($mut:tt)?) => {
+        $(#[$attr])*
+        pub fn $walk<V: $visitor>(&$($mut)? self, visitor: &mut V) {
+            fn tree<V: $visitor, Id: BinRead<Args = ()>>(visitor: &mut V, path: &mut Vec<Step>, tree: &$($mut)? PatriciaTree<TreeData<Id>>) {
+                for (index, node) in tree.$nodes().into_iter().enumerate() {
+                    path.push(Step::Node(index as u32));
+                    visitor.visit_tree_node(path, node.span(), node);
+                    path.pop();
+                }
+            }
+
+            let mut path = Vec::new();
+
+            let symbol = &$($mut)? *self.symbol.block;
+            for (index, name) in (&$($mut)? symbol.string_table.0).into_iter().enumerate() {
+                path.push(Step::String(StringId(index as u32)));
+                visitor.visit_string(&path, (**name).span(), &$($mut)? **name);
+                path.pop();
+            }
+
+            path.push(Step::SoundTree);
+            tree(visitor, &mut path, &$($mut)? *symbol.sound_tree);
+            path.pop();
+            path.push(Step::PlayerTree);
+            tree(visitor, &mut path, &$($mut)? *symbol.player_tree);
+            path.pop();
+            path.push(Step::GroupTree);
+            tree(visitor, &mut path, &$($mut)? *symbol.group_tree);
+            path.pop();
+            path.push(Step::BankTree);
+            tree(visitor, &mut path, &$($mut)? *symbol.bank_tree);
+            path.pop();
+
+            let info = &$($mut)? *self.info.block;
+            for (index, sound) in (&$($mut)? info.sound_table.0).into_iter().enumerate() {
+                path.push(Step::Sound(SoundId(index as u32)));
+                visitor.visit_sound(&path, (**sound).span(), &$($mut)? **sound);
+
+                path.push(Step::Details);
+    match &$($mut)? *sound.details {
+                    SoundDetails::Sequence(details) => visitor.visit_seq_details(&path, details.span(), details),
+                    SoundDetails::Stream(details) => visitor.visit_stream_details(&path, details.span(), details),
+                    SoundDetails::Wave(details) => visitor.visit_wave_details(&path, details.span(), details)
+                }
+                path.pop();
+                path.pop();
+            }
+
+            for (index, bank) in (&$($mut)? info.bank_table.0).into_iter().enumerate() {
+                path.push(Step::Bank(BankId(index as u32)));
+                visitor.visit_bank(&path, (**bank).span(), &$($mut)? **bank);
+                path.pop();
+            }
+
+            for (index, player) in (&$($mut)? info.player_table.0).into_iter().enumerate() {
+                path.push(Step::Player(PlayerId(index as u32)));
+                visitor.visit_player(&path, (**player).span(), &$($mut)? **player);
+                path.pop();
+            }
+
+            for (index, file) in (&$($mut)? info.file_table.0).into_iter().enumerate() {
+                path.push(Step::File(FileId(index as u32)));
+                visitor.visit_file(&path, (**file).span(), &$($mut)? **file);
+
+                if let Some(name) = &$($mut)? file.external_file {
+                    path.push(Step::ExternalName);
+                    visitor.visit_string(&path, (*name.0).span(), &$($mut)? *name.0);
+                    path.pop();
+                }
+                for (index, position) in (&$($mut)? file.file_positions.0).into_iter().enumerate() {
+                    path.push(Step::Position(index as u32));
+                    visitor.visit_file_position(&path, (**position).span(), &$($mut)? **position);
+                    path.pop();
+                }
+                path.pop();
+            }
+
+            for (index, group) in (&$($mut)? info.group_table.0).into_iter().enumerate() {
+                path.push(Step::Group(GroupId(index as u32)));
+                visitor.visit_group(&path, (**group).span(), &$($mut)? **group);
+
+                for (index, entry) in (&$($mut)? group.entries.0).into_iter().enumerate() {
+                    path.push(Step::Entry(index as u32));
+                    visitor.visit_group_entry(&path, (**entry).span(), &$($mut)? **entry);
+                    path.pop();
+                }
+                path.pop();
+            }
+        }
+    };
+}
+
+impl BRSAR {
+    walker!(
+        /// Calls the visitor for every string, tree node and table entry, in the order the tables
+        /// are in the SYMB and INFO blocks.
+        walk, Visitor, nodes,
+    );
+    walker!(
+        /// `walk`, with mutable access to every entry.
+        walk_mut, VisitorMut, nodes_mut, mut
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_data::test_brsar_with_bank;
+    use binread::BinReaderExt;
+    use binread::io::Cursor;
+
+    fn brsar_with_bank() -> (Vec<u8>, BRSAR) {
+        let data = test_brsar_with_bank();
+        let brsar = Cursor::new(&data).read_be().unwrap();
+        (data, brsar)
+    }
+
+    fn u32_at(data: &[u8], offset: u64) -> u32 {
+        let offset = offset as usize;
+        u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+    }
+
+    #[derive(Default)]
+    struct Collect {
+        visited: Vec<(String, Span)>,
+    }
+
+    impl Visitor for Collect {
+        fn visit_bank(&mut self, path: &[Step], span: Span, _: &BankInfo) {
+            self.visited.push((format_path(path), span));
+        }
+
+        fn visit_file_position(&mut self, path: &[Step], span: Span, _: &FilePosition) {
+            self.visited.push((format_path(path), span));
+        }
+
+        fn visit_group(&mut self, path: &[Step], span: Span, _: &GroupInfo) {
+            self.visited.push((format_path(path), span));
+        }
+
+        fn visit_group_entry(&mut self, path: &[Step], span: Span, _: &GroupEntry) {
+            self.visited.push((format_path(path), span));
+        }
+    }
+
+    #[test]
+    fn paths_and_spans() {
+        let (data, brsar) = brsar_with_bank();
+        let mut collect = Collect::default();
+        brsar.walk(&mut collect);
+
+        let paths: Vec<&str> = collect.visited.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["bank[0]", "file[0]/position[0]", "group[0]", "group[1]", "group[1]/entry[0]"]);
+
+        // check the spans against the fields that are in them
+        let span = |path: &str| collect.visited.iter().find(|(p, _)| p == path).unwrap().1;
+        let offset = |path: &str| span(path).offset;
+        assert_eq!(u32_at(&data, offset("bank[0]") + 4), 0); // file id
+        assert_eq!(u32_at(&data, offset("file[0]/position[0]")), 1); // group index
+        assert_eq!(u32_at(&data, offset("group[0]") + 0x10), brsar.group(GroupId(0)).unwrap().file_base);
+        assert_eq!(u32_at(&data, offset("group[1]") + 0x10), brsar.group(GroupId(1)).unwrap().file_base);
+        assert_eq!(u32_at(&data, offset("group[1]/entry[0]") + 8), 0x24); // file size
+        assert_eq!(span("bank[0]").len, 0x0C);
+        assert_eq!(span("group[1]").len, 0x28);
+        assert_eq!(span("group[1]/entry[0]").len, 0x18);
+    }
+
+    struct MoveFiles(u32);
+
+    impl VisitorMut for MoveFiles {
+        fn visit_group(&mut self, _: &[Step], _: Span, group: &mut GroupInfo) {
+            group.file_base += self.0;
+        }
+    }
+
+    #[test]
+    fn bulk_edit() {
+        let (_, mut brsar) = brsar_with_bank();
+        let bases: Vec<u32> = brsar.groups().map(|group| group.file_base).collect();
+        brsar.walk_mut(&mut MoveFiles(0x20));
+        let moved: Vec<u32> = brsar.groups().map(|group| group.file_base).collect();
+        assert_eq!(moved, bases.iter().map(|base| base + 0x20).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,134 @@
+//! Which entries of an archive refer to which, for finding out what depends on a file, bank or
+//! player before changing it.
+
+use super::BRSAR;
+use super::block::InfoBlock;
+use super::block::info::SoundDetails;
+use super::id::{SoundId, BankId, PlayerId, FileId, GroupId};
+
+/// The reverse of the references in the INFO block, indexed by the entry being referred to.
+///
+/// References to entries that don't exist are left out.
+pub struct CrossReferences {
+    file_sounds: Vec<Vec<SoundId>>,
+    file_banks: Vec<Vec<BankId>>,
+    file_groups: Vec<Vec<GroupId>>,
+    bank_sequences: Vec<Vec<SoundId>>,
+    player_sounds: Vec<Vec<SoundId>>,
+}
+
+fn add<Id: PartialEq>(lists: &mut [Vec<Id>], index: usize, id: Id) {
+    if let Some(list) = lists.get_mut(index) {
+        if !list.contains(&id) {
+            list.push(id);
+        }
+    }
+}
+
+fn lookup<Id>(lists: &[Vec<Id>], index: usize) -> &[Id] {
+    lists.get(index).map_or(&[][..], Vec::as_slice)
+}
+
+impl CrossReferences {
+    pub fn new(info: &InfoBlock) -> CrossReferences {
+        let files = info.file_table.0.len();
+        let mut refs = CrossReferences {
+            file_sounds: vec![Vec::new(); files],
+            file_banks: vec![Vec::new(); files],
+            file_groups: vec![Vec::new(); files],
+            bank_sequences: vec![Vec::new(); info.bank_table.0.len()],
+            player_sounds: vec![Vec::new(); info.player_table.0.len()],
+        };
+
+        for id in info.sound_table.ids() {
+            let sound = &info.sound_table[id];
+            add(&mut refs.file_sounds, sound.file_id.index(), id);
+            add(&mut refs.player_sounds, sound.player_id.index(), id);
+            if let SoundDetails::Sequence(details) = &*sound.details {
+                add(&mut refs.bank_sequences, details.soundbank_index.index(), id);
+            }
+        }
+        for id in info.bank_table.ids() {
+            add(&mut refs.file_banks, info.bank_table[id].file_id.index(), id);
+        }
+        for id in info.group_table.ids() {
+            for entry in info.group_table[id].entries.0.iter() {
+                add(&mut refs.file_groups, entry.file_id.index(), id);
+            }
+        }
+        refs
+    }
+
+    pub fn sounds_using_file(&self, file: FileId) -> &[SoundId] {
+        lookup(&self.file_sounds, file.index())
+    }
+
+    pub fn banks_using_file(&self, file: FileId) -> &[BankId] {
+        lookup(&self.file_banks, file.index())
+    }
+
+    pub fn groups_containing_file(&self, file: FileId) -> &[GroupId] {
+        lookup(&self.file_groups, file.index())
+    }
+
+    pub fn sequences_using_bank(&self, bank: BankId) -> &[SoundId] {
+        lookup(&self.bank_sequences, bank.index())
+    }
+
+    pub fn sounds_on_player(&self, player: PlayerId) -> &[SoundId] {
+        lookup(&self.player_sounds, player.index())
+    }
+
+    /// Files that no sound, bank or group refers to.
+    pub fn unreferenced_files(&self) -> impl Iterator<Item = FileId> + '_ {
+        (0..self.file_sounds.len() as u32)
+            .map(FileId)
+            .filter(move |&file| {
+                self.sounds_using_file(file).is_empty()
+                    && self.banks_using_file(file).is_empty()
+                    && self.groups_containing_file(file).is_empty()
+            })
+    }
+}
+
+impl BRSAR {
+    pub fn cross_references(&self) -> CrossReferences {
+        CrossReferences::new(&self.info.block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_data::{test_brsar_with_bank, test_brsar_with_file};
+    use binread::BinReaderExt;
+    use binread::io::Cursor;
+
+    #[test]
+    fn added_bank() {
+        let brsar: BRSAR = Cursor::new(test_brsar_with_bank()).read_be().unwrap();
+
+        let refs = brsar.cross_references();
+        assert_eq!(refs.banks_using_file(FileId(0)), &[BankId(0)]);
+        assert_eq!(refs.groups_containing_file(FileId(0)), &[GroupId(1)]);
+        assert!(refs.sounds_using_file(FileId(0)).is_empty());
+        assert!(refs.sequences_using_bank(BankId(0)).is_empty());
+        assert_eq!(refs.unreferenced_files().count(), 0);
+
+        // out of range
+        assert!(refs.banks_using_file(FileId(1)).is_empty());
+        assert!(refs.sounds_on_player(PlayerId(0)).is_empty());
+    }
+
+    #[test]
+    fn grouped_file() {
+        let brsar: BRSAR = Cursor::new(test_brsar_with_file()).read_be().unwrap();
+
+        // only the group refers to the file, which still keeps it in use
+        let refs = brsar.cross_references();
+        assert_eq!(refs.groups_containing_file(FileId(0)), &[GroupId(0)]);
+        assert!(refs.sounds_using_file(FileId(0)).is_empty());
+        assert!(refs.banks_using_file(FileId(0)).is_empty());
+        assert_eq!(refs.unreferenced_files().count(), 0);
+    }
+}
@@ -0,0 +1,346 @@
+//! Nintendo GameCube/Wii DSP-ADPCM.
+//!
+//! Audio is stored in 8 byte frames. The first byte of each frame holds the predictor index (high
+//! nibble) and the scale exponent (low nibble), followed by 14 signed 4-bit samples.
+//! Decoding each sample depends on the previous two decoded samples ("history"), so decoding from
+//! anywhere other than the start of the data requires history saved at that point, such as the
+//! ADPC block of an RSTM or the loop context in [`AdpcmInfo`](crate::common::AdpcmInfo).
+
+use crate::common::AdpcmInfo;
+
+pub const BYTES_PER_FRAME: usize = 8;
+pub const SAMPLES_PER_FRAME: usize = 14;
+pub const NIBBLES_PER_FRAME: usize = 16;
+
+/// 8 pairs of filter coefficients, in 5.11 fixed point.
+pub type Coefficients = [i16; 16];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct FrameHeader {
+    pub predictor: u8,
+    pub scale: u8,
+}
+
+impl FrameHeader {
+    pub fn from_byte(byte: u8) -> FrameHeader {
+        FrameHeader {
+            predictor: (byte >> 4) & 0x7,
+            scale: byte & 0xF,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        (self.predictor << 4) | (self.scale & 0xF)
+    }
+}
+
+/// The two most recently decoded samples.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct History {
+    pub hist1: i16,
+    pub hist2: i16,
+}
+
+impl History {
+    pub fn new(hist1: i16, hist2: i16) -> History {
+        History { hist1, hist2 }
+    }
+
+    fn push(&mut self, sample: i16) {
+        self.hist2 = self.hist1;
+        self.hist1 = sample;
+    }
+}
+
+pub fn clamp16(value: i32) -> i16 {
+    value.max(i16::MIN as i32).min(i16::MAX as i32) as i16
+}
+
+fn sign_extend_nibble(nibble: u8) -> i32 {
+    (((nibble as i8) << 4) >> 4) as i32
+}
+
+/// Number of frame-aligned bytes needed to hold the given number of samples.
+pub fn samples_to_bytes(samples: usize) -> usize {
+    samples.div_ceil(SAMPLES_PER_FRAME) * BYTES_PER_FRAME
+}
+
+/// Converts a sample index into a nibble address, which skips over the frame headers.
+pub fn sample_to_nibble(sample: usize) -> usize {
+    sample / SAMPLES_PER_FRAME * NIBBLES_PER_FRAME + sample % SAMPLES_PER_FRAME + 2
+}
+
+/// Total number of nibbles (including frame headers) used by the given number of samples.
+pub fn sample_count_to_nibbles(samples: usize) -> usize {
+    let rem = samples % SAMPLES_PER_FRAME;
+    samples / SAMPLES_PER_FRAME * NIBBLES_PER_FRAME + if rem == 0 { 0 } else { rem + 2 }
+}
+
+/// Converts a nibble address back into a sample index.
+/// Addresses that point at a frame header are rounded to the first sample of the frame.
+pub fn nibble_to_sample(nibble: usize) -> usize {
+    let rem = nibble % NIBBLES_PER_FRAME;
+    nibble / NIBBLES_PER_FRAME * SAMPLES_PER_FRAME + rem.saturating_sub(2)
+}
+
+/// Stateful decoder for a single channel.
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    pub coefs: Coefficients,
+    pub history: History,
+}
+
+impl Decoder {
+    pub fn new(coefs: Coefficients, history: History) -> Decoder {
+        Decoder { coefs, history }
+    }
+
+    /// Decoder positioned at the start of the channel's data.
+    pub fn from_info(info: &AdpcmInfo) -> Decoder {
+        Decoder::new(info.coefs, History::new(info.hist1, info.hist2))
+    }
+
+    /// Decoder primed with the history immediately preceding the loop start sample.
+    /// Use with [`Decoder::resume`], since the loop start doesn't have to be frame aligned.
+    pub fn from_info_loop(info: &AdpcmInfo) -> Decoder {
+        Decoder::new(info.coefs, History::new(info.loop_hist1, info.loop_hist2))
+    }
+
+    pub fn decode_sample(&mut self, header: FrameHeader, nibble: u8) -> i16 {
+        let predictor = header.predictor as usize;
+        let coef1 = self.coefs[predictor * 2] as i64;
+        let coef2 = self.coefs[predictor * 2 + 1] as i64;
+
+        // i64, as the predictions alone can overflow i32 with extreme coefficients
+        let sample = (((sign_extend_nibble(nibble) as i64) << header.scale) << 11)
+            + 1024
+            + coef1 * self.history.hist1 as i64
+            + coef2 * self.history.hist2 as i64;
+        let sample = (sample >> 11).clamp(i16::MIN as i64, i16::MAX as i64) as i16;
+        self.history.push(sample);
+        sample
+    }
+
+    /// Decodes up to `max` samples from one frame, returning how many were decoded.
+    pub fn decode_frame(&mut self, frame: &[u8], out: &mut Vec<i16>, max: usize) -> usize {
+        let header = match frame.first() {
+            Some(&byte) => FrameHeader::from_byte(byte),
+            None => return 0
+        };
+
+        let mut decoded = 0;
+        for &byte in &frame[1..frame.len().min(BYTES_PER_FRAME)] {
+            for &nibble in &[byte >> 4, byte & 0xF] {
+                if decoded == max {
+                    return decoded;
+                }
+                out.push(self.decode_sample(header, nibble));
+                decoded += 1;
+            }
+        }
+        decoded
+    }
+
+    /// Decodes `count` samples from frame aligned data, stopping early if the data runs out.
+    pub fn decode(&mut self, data: &[u8], count: usize) -> Vec<i16> {
+        let mut out = Vec::with_capacity(count);
+        self.decode_into(data, count, &mut out);
+        out
+    }
+
+    pub fn decode_into(&mut self, data: &[u8], count: usize, out: &mut Vec<i16>) {
+        let mut remaining = count;
+        for frame in data.chunks(BYTES_PER_FRAME) {
+            if remaining == 0 {
+                break;
+            }
+            remaining -= self.decode_frame(frame, out, remaining);
+        }
+    }
+
+    /// Decodes `count` samples starting at `start`, where `self.history` is the history at the
+    /// start of the frame containing `start` (for example the loop context, or an ADPC entry).
+    /// `data` must begin at that frame.
+    pub fn decode_from(&mut self, data: &[u8], start: usize, count: usize) -> Vec<i16> {
+        let skip = start % SAMPLES_PER_FRAME;
+        let mut out = self.decode(data, skip + count);
+        out.drain(..skip.min(out.len()));
+        out
+    }
+
+    /// Decodes `count` samples starting at `start`, where `self.history` is the history
+    /// immediately preceding `start`, as the hardware does when jumping to a loop start.
+    /// `data` must begin at the frame containing `start`.
+    pub fn resume(&mut self, data: &[u8], start: usize, count: usize) -> Vec<i16> {
+        let mut out = Vec::with_capacity(count);
+        let header = match data.first() {
+            Some(&byte) => FrameHeader::from_byte(byte),
+            None => return out
+        };
+
+        let skip = start % SAMPLES_PER_FRAME;
+        for idx in skip..SAMPLES_PER_FRAME {
+            let byte = match data.get(1 + idx / 2) {
+                Some(&byte) => byte,
+                None => return out
+            };
+            if out.len() == count {
+                return out;
+            }
+            let nibble = if idx % 2 == 0 { byte >> 4 } else { byte & 0xF };
+            out.push(self.decode_sample(header, nibble));
+        }
+
+        let remaining = count - out.len();
+        self.decode_into(data.get(BYTES_PER_FRAME..).unwrap_or(&[]), remaining, &mut out);
+        out
+    }
+}
+
+/// Recomputes the loop context of `info` (predictor/scale and history at `loop_start`) from the
+/// encoded data, or clears it if there's no loop.
+pub fn set_loop_context(info: &mut AdpcmInfo, data: &[u8], loop_start: Option<usize>) {
+    let (pred_scale, history) = match loop_start {
+        Some(start) => {
+            let mut decoder = Decoder::from_info(info);
+            decoder.decode(data, start);
+            let frame = start / SAMPLES_PER_FRAME * BYTES_PER_FRAME;
+            (data.get(frame).copied().unwrap_or(0) as u16, decoder.history)
+        }
+        None => (0, History::default())
+    };
+    info.loop_pred_scale = pred_scale;
+    info.loop_hist1 = history.hist1;
+    info.loop_hist2 = history.hist2;
+}
+
+/// Convenience wrapper for decoding a whole channel in one go.
+pub fn decode(data: &[u8], coefs: &Coefficients, history: History, count: usize) -> Vec<i16> {
+    Decoder::new(*coefs, history).decode(data, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // predictor pair 0 acts as a plain first order filter, the rest are unused.
+    const COEFS: Coefficients = [
+        0x0800, 0, 0x1000, -0x0800, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0
+    ];
+
+    #[test]
+    fn frame_header() {
+        let header = FrameHeader::from_byte(0x3B);
+        assert_eq!(header, FrameHeader { predictor: 3, scale: 0xB });
+        assert_eq!(header.to_byte(), 0x3B);
+    }
+
+    #[test]
+    fn silent_frame() {
+        let out = decode(&[0u8; 8], &COEFS, History::default(), 14);
+        assert_eq!(out, vec![0; 14]);
+    }
+
+    #[test]
+    fn known_frame() {
+        // predictor 0 (coef1 = 1.0), scale 0: each sample is the previous one plus the nibble
+        let frame = [0x00, 0x12, 0xF0, 0, 0, 0, 0, 0];
+        let out = decode(&frame, &COEFS, History::default(), 14);
+        assert_eq!(&out[..4], &[1, 3, 2, 2]);
+
+        // scale 2 multiplies nibbles by 4
+        let frame = [0x02, 0x10, 0, 0, 0, 0, 0, 0];
+        let out = decode(&frame, &COEFS, History::new(100, 0), 2);
+        assert_eq!(out, vec![104, 104]);
+    }
+
+    #[test]
+    fn second_order_prediction() {
+        // predictor 1: 2 * hist1 - hist2, i.e. continue a linear ramp
+        let frame = [0x10, 0, 0, 0, 0, 0, 0, 0];
+        let out = decode(&frame, &COEFS, History::new(20, 10), 3);
+        assert_eq!(out, vec![30, 40, 50]);
+    }
+
+    #[test]
+    fn clamps() {
+        let frame = [0x0C, 0x77, 0x77, 0x77, 0, 0, 0, 0];
+        let out = decode(&frame, &COEFS, History::new(i16::MAX - 10, 0), 6);
+        assert!(out.iter().all(|&s| s == i16::MAX));
+    }
+
+    #[test]
+    fn extreme_coefficients() {
+        let coefs = [-0x8000; 16];
+        let frame = [0x0F, 0x88, 0x88, 0x88, 0, 0, 0, 0];
+        let out = decode(&frame, &coefs, History::new(i16::MIN, i16::MIN), 6);
+        assert!(out.iter().all(|&s| s == i16::MAX || s == i16::MIN));
+    }
+
+    #[test]
+    fn count_stops_mid_frame() {
+        let frames = [0x00u8, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x00, 0x11, 0, 0, 0, 0, 0, 0];
+        let out = decode(&frames, &COEFS, History::default(), 16);
+        assert_eq!(out.len(), 16);
+        assert_eq!(out[15], 16);
+
+        // running out of data stops early
+        assert_eq!(decode(&frames[..8], &COEFS, History::default(), 100).len(), 14);
+    }
+
+    #[test]
+    fn resume_from_saved_history() {
+        let frames: Vec<u8> = (0..4u8)
+            .flat_map(|i| vec![i & 1, 0x1F, 0x2E, 0x3D, 0x4C, 0x5B, 0x6A, 0x79])
+            .collect();
+        let full = decode(&frames, &COEFS, History::default(), 56);
+
+        let mut first = Decoder::new(COEFS, History::default());
+        first.decode(&frames[..16], 28);
+        let history = first.history;
+        assert_eq!(history, History::new(full[27], full[26]));
+
+        let rest = Decoder::new(COEFS, history).decode(&frames[16..], 28);
+        assert_eq!(&full[28..], &rest[..]);
+
+        // starting mid-frame uses the history from the start of that frame
+        let mid = Decoder::new(COEFS, history).decode_from(&frames[16..], 28 + 5, 10);
+        assert_eq!(&full[33..43], &mid[..]);
+    }
+
+    #[test]
+    fn loop_context() {
+        let samples: Vec<i16> = (0..200).map(|i| ((i * 37) % 101 * 200 - 10000) as i16).collect();
+        let encoded = crate::codec::adpcm_encoder::encode(&samples, Some(45));
+
+        let mut info = encoded.info.clone();
+        set_loop_context(&mut info, &encoded.data, None);
+        assert_eq!((info.loop_pred_scale, info.loop_hist1, info.loop_hist2), (0, 0, 0));
+        set_loop_context(&mut info, &encoded.data, Some(45));
+        assert_eq!(info.loop_pred_scale, encoded.info.loop_pred_scale);
+        assert_eq!((info.loop_hist1, info.loop_hist2), (encoded.info.loop_hist1, encoded.info.loop_hist2));
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(sample_to_nibble(0), 2);
+        assert_eq!(sample_to_nibble(13), 15);
+        assert_eq!(sample_to_nibble(14), 18);
+        assert_eq!(nibble_to_sample(2), 0);
+        assert_eq!(nibble_to_sample(18), 14);
+        assert_eq!(nibble_to_sample(16), 14);
+        for sample in 0..100 {
+            assert_eq!(nibble_to_sample(sample_to_nibble(sample)), sample);
+        }
+        assert_eq!(sample_count_to_nibbles(14), 16);
+        assert_eq!(sample_count_to_nibbles(15), 19);
+        for count in 0..100 {
+            assert_eq!(nibble_to_sample(sample_count_to_nibbles(count)), count);
+        }
+        assert_eq!(samples_to_bytes(0), 0);
+        assert_eq!(samples_to_bytes(1), 8);
+        assert_eq!(samples_to_bytes(14), 8);
+        assert_eq!(samples_to_bytes(15), 16);
+    }
+}
@@ -0,0 +1,568 @@
+//! DSP-ADPCM encoder.
+//!
+//! This is a port of the coefficient search and frame encoder used by Nintendo's DSPADPCM tool,
+//! so output should match what official tooling produces for the same input.
+
+use super::adpcm::{Coefficients, FrameHeader, History, BYTES_PER_FRAME, SAMPLES_PER_FRAME};
+use crate::common::AdpcmInfo;
+
+type Vec3 = [f64; 3];
+
+/// Samples processed per block during coefficient analysis.
+const ANALYSIS_BLOCK: usize = 0x3800;
+
+/// Result of encoding a single channel.
+pub struct EncodedChannel {
+    pub data: Vec<u8>,
+    pub info: AdpcmInfo,
+    /// The samples as the hardware will decode them, used for computing history tables.
+    pub decoded: Vec<i16>,
+}
+
+impl EncodedChannel {
+    /// History immediately before `sample`, as stored in loop contexts and RSTM ADPC tables.
+    pub fn history_at(&self, sample: usize) -> History {
+        let get = |idx: Option<usize>| idx.and_then(|idx| self.decoded.get(idx)).copied().unwrap_or(0);
+        History::new(get(sample.checked_sub(1)), get(sample.checked_sub(2)))
+    }
+}
+
+/// Encodes a single channel of PCM16, computing its own coefficient set.
+///
+/// If `loop_start` is set, the loop context (predictor/scale and history) is filled in for it.
+pub fn encode(samples: &[i16], loop_start: Option<usize>) -> EncodedChannel {
+    let coefs = correlate_coefs(samples);
+    encode_with_coefs(samples, &coefs, loop_start)
+}
+
+/// Encodes each channel independently, each with its own coefficient set.
+pub fn encode_channels(channels: &[Vec<i16>], loop_start: Option<usize>) -> Vec<EncodedChannel> {
+    channels.iter().map(|samples| encode(samples, loop_start)).collect()
+}
+
+pub fn encode_with_coefs(samples: &[i16], coefs: &Coefficients, loop_start: Option<usize>) -> EncodedChannel {
+    let frame_count = samples.len().div_ceil(SAMPLES_PER_FRAME);
+    let mut data = Vec::with_capacity(frame_count * BYTES_PER_FRAME);
+    let mut decoded = Vec::with_capacity(samples.len());
+
+    // [hist2, hist1, 14 samples]
+    let mut buffer = [0i16; 16];
+    for frame in samples.chunks(SAMPLES_PER_FRAME) {
+        for (dst, &src) in buffer[2..].iter_mut().zip(frame.iter().chain(std::iter::repeat(&0))) {
+            *dst = src;
+        }
+
+        let mut out = [0u8; BYTES_PER_FRAME];
+        encode_frame(&mut buffer, SAMPLES_PER_FRAME, &mut out, coefs);
+        data.extend_from_slice(&out);
+        decoded.extend_from_slice(&buffer[2..2 + frame.len()]);
+
+        buffer[0] = buffer[14];
+        buffer[1] = buffer[15];
+    }
+
+    let info = AdpcmInfo {
+        coefs: *coefs,
+        pred_scale: data.first().copied().unwrap_or(0) as u16,
+        ..AdpcmInfo::default()
+    };
+
+    let mut encoded = EncodedChannel { data, info, decoded };
+    if let Some(loop_start) = loop_start {
+        let frame = loop_start / SAMPLES_PER_FRAME * BYTES_PER_FRAME;
+        let history = encoded.history_at(loop_start);
+        encoded.info.loop_pred_scale = encoded.data.get(frame).copied().unwrap_or(0) as u16;
+        encoded.info.loop_hist1 = history.hist1;
+        encoded.info.loop_hist2 = history.hist2;
+    }
+    encoded
+}
+
+/// Encodes one frame of up to 14 samples.
+///
+/// `pcm` holds the two previously *decoded* samples followed by the samples to encode. On return,
+/// the samples are replaced with how they will decode, ready to be used as history for the next frame.
+pub fn encode_frame(pcm: &mut [i16; 16], sample_count: usize, out: &mut [u8; BYTES_PER_FRAME], coefs: &Coefficients) {
+    let mut in_samples = [[0i32; 16]; 8];
+    let mut out_samples = [[0i32; 14]; 8];
+    let mut scale = [0i32; 8];
+    let mut dist_accum = [0f64; 8];
+
+    for i in 0..8 {
+        let coef1 = coefs[i * 2] as i32;
+        let coef2 = coefs[i * 2 + 1] as i32;
+
+        in_samples[i][0] = pcm[0] as i32;
+        in_samples[i][1] = pcm[1] as i32;
+
+        // find the largest difference between prediction and input
+        let mut distance = 0i32;
+        for s in 0..sample_count {
+            let v1 = (pcm[s] as i32 * coef2 + pcm[s + 1] as i32 * coef1) / 2048;
+            in_samples[i][s + 2] = v1;
+            let v3 = (pcm[s + 2] as i32 - v1).clamp(-32768, 32767);
+            if v3.abs() > distance.abs() {
+                distance = v3;
+            }
+        }
+
+        // initial scale guess
+        scale[i] = 0;
+        while scale[i] <= 12 && !(-8..=7).contains(&distance) {
+            scale[i] += 1;
+            distance /= 2;
+        }
+        scale[i] = if scale[i] <= 1 { -1 } else { scale[i] - 2 };
+
+        loop {
+            scale[i] += 1;
+            dist_accum[i] = 0.0;
+            let mut index = 0i32;
+
+            for s in 0..sample_count {
+                let v1 = in_samples[i][s] * coef2 + in_samples[i][s + 1] * coef1;
+                let v2 = ((pcm[s + 2] as i32) << 11) - v1;
+                let v2 = v2 / 2048;
+                let scaled = v2 as f64 / (1 << scale[i]) as f64;
+                let mut v3 = if v2 > 0 {
+                    (scaled + 0.4999999f32 as f64) as i32
+                } else {
+                    (scaled - 0.4999999f32 as f64) as i32
+                };
+
+                if v3 < -8 {
+                    index = index.max(-8 - v3);
+                    v3 = -8;
+                } else if v3 > 7 {
+                    index = index.max(v3 - 7);
+                    v3 = 7;
+                }
+
+                out_samples[i][s] = v3;
+
+                let v1 = (v1 + ((v3 * (1 << scale[i])) << 11) + 1024) >> 11;
+                let v2 = v1.clamp(-32768, 32767);
+                in_samples[i][s + 2] = v2;
+                let diff = (pcm[s + 2] as i32 - v2) as f64;
+                dist_accum[i] += diff * diff;
+            }
+
+            let mut x = index + 8;
+            while x > 256 {
+                scale[i] += 1;
+                if scale[i] >= 12 {
+                    scale[i] = 11;
+                }
+                x >>= 1;
+            }
+
+            if !(scale[i] < 12 && index > 1) {
+                break;
+            }
+        }
+    }
+
+    let mut best = 0;
+    for i in 1..8 {
+        if dist_accum[i] < dist_accum[best] {
+            best = i;
+        }
+    }
+
+    for s in 0..sample_count {
+        pcm[s + 2] = in_samples[best][s + 2] as i16;
+    }
+
+    out_samples[best][sample_count..].fill(0);
+
+    out[0] = FrameHeader { predictor: best as u8, scale: (scale[best] & 0xF) as u8 }.to_byte();
+    for y in 0..7 {
+        out[y + 1] = ((out_samples[best][y * 2] << 4) | (out_samples[best][y * 2 + 1] & 0xF)) as u8;
+    }
+}
+
+// region Coefficient analysis
+
+fn inner_product_merge(pcm: &[i16; 28]) -> Vec3 {
+    let mut out = [0.0; 3];
+    for i in 0..=2 {
+        for x in 0..14 {
+            out[i] -= pcm[14 + x - i] as f64 * pcm[14 + x] as f64;
+        }
+    }
+    out
+}
+
+fn outer_product_merge(pcm: &[i16; 28]) -> [Vec3; 3] {
+    let mut mtx = [[0.0; 3]; 3];
+    for x in 1..=2 {
+        for y in 1..=2 {
+            for z in 0..14 {
+                mtx[x][y] += pcm[14 + z - x] as f64 * pcm[14 + z - y] as f64;
+            }
+        }
+    }
+    mtx
+}
+
+/// LU decomposition with partial pivoting. Returns None if the matrix is (nearly) singular.
+fn analyze_ranges(mtx: &mut [Vec3; 3]) -> Option<[usize; 3]> {
+    let mut recips = [0.0; 3];
+    let mut idxs = [0usize; 3];
+
+    for x in 1..=2 {
+        let val = mtx[x][1].abs().max(mtx[x][2].abs());
+        if val < f64::EPSILON {
+            return None;
+        }
+        recips[x] = 1.0 / val;
+    }
+
+    let mut max_index = 0;
+    for i in 1..=2 {
+        for x in 1..i {
+            mtx[x][i] = (1..x).fold(mtx[x][i], |tmp, y| tmp - mtx[x][y] * mtx[y][i]);
+        }
+
+        let mut val = 0.0;
+        for x in i..=2 {
+            let tmp = (1..i).fold(mtx[x][i], |tmp, y| tmp - mtx[x][y] * mtx[y][i]);
+            mtx[x][i] = tmp;
+            let tmp = tmp.abs() * recips[x];
+            if tmp >= val {
+                val = tmp;
+                max_index = x;
+            }
+        }
+
+        if max_index != i {
+            // column 0 is unused, so the whole rows can be swapped
+            mtx.swap(max_index, i);
+            recips[max_index] = recips[i];
+        }
+
+        idxs[i] = max_index;
+
+        if mtx[i][i] == 0.0 {
+            return None;
+        }
+
+        if i != 2 {
+            let tmp = 1.0 / mtx[i][i];
+            for row in &mut mtx[i + 1..] {
+                row[i] *= tmp;
+            }
+        }
+    }
+
+    let mut min = 1.0e10f64;
+    let mut max = 0.0f64;
+    for (i, row) in mtx.iter().enumerate().skip(1) {
+        let tmp = row[i].abs();
+        min = min.min(tmp);
+        max = max.max(tmp);
+    }
+
+    if min / max < 1.0e-10 {
+        None
+    } else {
+        Some(idxs)
+    }
+}
+
+fn bidirectional_filter(mtx: &[Vec3; 3], idxs: &[usize; 3], vec: &mut Vec3) {
+    let mut x = 0;
+    for i in 1..=2 {
+        let index = idxs[i];
+        let mut tmp = vec[index];
+        vec[index] = vec[i];
+        if x != 0 {
+            for y in x..i {
+                tmp -= vec[y] * mtx[i][y];
+            }
+        } else if tmp != 0.0 {
+            x = i;
+        }
+        vec[i] = tmp;
+    }
+
+    for i in (1..=2).rev() {
+        let mut tmp = vec[i];
+        for y in i + 1..=2 {
+            tmp -= vec[y] * mtx[i][y];
+        }
+        vec[i] = tmp / mtx[i][i];
+    }
+
+    vec[0] = 1.0;
+}
+
+/// Returns false if the resulting filter would be unstable.
+fn quadratic_merge(vec: &mut Vec3) -> bool {
+    let v2 = vec[2];
+    let tmp = 1.0 - v2 * v2;
+    if tmp == 0.0 {
+        return false;
+    }
+
+    let v0 = (vec[0] - v2 * v2) / tmp;
+    let v1 = (vec[1] - vec[1] * v2) / tmp;
+    vec[0] = v0;
+    vec[1] = v1;
+
+    v1.abs() <= 1.0
+}
+
+fn finish_record(input: &mut Vec3) -> Vec3 {
+    for value in &mut input[1..] {
+        if *value >= 1.0 {
+            *value = 0.9999999999;
+        } else if *value <= -1.0 {
+            *value = -0.9999999999;
+        }
+    }
+    [1.0, input[2] * input[1] + input[1], input[2]]
+}
+
+fn matrix_filter(src: &Vec3) -> Vec3 {
+    let mut mtx = [[0.0; 3]; 3];
+
+    mtx[2][0] = 1.0;
+    for i in 1..=2 {
+        mtx[2][i] = -src[i];
+    }
+
+    for i in (1..=2).rev() {
+        let val = 1.0 - mtx[i][i] * mtx[i][i];
+        for y in 1..=i {
+            mtx[i - 1][y] = (mtx[i][i] * mtx[i][y] + mtx[i][y]) / val;
+        }
+    }
+
+    let mut dst = [1.0, 0.0, 0.0];
+    for i in 1..=2 {
+        for y in 1..=i {
+            dst[i] += mtx[i][y] * dst[i - y];
+        }
+    }
+    dst
+}
+
+fn merge_finish_record(src: &Vec3) -> Vec3 {
+    let mut tmp = [0.0; 3];
+    let mut dst = [1.0, 0.0, 0.0];
+    let mut val = src[0];
+
+    for i in 1..=2 {
+        let mut v2 = 0.0;
+        for y in 1..i {
+            v2 += dst[y] * src[i - y];
+        }
+
+        dst[i] = if val > 0.0 { -(v2 + src[i]) / val } else { 0.0 };
+        tmp[i] = dst[i];
+
+        for y in 1..i {
+            dst[y] += dst[i] * dst[i - y];
+        }
+
+        val *= 1.0 - dst[i] * dst[i];
+    }
+
+    finish_record(&mut tmp)
+}
+
+fn contrast_vectors(source1: &Vec3, source2: &Vec3) -> f64 {
+    let val = (source2[2] * source2[1] - source2[1]) / (1.0 - source2[2] * source2[2]);
+    let val1 = source1[0] * source1[0] + source1[1] * source1[1] + source1[2] * source1[2];
+    let val2 = source1[0] * source1[1] + source1[1] * source1[2];
+    let val3 = source1[0] * source1[2];
+    val1 + 2.0 * val * val2 + 2.0 * (-source2[1] * val - source2[2]) * val3
+}
+
+fn filter_records(best: &mut [Vec3; 8], exp: usize, records: &[Vec3]) {
+    for _ in 0..2 {
+        let mut counts = [0usize; 8];
+        let mut sums = [[0.0; 3]; 8];
+
+        for record in records {
+            let mut index = 0;
+            let mut value = 1.0e30;
+            for (i, vector) in best.iter().enumerate().take(exp) {
+                let tmp = contrast_vectors(vector, record);
+                if tmp < value {
+                    value = tmp;
+                    index = i;
+                }
+            }
+
+            counts[index] += 1;
+            let filtered = matrix_filter(record);
+            for i in 0..=2 {
+                sums[index][i] += filtered[i];
+            }
+        }
+
+        for (sum, &count) in sums.iter_mut().zip(&counts).take(exp) {
+            if count > 0 {
+                for value in sum.iter_mut() {
+                    *value /= count as f64;
+                }
+            }
+        }
+
+        for i in 0..exp {
+            best[i] = merge_finish_record(&sums[i]);
+        }
+    }
+}
+
+fn to_coef(value: f64) -> i16 {
+    let d = -value * 2048.0;
+    d.round().clamp(-32768.0, 32767.0) as i16
+}
+
+/// Computes the 8 predictor coefficient pairs best suited to the given samples.
+pub fn correlate_coefs(samples: &[i16]) -> Coefficients {
+    let mut records = Vec::with_capacity(samples.len().div_ceil(14) * 2);
+    let mut block = vec![0i16; ANALYSIS_BLOCK];
+    // [previous 14 samples, current 14 samples]
+    let mut history = [0i16; 28];
+
+    for chunk in samples.chunks(ANALYSIS_BLOCK) {
+        // partial blocks are padded with (up to a frame of) silence
+        let len = chunk.len();
+        block[..len].copy_from_slice(chunk);
+        block[len..(len + 14).min(ANALYSIS_BLOCK)].fill(0);
+
+        let mut i = 0;
+        while i < len {
+            history.copy_within(14.., 0);
+            history[14..].copy_from_slice(&block[i..i + 14]);
+            i += 14;
+
+            let mut vec1 = inner_product_merge(&history);
+            if vec1[0].abs() > 10.0 {
+                let mut mtx = outer_product_merge(&history);
+                if let Some(idxs) = analyze_ranges(&mut mtx) {
+                    bidirectional_filter(&mtx, &idxs, &mut vec1);
+                    if quadratic_merge(&mut vec1) {
+                        records.push(finish_record(&mut vec1));
+                    }
+                }
+            }
+        }
+    }
+
+    // nothing but (near) silence, any coefficients will do
+    if records.is_empty() {
+        return [0; 16];
+    }
+
+    let mut best = [[0.0; 3]; 8];
+
+    let mut vec1 = [1.0, 0.0, 0.0];
+    for record in &records {
+        let filtered = matrix_filter(record);
+        for y in 1..=2 {
+            vec1[y] += filtered[y];
+        }
+    }
+    for value in &mut vec1[1..] {
+        *value /= records.len() as f64;
+    }
+    best[0] = merge_finish_record(&vec1);
+
+    let mut exp = 1;
+    for w in 1..=3 {
+        let vec2 = [0.0, -1.0, 0.0];
+        for i in 0..exp {
+            for y in 0..=2 {
+                best[exp + i][y] = 0.01 * vec2[y] + best[i][y];
+            }
+        }
+        exp = 1 << w;
+        filter_records(&mut best, exp, &records);
+    }
+
+    let mut coefs = [0i16; 16];
+    for z in 0..8 {
+        coefs[z * 2] = to_coef(best[z][1]);
+        coefs[z * 2 + 1] = to_coef(best[z][2]);
+    }
+    coefs
+}
+
+// endregion
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::adpcm::{self, Decoder};
+
+    fn test_signal(len: usize) -> Vec<i16> {
+        // a couple of harmonics and a slow amplitude sweep, roughly like an instrument note
+        (0..len).map(|i| {
+            let t = i as f64 / 32000.0;
+            let env = 0.3 + 0.6 * (t * 1.5).min(1.0);
+            let v = (t * 440.0 * std::f64::consts::PI * 2.0).sin() * 0.6
+                + (t * 880.0 * std::f64::consts::PI * 2.0).sin() * 0.25
+                + (t * 1320.0 * std::f64::consts::PI * 2.0).sin() * 0.1;
+            (v * env * 30000.0) as i16
+        }).collect()
+    }
+
+    fn snr(reference: &[i16], test: &[i16]) -> f64 {
+        let signal: f64 = reference.iter().map(|&s| (s as f64).powi(2)).sum();
+        let noise: f64 = reference.iter().zip(test).map(|(&a, &b)| (a as f64 - b as f64).powi(2)).sum();
+        10.0 * (signal / noise).log10()
+    }
+
+    #[test]
+    fn decode_after_encode_snr() {
+        let samples = test_signal(32000);
+        let encoded = encode(&samples, None);
+        assert_eq!(encoded.data.len(), adpcm::samples_to_bytes(samples.len()));
+
+        let decoded = Decoder::from_info(&encoded.info).decode(&encoded.data, samples.len());
+        assert_eq!(decoded, encoded.decoded);
+
+        let snr = snr(&samples, &decoded);
+        assert!(snr > 30.0, "SNR too low: {:.2} dB", snr);
+    }
+
+    #[test]
+    fn silence() {
+        let encoded = encode(&[0i16; 100], None);
+        assert!(encoded.data.iter().all(|&b| b == 0));
+        assert!(encoded.decoded.iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn loop_context() {
+        let samples = test_signal(4000);
+        let loop_start = 1234;
+        let encoded = encode(&samples, Some(loop_start));
+
+        let frame = loop_start / SAMPLES_PER_FRAME * BYTES_PER_FRAME;
+        assert_eq!(encoded.info.loop_pred_scale, encoded.data[frame] as u16);
+        assert_eq!(encoded.info.loop_hist1, encoded.decoded[loop_start - 1]);
+        assert_eq!(encoded.info.loop_hist2, encoded.decoded[loop_start - 2]);
+        assert_eq!(encoded.info.pred_scale, encoded.data[0] as u16);
+
+        // resuming from the loop context reproduces the same audio
+        let resumed = Decoder::from_info_loop(&encoded.info)
+            .resume(&encoded.data[frame..], loop_start, 100);
+        assert_eq!(&resumed[..], &encoded.decoded[loop_start..loop_start + 100]);
+    }
+
+    #[test]
+    fn coefficients_are_stable() {
+        let coefs = correlate_coefs(&test_signal(16000));
+        // coef1 in (-2, 2), coef2 in (-1, 1) in 5.11 fixed point
+        for pair in coefs.chunks(2) {
+            assert!(pair[0].abs() < 4096 && pair[1].abs() <= 2048, "{:?}", pair);
+        }
+        assert!(coefs.iter().any(|&c| c != 0));
+    }
+}
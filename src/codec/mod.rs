@@ -0,0 +1,41 @@
+pub mod adpcm;
+pub mod adpcm_encoder;
+pub mod pcm;
+
+use crate::common::{SoundEncoding, AdpcmInfo};
+use std::io;
+
+/// Decodes a single (non-interleaved) channel of wave data to PCM16.
+///
+/// `adpcm` is only used for [`SoundEncoding::DspAdpcm`], where it's required.
+pub fn decode(encoding: SoundEncoding, data: &[u8], adpcm: Option<&AdpcmInfo>, count: usize) -> io::Result<Vec<i16>> {
+    Ok(match encoding {
+        SoundEncoding::SPcm8 => pcm::decode_pcm8(data, count),
+        SoundEncoding::SPcm16 => pcm::decode_pcm16(data, count),
+        SoundEncoding::DspAdpcm => match adpcm {
+            Some(info) => adpcm::Decoder::from_info(info).decode(data, count),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "missing ADPCM info"))
+        }
+    })
+}
+
+/// Number of bytes a channel of `samples` samples takes up in the given encoding.
+pub fn encoded_len(encoding: SoundEncoding, samples: usize) -> usize {
+    match encoding {
+        SoundEncoding::SPcm8 => samples,
+        SoundEncoding::SPcm16 => samples * 2,
+        SoundEncoding::DspAdpcm => adpcm::samples_to_bytes(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_adpcm_info() {
+        assert!(decode(SoundEncoding::DspAdpcm, &[0; 8], None, 14).is_err());
+        assert_eq!(decode(SoundEncoding::DspAdpcm, &[0; 8], Some(&AdpcmInfo::default()), 14).unwrap(), vec![0; 14]);
+        assert_eq!(decode(SoundEncoding::SPcm8, &[1, 2], None, 2).unwrap(), vec![0x100, 0x200]);
+    }
+}
@@ -0,0 +1,41 @@
+/// Signed 8-bit PCM, widened to 16 bits.
+pub fn decode_pcm8(data: &[u8], count: usize) -> Vec<i16> {
+    data.iter().take(count).map(|&s| (s as i8 as i16) << 8).collect()
+}
+
+/// Big-endian signed 16-bit PCM.
+pub fn decode_pcm16(data: &[u8], count: usize) -> Vec<i16> {
+    data.chunks_exact(2).take(count).map(|s| i16::from_be_bytes([s[0], s[1]])).collect()
+}
+
+pub fn encode_pcm8(samples: &[i16]) -> Vec<u8> {
+    samples.iter().map(|&s| (s >> 8) as i8 as u8).collect()
+}
+
+pub fn encode_pcm16(samples: &[i16]) -> Vec<u8> {
+    samples.iter().flat_map(|s| s.to_be_bytes().to_vec()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pcm8_sign_extension() {
+        assert_eq!(decode_pcm8(&[0x00, 0x7F, 0x80, 0xFF], 4), vec![0, 0x7F00, -0x8000, -0x100]);
+    }
+
+    #[test]
+    fn pcm16_round_trip() {
+        let samples = vec![0, 1, -1, i16::MAX, i16::MIN, 0x1234];
+        let bytes = encode_pcm16(&samples);
+        assert_eq!(&bytes[10..], &[0x12, 0x34]);
+        assert_eq!(decode_pcm16(&bytes, samples.len()), samples);
+    }
+
+    #[test]
+    fn count_truncates() {
+        assert_eq!(decode_pcm16(&[0, 1, 0, 2, 0, 3], 2), vec![1, 2]);
+        assert_eq!(decode_pcm8(&[1, 2, 3], 1), vec![0x100]);
+    }
+}
@@ -1,31 +1,90 @@
 use binread::{BinRead, BinReaderExt, FilePtr8, BinResult, ReadOptions, FilePtr};
 
-use binread::io::{Cursor, Read, Seek};
+use binread::io::{Cursor, Read, Seek, SeekFrom};
 use std::io;
 use binread::file_ptr::IntoSeekFrom;
+use std::cell::OnceCell;
+use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 use binwrite::{BinWrite, WriterOption};
 use std::convert::TryInto;
+use super::{Span, Spanned};
 
-struct Relative<T: BinRead>(T);
+/// Whether pointers follow their target while parsing (`Eager`, like `FilePtr`), or only record
+/// where it is and read it the first time it's resolved (`Lazy`).
+pub trait Mode: 'static {
+    const LAZY: bool;
+}
 
-impl<BR: BinRead> BinRead for Relative<BR> {
-    type Args = (u64, BR::Args);
+pub enum Eager {}
+pub enum Lazy {}
 
-    fn read_options<R: Read + Seek>(reader: &mut R, ro: &ReadOptions, args: Self::Args) -> BinResult<Self> {
-        let (offset, args) = args;
-        let mut temp_options = ro.clone();
-        temp_options.offset = offset;
+impl Mode for Eager {
+    const LAZY: bool = false;
+}
+
+impl Mode for Lazy {
+    const LAZY: bool = true;
+}
+
+/// The target of a pointer, along with what it has to be read with.
+pub(crate) struct Target<BR: BinRead> {
+    // what the pointer is relative to
+    offset: u64,
+    endian: binread::Endian,
+    args: BR::Args,
+    value: OnceCell<BR>,
+}
 
-        Ok(Relative(BR::read_options(reader, &temp_options, args)?))
+impl<BR: BinRead> Target<BR> {
+    pub(crate) fn new(options: &ReadOptions, args: BR::Args) -> Target<BR> {
+        Target { offset: options.offset, endian: options.endian, args, value: OnceCell::new() }
     }
 
-    fn after_parse<R: Read + Seek>(&mut self, reader: &mut R, ro: &ReadOptions, args: Self::Args) -> BinResult<()> {
-        let (offset, args) = args;
-        let mut temp_options = ro.clone();
-        temp_options.offset = offset;
+    /// Reads the value `ptr` bytes after `base`, following its own pointers, then goes back to
+    /// where the reader was.
+    pub(crate) fn read<R: Read + Seek, Ptr: IntoSeekFrom>(reader: &mut R, base: u64, ptr: Ptr, options: &ReadOptions, args: BR::Args) -> BinResult<BR> {
+        let before = reader.seek(SeekFrom::Current(0))?;
+        reader.seek(SeekFrom::Start(base))?;
+        reader.seek(ptr.into_seek_from())?;
+
+        let mut value = BR::read_options(reader, options, args)?;
+        value.after_parse(reader, options, args)?;
 
-        self.0.after_parse(reader, &temp_options, args)
+        reader.seek(SeekFrom::Start(before))?;
+        Ok(value)
+    }
+
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub(crate) fn set(&mut self, value: BR) {
+        self.value = OnceCell::from(value);
+    }
+
+    pub(crate) fn get(&self) -> Option<&BR> {
+        self.value.get()
+    }
+
+    pub(crate) fn get_mut(&mut self) -> Option<&mut BR> {
+        self.value.get_mut()
+    }
+
+    pub(crate) fn into_inner(self) -> Option<BR> {
+        self.value.into_inner()
+    }
+
+    /// The cached value, or the value read from `reader` the first time.
+    pub(crate) fn resolve<R: Read + Seek, Ptr: IntoSeekFrom>(&self, reader: &mut R, base: u64, ptr: Ptr) -> BinResult<&BR> {
+        if let Some(value) = self.value.get() {
+            return Ok(value);
+        }
+        let mut options = ReadOptions::default();
+        options.endian = self.endian;
+        options.offset = self.offset;
+        let value = Self::read(reader, base, ptr, &options, self.args)?;
+        Ok(self.value.get_or_init(|| value))
     }
 }
 
@@ -34,51 +93,57 @@ impl<BR: BinRead> BinRead for Relative<BR> {
 /// This wrapper will always read from an offset from 0, but will pass on any existing offset to
 /// the type it is wrapping.
 ///
+/// In `Lazy` mode the target isn't read until [`AbsPtr::resolve`] is called.
+///
 /// NOTE: This integrates with BinWrite, but does not serialize the value on its own.
 /// You need to add the value to a pool manually when needed.
 ///
 /// TODO: example
 ///
 /// See [`binread::FilePtr`](binread::FilePtr) for more information.
-pub struct AbsPtr<Ptr: IntoSeekFrom, T: BinRead>(FilePtr<Ptr, Relative<T>>);
+pub struct AbsPtr<Ptr: IntoSeekFrom, T: BinRead, M: Mode = Eager> {
+    ptr: Ptr,
+    // of the pointer itself
+    pos: u64,
+    target: Target<T>,
+    mode: PhantomData<M>,
+}
 
 /// Type alias for 8-bit absolute pointers
-pub type AbsPtr8<T> = AbsPtr<u8, T>;
+pub type AbsPtr8<T, M = Eager> = AbsPtr<u8, T, M>;
 /// Type alias for 16-bit absolute pointers
-pub type AbsPtr16<T> = AbsPtr<u16, T>;
+pub type AbsPtr16<T, M = Eager> = AbsPtr<u16, T, M>;
 /// Type alias for 32-bit absolute pointers
-pub type AbsPtr32<T> = AbsPtr<u32, T>;
+pub type AbsPtr32<T, M = Eager> = AbsPtr<u32, T, M>;
 /// Type alias for 64-bit absolute pointers
-pub type AbsPtr64<T> = AbsPtr<u64, T>;
+pub type AbsPtr64<T, M = Eager> = AbsPtr<u64, T, M>;
 /// Type alias for 128-bit absolute pointers
-pub type AbsPtr128<T> = AbsPtr<u128, T>;
+pub type AbsPtr128<T, M = Eager> = AbsPtr<u128, T, M>;
 
-impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead> BinRead for AbsPtr<Ptr, BR> {
+impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead, M: Mode> BinRead for AbsPtr<Ptr, BR, M> {
     type Args = BR::Args;
 
     fn read_options<R: Read + Seek>(reader: &mut R, ro: &ReadOptions, args: Self::Args) -> BinResult<Self> {
-        let mut temp_options = ro.clone();
-        temp_options.offset = 0;
-
-        Ok(AbsPtr(FilePtr::read_options(reader, &temp_options, (ro.offset, args))?))
+        let pos = reader.seek(SeekFrom::Current(0))?;
+        Ok(AbsPtr { ptr: Ptr::read_options(reader, ro, ())?, pos, target: Target::new(ro, args), mode: PhantomData })
     }
 
     fn after_parse<R: Read + Seek>(&mut self, reader: &mut R, ro: &ReadOptions, args: Self::Args) -> BinResult<()> {
-        let mut temp_options = ro.clone();
-        temp_options.offset = 0;
-
-        self.0.after_parse(reader, &temp_options, (ro.offset, args))
+        if !M::LAZY {
+            self.target.set(Target::read(reader, 0, self.ptr, ro, args)?);
+        }
+        Ok(())
     }
 }
 
-impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead> AbsPtr<Ptr, BR> {
+impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead, M: Mode> AbsPtr<Ptr, BR, M> {
     /// Consume the pointer and return the inner type
     ///
     /// # Panics
     ///
     /// Will panic if the file pointer hasn't been properly postprocessed
     pub fn into_inner(self) -> BR {
-        self.0.into_inner().0
+        self.target.into_inner().expect("Deref'd AbsPtr before reading (make sure to use AbsPtr::after_parse first)")
     }
 
     /// Custom parser designed for use with the `parse_with` attribute ([example](binread::attribute#custom-parsers))
@@ -88,33 +153,50 @@ impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead> AbsPtr<Ptr, BR> {
         ro: &ReadOptions,
         args: BR::Args
     ) -> BinResult<BR> {
-        let mut temp_options = ro.clone();
-        temp_options.offset = 0;
-
-        Ok(
-            AbsPtr::<Ptr, BR>(
-                FilePtr::<Ptr, _>::parse(reader, &temp_options, (ro.offset, args))?
-            ).into_inner()
-        )
+        let ptr = Ptr::read_options(reader, ro, ())?;
+        Target::read(reader, 0, ptr, ro, args)
     }
 
     pub fn ptr(&self) -> Ptr {
-        self.0.ptr
+        self.ptr
     }
     pub fn set_ptr(&mut self, new: Ptr) {
-        self.0.ptr = new;
+        self.ptr = new;
+    }
+}
+
+/// The pointer itself, not its target.
+impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead, M: Mode> Spanned for AbsPtr<Ptr, BR, M> {
+    fn span(&self) -> Span {
+        Span { offset: self.pos, len: std::mem::size_of::<Ptr>() as u64 }
+    }
+}
+
+impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead> AbsPtr<Ptr, BR, Lazy> {
+    pub fn is_resolved(&self) -> bool {
+        self.target.get().is_some()
+    }
+
+    /// Reads the target the first time, and returns the same value afterwards.
+    pub fn resolve<R: Read + Seek>(&self, reader: &mut R) -> BinResult<&BR> {
+        self.target.resolve(reader, 0, self.ptr)
+    }
+
+    /// Resolves the pointer against the whole file it was read from.
+    pub fn get(&self, data: &[u8]) -> BinResult<&BR> {
+        self.resolve(&mut Cursor::new(data))
     }
 }
 
 use crate::common::binwrite_utils::pool::BinWriteLength;
 
-impl<Ptr, BR, E> AbsPtr<Ptr, BR> where
+impl<Ptr, BR, E, M: Mode> AbsPtr<Ptr, BR, M> where
     Ptr: IntoSeekFrom,
     BR: BinRead + BinWriteLength<dyn io::Write>,
     usize: TryInto<Ptr, Error = E>
 {
     pub fn add_to_pool<'a>(&'a mut self, pool: &mut super::Pool<'a>) -> Result<(), E> {
-        self.0.ptr = pool.push(&mut self.0.value.as_mut().unwrap().0).try_into()?;
+        self.ptr = pool.push(self.target.get_mut().unwrap()).try_into()?;
         Ok(())
     }
 }
@@ -123,17 +205,17 @@ impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead> Deref for AbsPtr<Ptr,
     type Target = BR;
 
     fn deref(&self) -> &Self::Target {
-        &self.0.deref().0
+        self.target.get().expect("Deref'd AbsPtr before reading (make sure to use AbsPtr::after_parse first)")
     }
 }
 
 impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead> DerefMut for AbsPtr<Ptr, BR> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0.deref_mut().0
+        self.target.get_mut().expect("Deref'd AbsPtr before reading (make sure to use AbsPtr::after_parse first)")
     }
 }
 
-impl<Ptr: BinRead<Args = ()> + BinWrite + IntoSeekFrom, BR: BinRead> BinWrite for AbsPtr<Ptr, BR> {
+impl<Ptr: BinRead<Args = ()> + BinWrite + IntoSeekFrom, BR: BinRead, M: Mode> BinWrite for AbsPtr<Ptr, BR, M> {
     fn write_options<W: io::Write>(&self, writer: &mut W, options: &WriterOption) -> io::Result<()> {
         self.ptr().write_options(writer, options)
 
@@ -205,11 +287,11 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "Deref'd FilePtr before reading (make sure to use FilePtr::after_parse first)")] // TODO
+    //#[should_panic(expected = "Deref'd FilePtr before reading (make sure to use FilePtr::after_parse first)")]
     fn nested_file_ptr() {
         let test: Wrapper<FilePtr8<FilePtr8<u8>>> = Cursor::new([0x01, 0x02, 0xFF]).read_be().unwrap();
 
-        // FAILS: after_parse was never called.
+        // binread 1.3 calls after_parse on the inner pointer too.
         assert_eq!(**test.0, 0xFF);
     }
 
@@ -265,4 +347,34 @@ mod tests {
          let test: (u32, CurPosTest) = Cursor::new([0x00u8; 16]).read_be().unwrap();
          verify_curpos(test.1, 4);
      }
-}
\ No newline at end of file
+
+    #[derive(BinRead)]
+    struct LazyBlock {
+        header: crate::common::BlockHeader,
+        values: crate::common::r32<crate::common::Table<crate::common::Reference<u32, Lazy>>, Lazy>,
+    }
+
+    #[test]
+    fn lazy_resolve() {
+        // block at 0x10, its body at 0x18: a pointer to a table of two references
+        let mut data = vec![0; 0x18];
+        data[0x10..0x14].copy_from_slice(b"TEST");
+        for value in &[0x4u32, 2, 0x0100_0000, 0x18, 0, 0x34, 0x1234, 0xABCD] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+
+        let block: crate::common::BlockPtr<LazyBlock, Lazy> = Cursor::new(&[0, 0, 0, 0x10, 0, 0, 0, 0x40]).read_be().unwrap();
+        assert_eq!(block.len, 0x40);
+        assert!(!block.block.is_resolved());
+
+        let table = block.block.get(&data).unwrap().values.get(&data).unwrap();
+        assert_eq!(table.0.len(), 2);
+        assert!(!table.0[0].is_resolved());
+        assert_eq!(*table.0[0].get(&data).unwrap(), 0x1234); // relative, from 0x18 + 0x18
+        assert_eq!(*table.0[1].get(&data).unwrap(), 0xABCD); // absolute
+        assert!(table.0[0].is_resolved());
+
+        // cached, so a different buffer doesn't change anything
+        assert_eq!(*table.0[0].get(&[]).unwrap(), 0x1234);
+    }
+}
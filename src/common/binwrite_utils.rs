@@ -127,13 +127,15 @@ pub(crate) mod pool {
 
     impl<'a> BinWrite for PoolEntry<'a> {
         fn write_options<W: Write>(&self, writer: &mut W, options: &WriterOption) -> Result<()> {
-            let writer = writer.into();
-            self.write_options(writer, options)
+            // the entry writes to a `dyn Write + 'static`, which `writer` might not be
+            let mut buffer = Vec::new();
+            BinWriteLength::write_options(*self, &mut buffer as &mut dyn Write, options)?;
+            writer.write_all(&buffer)
         }
     }
 
     // TODO: Is there a better way to handle this?
-    #[derive(BinWrite)]
+    #[derive(BinWrite, Default)]
     pub struct Pool<'a> {
         contents: Vec<PoolEntry<'a>>,
         #[binwrite(ignore)]
@@ -142,10 +144,7 @@ pub(crate) mod pool {
 
     impl<'a> Pool<'a> {
         pub fn new() -> Pool<'a> {
-            Pool {
-                contents: Vec::new(),
-                cur_len: 0
-            }
+            Pool::default()
         }
 
         /// Clears pool
@@ -187,22 +186,41 @@ pub use pool::Pool;
 mod null_string {
     use binread::{BinRead, NullString};
     use binwrite::BinWrite;
+    use crate::common::binread_utils::CurPos;
+    use crate::common::{Span, Spanned};
     /// Wrapper around binread::NullString to add BinWrite support
-    #[derive(BinRead, BinWrite, Clone, PartialEq, Default)]
+    #[derive(BinRead, BinWrite, Clone, Default)]
     pub struct WriteNullString {
+        #[br(map = |pos: CurPos| pos.0)]
+        #[binwrite(ignore)]
+        offset: u64,
         #[binwrite(cstr, preprocessor(NullString::to_string))]
         inner: NullString
     }
 
+    // where the strings were read from doesn't matter
+    impl PartialEq for WriteNullString {
+        fn eq(&self, other: &Self) -> bool {
+            self.inner == other.inner
+        }
+    }
+
+    /// The string, including its terminator.
+    impl Spanned for WriteNullString {
+        fn span(&self) -> Span {
+            Span { offset: self.offset, len: self.inner.0.len() as u64 + 1 }
+        }
+    }
+
     impl super::BinLength for WriteNullString {
         fn serialized_length(&self) -> usize {
             self.inner.0.len() + 1 // add one for null byte
         }
     }
 
-    impl ToString for WriteNullString {
-        fn to_string(&self) -> String {
-            self.inner.to_string()
+    impl std::fmt::Display for WriteNullString {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str(&self.inner.to_string())
         }
     }
 
@@ -227,7 +245,7 @@ mod null_string {
 
         #[test]
         fn serialize_test() {
-            let test = WriteNullString { inner: NullString(Vec::from(&b"Test!"[..])) };
+            let test = WriteNullString { offset: 0, inner: NullString(Vec::from(&b"Test!"[..])) };
 
             let expected = Vec::from(&b"Test!\0"[..]);
 
@@ -259,9 +277,12 @@ mod null_string {
 
 pub use null_string::WriteNullString as NullString;
 mod file_ptr {
-    use binread::{BinRead, FilePtr, ReadOptions, BinResult};
+    use binread::{BinRead, ReadOptions, BinResult};
     use binread::file_ptr::IntoSeekFrom;
-    use binread::io::{Read, Seek};
+    use binread::io::{Cursor, Read, Seek};
+    use crate::common::binread_utils::{Mode, Eager, Lazy, Target};
+    use crate::common::{Span, Spanned};
+    use std::marker::PhantomData;
 
     use binwrite::{BinWrite, WriterOption};
     use super::Pool;
@@ -277,44 +298,58 @@ mod file_ptr {
     /// NOTE: This does not serialize the value on its own.
     /// You need to add the value to a pool manually when needed.
     ///
+    /// In `Lazy` mode the target isn't read until [`RelPtr::resolve`] is called.
+    ///
     /// TODO: example
     ///
     /// See [`binread::FilePtr`](binread::FilePtr) for more information.
-    pub struct RelPtr<Ptr: IntoSeekFrom, T: BinRead>(FilePtr<Ptr, T>);
+    pub struct RelPtr<Ptr: IntoSeekFrom, T: BinRead, M: Mode = Eager> {
+        ptr: Ptr,
+        // of the pointer itself
+        pos: u64,
+        target: Target<T>,
+        mode: PhantomData<M>,
+    }
 
     /// Type alias for 8-bit absolute pointers
-    pub type RelPtr8<T> = RelPtr<u8, T>;
+    pub type RelPtr8<T, M = Eager> = RelPtr<u8, T, M>;
     /// Type alias for 16-bit absolute pointers
-    pub type RelPtr16<T> = RelPtr<u16, T>;
+    pub type RelPtr16<T, M = Eager> = RelPtr<u16, T, M>;
     /// Type alias for 32-bit absolute pointers
-    pub type RelPtr32<T> = RelPtr<u32, T>;
+    pub type RelPtr32<T, M = Eager> = RelPtr<u32, T, M>;
     /// Type alias for 64-bit absolute pointers
-    pub type RelPtr64<T> = RelPtr<u64, T>;
+    pub type RelPtr64<T, M = Eager> = RelPtr<u64, T, M>;
     /// Type alias for 128-bit absolute pointers
-    pub type RelPtr128<T> = RelPtr<u128, T>;
+    pub type RelPtr128<T, M = Eager> = RelPtr<u128, T, M>;
 
-    impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead> BinRead for RelPtr<Ptr, BR> {
+    impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead, M: Mode> BinRead for RelPtr<Ptr, BR, M> {
         type Args = BR::Args;
 
         fn read_options<R: Read + Seek>(reader: &mut R, ro: &ReadOptions, args: Self::Args) -> BinResult<Self> {
-            Ok(RelPtr(FilePtr::read_options(reader, ro, args)?))
+            let pos = reader.seek(io::SeekFrom::Current(0))?;
+            Ok(RelPtr { ptr: Ptr::read_options(reader, ro, ())?, pos, target: Target::new(ro, args), mode: PhantomData })
         }
 
         fn after_parse<R: Read + Seek>(&mut self, reader: &mut R, ro: &ReadOptions, args: Self::Args) -> BinResult<()> {
-            self.0.after_parse(reader, ro, args)?;
+            if M::LAZY {
+                return Ok(());
+            }
+            let mut value: BR = Target::read(reader, ro.offset, self.ptr, ro, args)?;
             // TODO: remove when binread bug is fixed
-            self.0.value.as_mut().unwrap().after_parse(reader, ro, args)
+            value.after_parse(reader, ro, args)?;
+            self.target.set(value);
+            Ok(())
         }
     }
 
-    impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead> RelPtr<Ptr, BR> {
+    impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead, M: Mode> RelPtr<Ptr, BR, M> {
         /// Consume the pointer and return the inner type
         ///
         /// # Panics
         ///
         /// Will panic if the file pointer hasn't been properly postprocessed
         pub fn into_inner(self) -> BR {
-            self.0.into_inner()
+            self.target.into_inner().expect("Deref'd RelPtr before reading (make sure to use RelPtr::after_parse first)")
         }
 
         /// Custom parser designed for use with the `parse_with` attribute ([example](binread::attribute#custom-parsers))
@@ -324,28 +359,48 @@ mod file_ptr {
             ro: &ReadOptions,
             args: BR::Args
         ) -> BinResult<BR> {
-            Ok(
-                RelPtr::<Ptr, BR>(
-                    FilePtr::<Ptr, _>::parse(reader, ro, args)?
-                ).into_inner()
-            )
+            let ptr = Ptr::read_options(reader, ro, ())?;
+            Target::read(reader, ro.offset, ptr, ro, args)
         }
 
         pub fn ptr(&self) -> Ptr {
-            self.0.ptr
+            self.ptr
         }
         pub fn set_ptr(&mut self, new: Ptr) {
-            self.0.ptr = new;
+            self.ptr = new;
+        }
+    }
+
+    /// The pointer itself, not its target.
+    impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead, M: Mode> Spanned for RelPtr<Ptr, BR, M> {
+        fn span(&self) -> Span {
+            Span { offset: self.pos, len: std::mem::size_of::<Ptr>() as u64 }
+        }
+    }
+
+    impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead> RelPtr<Ptr, BR, Lazy> {
+        pub fn is_resolved(&self) -> bool {
+            self.target.get().is_some()
+        }
+
+        /// Reads the target the first time, and returns the same value afterwards.
+        pub fn resolve<R: Read + Seek>(&self, reader: &mut R) -> BinResult<&BR> {
+            self.target.resolve(reader, self.target.offset(), self.ptr)
+        }
+
+        /// Resolves the pointer against the whole file it was read from.
+        pub fn get(&self, data: &[u8]) -> BinResult<&BR> {
+            self.resolve(&mut Cursor::new(data))
         }
     }
 
-    impl<Ptr, BR, E> RelPtr<Ptr, BR> where
+    impl<Ptr, BR, E, M: Mode> RelPtr<Ptr, BR, M> where
         Ptr: IntoSeekFrom,
         BR: BinRead + BinWriteLength<dyn io::Write>,
         usize: TryInto<Ptr, Error = E>
     {
         pub fn add_to_pool<'a>(&'a mut self, pool: &mut Pool<'a>) -> Result<(), E> {
-            self.0.ptr = pool.push(self.0.value.as_mut().unwrap()).try_into()?;
+            self.ptr = pool.push(self.target.get_mut().unwrap()).try_into()?;
             Ok(())
         }
     }
@@ -354,17 +409,17 @@ mod file_ptr {
         type Target = BR;
 
         fn deref(&self) -> &Self::Target {
-            self.0.deref()
+            self.target.get().expect("Deref'd RelPtr before reading (make sure to use RelPtr::after_parse first)")
         }
     }
 
     impl<Ptr: BinRead<Args = ()> + IntoSeekFrom, BR: BinRead> DerefMut for RelPtr<Ptr, BR> {
         fn deref_mut(&mut self) -> &mut Self::Target {
-            self.0.deref_mut()
+            self.target.get_mut().expect("Deref'd RelPtr before reading (make sure to use RelPtr::after_parse first)")
         }
     }
 
-    impl<Ptr: BinRead<Args = ()> + BinWrite + IntoSeekFrom, BR: BinRead> BinWrite for RelPtr<Ptr, BR> {
+    impl<Ptr: BinRead<Args = ()> + BinWrite + IntoSeekFrom, BR: BinRead, M: Mode> BinWrite for RelPtr<Ptr, BR, M> {
         fn write_options<W: io::Write>(&self, writer: &mut W, options: &WriterOption) -> io::Result<()> {
             self.ptr().write_options(writer, options)
 
@@ -0,0 +1,141 @@
+//! Writing the container every format shares: a `FileHeader`, the table of block offsets and
+//! sizes, then the blocks, each starting with a `BlockHeader` and padded to 0x20.
+
+use super::align_up;
+use std::io::{self, Write};
+
+const ALIGN: usize = 0x20;
+
+/// Lays out the blocks of a file from the length of their bodies, so that formats only have to
+/// write what comes after each block header.
+///
+/// Call [`ContainerWriter::write_header`], then [`ContainerWriter::write_block`] for every
+/// block in the order they were added.
+pub struct ContainerWriter {
+    magic: [u8; 4],
+    version: u16,
+    // None for slots that are in the block table, but left empty
+    blocks: Vec<Option<BlockLayout>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BlockLayout {
+    magic: [u8; 4],
+    body_len: usize,
+}
+
+impl ContainerWriter {
+    pub fn new(magic: [u8; 4], version: u16) -> ContainerWriter {
+        ContainerWriter { magic, version, blocks: Vec::new() }
+    }
+
+    /// Adds a block with a body of `body_len` bytes, not counting the block header or padding.
+    /// Returns the index to write it with.
+    pub fn add_block(&mut self, magic: [u8; 4], body_len: usize) -> usize {
+        self.blocks.push(Some(BlockLayout { magic, body_len }));
+        self.blocks.len() - 1
+    }
+
+    /// Adds a zeroed entry to the block table, like the ADPC slot of PCM streams.
+    /// It isn't counted in the header's block count.
+    pub fn reserve_block(&mut self) -> usize {
+        self.blocks.push(None);
+        self.blocks.len() - 1
+    }
+
+    pub fn header_len(&self) -> usize {
+        align_up(0x10 + self.blocks.len() * 8, ALIGN)
+    }
+
+    /// Size of a block including its header and padding, 0 for reserved slots.
+    pub fn block_size(&self, index: usize) -> usize {
+        match self.blocks.get(index) {
+            Some(Some(block)) => align_up(8 + block.body_len, ALIGN),
+            _ => 0
+        }
+    }
+
+    /// Offset of a block from the start of the file, 0 for reserved slots.
+    pub fn block_offset(&self, index: usize) -> usize {
+        match self.blocks.get(index) {
+            Some(Some(_)) => self.header_len() + (0..index).map(|idx| self.block_size(idx)).sum::<usize>(),
+            _ => 0
+        }
+    }
+
+    pub fn file_len(&self) -> usize {
+        self.header_len() + (0..self.blocks.len()).map(|idx| self.block_size(idx)).sum::<usize>()
+    }
+
+    pub fn write_header<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let block_count = self.blocks.iter().filter(|block| block.is_some()).count();
+
+        writer.write_all(&self.magic)?;
+        writer.write_all(&0xFEFFu16.to_be_bytes())?;
+        writer.write_all(&self.version.to_be_bytes())?;
+        writer.write_all(&(self.file_len() as u32).to_be_bytes())?;
+        writer.write_all(&(self.header_len() as u16).to_be_bytes())?;
+        writer.write_all(&(block_count as u16).to_be_bytes())?;
+        for idx in 0..self.blocks.len() {
+            writer.write_all(&(self.block_offset(idx) as u32).to_be_bytes())?;
+            writer.write_all(&(self.block_size(idx) as u32).to_be_bytes())?;
+        }
+        writer.write_all(&vec![0; self.header_len() - 0x10 - self.blocks.len() * 8])
+    }
+
+    /// Writes the block header, then the body written by `body`, then the padding.
+    /// The body is buffered first, so nothing is written if it's longer than the length the
+    /// block was added with.
+    pub fn write_block<W, F>(&self, writer: &mut W, index: usize, body: F) -> io::Result<()>
+        where W: Write, F: FnOnce(&mut Vec<u8>) -> io::Result<()>
+    {
+        let block = match self.blocks.get(index) {
+            Some(Some(block)) => *block,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "no such block"))
+        };
+        let size = self.block_size(index);
+
+        let mut buf = Vec::with_capacity(size - 8);
+        body(&mut buf)?;
+        if buf.len() > block.body_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "block body is longer than its layout"));
+        }
+        buf.resize(size - 8, 0);
+
+        writer.write_all(&block.magic)?;
+        writer.write_all(&(size as u32).to_be_bytes())?;
+        writer.write_all(&buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout() {
+        let mut container = ContainerWriter::new(*b"RTST", 0x0102);
+        let first = container.add_block(*b"HEAD", 0x19);
+        let empty = container.reserve_block();
+        let last = container.add_block(*b"DATA", 0);
+        assert_eq!(container.header_len(), 0x40);
+        assert_eq!((container.block_offset(first), container.block_size(first)), (0x40, 0x40));
+        assert_eq!((container.block_offset(empty), container.block_size(empty)), (0, 0));
+        assert_eq!((container.block_offset(last), container.block_size(last)), (0x80, 0x20));
+
+        let mut out = Vec::new();
+        container.write_header(&mut out).unwrap();
+        container.write_block(&mut out, first, |body| body.write_all(&[0xAA; 0x19])).unwrap();
+        container.write_block(&mut out, last, |_| Ok(())).unwrap();
+        assert_eq!(out.len(), container.file_len());
+        assert_eq!(&out[..0x10], &[b'R', b'T', b'S', b'T', 0xFE, 0xFF, 0x01, 0x02, 0, 0, 0, 0xA0, 0, 0x40, 0, 2]);
+        assert_eq!(&out[0x10..0x28], &[0, 0, 0, 0x40, 0, 0, 0, 0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x80, 0, 0, 0, 0x20]);
+        assert_eq!(&out[0x40..0x48], b"HEAD\0\0\0\x40");
+        assert_eq!((out[0x48 + 0x18], out[0x48 + 0x19]), (0xAA, 0));
+        assert_eq!(&out[0x80..0x88], b"DATA\0\0\0\x20");
+
+        assert!(container.write_block(&mut out, last, |body| body.write_all(&[1])).is_err());
+        assert_eq!(out.len(), container.file_len());
+        assert!(container.write_block(&mut out, empty, |_| Ok(())).is_err());
+    }
+}
@@ -2,22 +2,27 @@
 
 pub mod binread_utils;
 pub mod binwrite_utils;
+pub mod container;
 
 // TODO: NullString
 pub use binwrite_utils::{Pool, NullString};
+pub use container::ContainerWriter;
+pub use binread_utils::{Mode, Eager, Lazy};
+use binread_utils::CurPos;
 use std::marker::PhantomData;
 use std::convert::TryFrom;
 
 use binread::{BinRead, BinReaderExt, FilePtr32, BinResult, ReadOptions};
 use binread::io::{Read, Seek, SeekFrom};
+use std::io::{self, Write};
 use std::any::Any;
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, Range};
 
 #[allow(non_camel_case_types)]
-pub type r32<T> = binwrite_utils::RelPtr32<T>;
+pub type r32<T, M = Eager> = binwrite_utils::RelPtr32<T, M>;
 
 #[allow(non_camel_case_types)]
-pub type a32<T> = binread_utils::AbsPtr32<T>;
+pub type a32<T, M = Eager> = binread_utils::AbsPtr32<T, M>;
 
 #[allow(non_camel_case_types)]
 pub type s32 = i32;
@@ -27,16 +32,30 @@ pub type s32 = i32;
 
 // TODO: pass args
 #[derive(BinRead)]
-pub struct BlockPtr<BR: BinRead<Args=()>> {
+pub struct BlockPtr<BR: BinRead<Args=()>, M: Mode = Eager> {
     #[br(restore_position)]
     offset: u32,
     // the idea is that this makes r32 act the way we want
     // TODO: may need to do this somewhere else
     #[br(deref_now, offset = offset as u64 + 8)]
-    pub block: a32<BR>,
+    pub block: a32<BR, M>,
     pub len: u32
 }
 
+impl<BR: BinRead<Args=()>> BlockPtr<BR> {
+    /// Position of the block header, references in the block are relative to 8 bytes after it.
+    pub fn offset(&self) -> u64 {
+        self.offset as u64
+    }
+}
+
+/// The whole block, as given by the block table.
+impl<BR: BinRead<Args=()>, M: Mode> Spanned for BlockPtr<BR, M> {
+    fn span(&self) -> Span {
+        Span { offset: self.offset as u64, len: self.len as u64 }
+    }
+}
+
 #[derive(BinRead, PartialEq, Debug)]
 #[br(big)]
 #[repr(u16)]
@@ -47,6 +66,7 @@ pub enum Endian {
 
 #[derive(BinRead)]
 pub struct FileHeader {
+    span_start: CurPos,
     pub magic: [u8; 4],
     pub endian: Endian, // 0xFEFF or 0xFFEE
     // TODO: does endianness apply?
@@ -57,6 +77,15 @@ pub struct FileHeader {
     pub header_size: u16,
     #[br(is_big = endian == Endian::Big)]
     pub block_count: u16,
+    #[br(args(span_start.0))]
+    span: Span
+}
+
+/// The header itself, without the block table that follows it.
+impl Spanned for FileHeader {
+    fn span(&self) -> Span {
+        self.span
+    }
 }
 
 // Will NOT downcast properly to files with a32 references
@@ -69,32 +98,188 @@ pub struct GenericFile {
 
 #[derive(BinRead)]
 pub struct BlockHeader {
+    span_start: CurPos,
     pub magic: [u8; 4],
     pub size: u32,
+    #[br(args(span_start.0))]
+    span: Span
+}
+
+impl Spanned for BlockHeader {
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+/// Reads the rest of a block as raw bytes, for use with `parse_with`. `used` is how much of the
+/// block (header included) comes before the field, a block too small to hold it is an error.
+pub fn read_block_rest<R: Read + Seek>(reader: &mut R, ro: &ReadOptions, (size, used): (u32, u32)) -> BinResult<Vec<u8>> {
+    let len = size.checked_sub(used).ok_or_else(|| binread::Error::AssertFail {
+        pos: reader.seek(SeekFrom::Current(0)).unwrap_or(0) as usize,
+        message: format!("block size 0x{:x} is smaller than 0x{:x}", size, used),
+    })?;
+    let mut options = *ro;
+    options.count = Some(len as usize);
+    Vec::read_options(reader, &options, ())
+}
+
+/// Where the rest of a block is, for use with `parse_with`, like `read_block_rest` but without
+/// reading it.
+pub fn block_rest_range<R: Read + Seek>(reader: &mut R, _: &ReadOptions, (size, used): (u32, u32)) -> BinResult<Range<u64>> {
+    let start = reader.seek(SeekFrom::Current(0))?;
+    let end = start + size.checked_sub(used).ok_or_else(|| binread::Error::AssertFail {
+        pos: start as usize,
+        message: format!("block size 0x{:x} is smaller than 0x{:x}", size, used),
+    })? as u64;
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(start))?;
+    if end > len {
+        return Err(binread::Error::AssertFail { pos: start as usize, message: "block extends past the end of the file".into() });
+    }
+    Ok(start..end)
 }
 
 // Will NOT downcast properly to blocks with a32 references
 #[derive(BinRead)]
 pub struct GenericBlock {
     pub header: BlockHeader,
-    #[br(count = header.size)]
-    pub body: Vec<u8>
+    // the size includes the header, the body is left where it is
+    #[br(args(header.size, 8), parse_with = block_rest_range)]
+    pub body: Range<u64>
+}
+
+impl GenericBlock {
+    /// The body of the block, out of the bytes the file was read from.
+    pub fn body<'a>(&self, file: &'a [u8]) -> Option<&'a [u8]> {
+        file.get(self.body.start as usize..self.body.end as usize)
+    }
+}
+
+/// Borrowed counterpart to `GenericFile`, the block bodies point into the original buffer
+/// (which can be a memory map) instead of being copied.
+pub struct RawFile<'a> {
+    pub magic: [u8; 4],
+    pub endian: Endian,
+    pub version: u16,
+    pub blocks: Vec<RawBlock<'a>>,
+}
+
+pub struct RawBlock<'a> {
+    pub magic: [u8; 4],
+    /// Offset of the block header from the start of the file.
+    pub offset: usize,
+    /// Everything after the block header, as long as the size in the block table says.
+    pub body: &'a [u8],
+}
+
+impl<'a> RawFile<'a> {
+    pub fn parse(data: &'a [u8]) -> io::Result<RawFile<'a>> {
+        fn invalid(msg: &str) -> io::Error {
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        }
+
+        let header = data.get(..0x10).ok_or_else(|| invalid("truncated file header"))?;
+        let endian = match [header[4], header[5]] {
+            [0xFE, 0xFF] => Endian::Big,
+            [0xFF, 0xFE] => Endian::Little,
+            _ => return Err(invalid("bad byte order mark"))
+        };
+        let u16_at = |pos: usize| match endian {
+            Endian::Big => u16::from_be_bytes([data[pos], data[pos + 1]]),
+            Endian::Little => u16::from_le_bytes([data[pos], data[pos + 1]]),
+        };
+        let u32_at = |pos: usize| -> io::Result<u32> {
+            let bytes = data.get(pos..pos + 4).ok_or_else(|| invalid("truncated block table"))?;
+            let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            Ok(match endian {
+                Endian::Big => u32::from_be_bytes(bytes),
+                Endian::Little => u32::from_le_bytes(bytes),
+            })
+        };
+
+        let mut blocks = Vec::new();
+        for idx in 0..u16_at(0xE) as usize {
+            let offset = u32_at(0x10 + idx * 8)? as usize;
+            let size = u32_at(0x14 + idx * 8)? as usize;
+            if offset == 0 && size == 0 {
+                continue; // reserved slot
+            }
+            let block = offset.checked_add(size)
+                .and_then(|end| data.get(offset..end))
+                .filter(|block| block.len() >= 8)
+                .ok_or_else(|| invalid("block out of bounds"))?;
+            let mut magic = [0; 4];
+            magic.copy_from_slice(&block[..4]);
+            blocks.push(RawBlock { magic, offset, body: &block[8..] });
+        }
+
+        let version = u16_at(6);
+        let mut magic = [0; 4];
+        magic.copy_from_slice(&header[..4]);
+        Ok(RawFile { magic, endian, version, blocks })
+    }
+
+    pub fn block(&self, magic: &[u8; 4]) -> Option<&RawBlock<'a>> {
+        self.blocks.iter().find(|block| &block.magic == magic)
+    }
+}
+
+/// Where something was parsed from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Span {
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl Span {
+    pub fn end(&self) -> u64 {
+        self.offset + self.len
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.offset as usize..self.end() as usize
+    }
+}
+
+/// Reads nothing, the span goes from the position passed in to the current one.
+/// Put it after the last field of a struct, along with a `CurPos` before the first.
+impl BinRead for Span {
+    type Args = (u64,);
+
+    fn read_options<R: Read + Seek>(reader: &mut R, _: &ReadOptions, (offset,): Self::Args) -> BinResult<Self> {
+        let end = reader.seek(SeekFrom::Current(0))?;
+        Ok(Span { offset, len: end - offset })
+    }
+}
+
+/// Parsed data that knows which bytes of the file it came from.
+pub trait Spanned {
+    fn span(&self) -> Span;
 }
 
 // TODO: make generic over count type
-pub struct Table<T>(pub Vec<T>);
+pub struct Table<T>(pub Vec<T>, Span);
+
+/// The count and the entries, not what the entries point to.
+impl<T> Spanned for Table<T> {
+    fn span(&self) -> Span {
+        self.1
+    }
+}
 
 impl<Arg: Copy + 'static, BR: BinRead<Args=Arg>> BinRead for Table<BR> {
     type Args = Arg;
 
     fn read_options<R: Read + Seek>(reader: &mut R, ro: &ReadOptions, args: Self::Args) -> BinResult<Self> {
-        let mut temp_options = ro.clone();
+        let offset = reader.seek(SeekFrom::Current(0))?;
+        let mut temp_options = *ro;
         temp_options.count = Some(u32::read_options(reader, ro, ())? as usize);
-        Ok(Table(Vec::read_options(reader, &temp_options, args)?))
+        let entries = Vec::read_options(reader, &temp_options, args)?;
+        Ok(Table(entries, Span::read_options(reader, ro, (offset,))?))
     }
 
     fn after_parse<R: Read + Seek>(&mut self, reader: &mut R, ro: &ReadOptions, args: Self::Args) -> BinResult<()> {
-        let mut temp_options = ro.clone();
+        let mut temp_options = *ro;
         temp_options.count = Some(u32::read_options(reader, ro, ())? as usize);
         self.0.after_parse(reader, &temp_options, args)
     }
@@ -102,12 +287,12 @@ impl<Arg: Copy + 'static, BR: BinRead<Args=Arg>> BinRead for Table<BR> {
 
 // multiple Types are currently handled by optionally passing the type to the wrapped type
 // TODO: outer struct w/ type, inner enum with reference
-pub enum MultiReference<BR: BinRead> {
-    Absolute(u8, a32<BR>),
-    Relative(u8, r32<BR>)
+pub enum MultiReference<BR: BinRead, M: Mode = Eager> {
+    Absolute(u8, a32<BR, M>),
+    Relative(u8, r32<BR, M>)
 }
 
-impl<Arg: Any + Copy, BR: BinRead<Args=(u8, Arg)>> BinRead for MultiReference<BR> {
+impl<Arg: Any + Copy, BR: BinRead<Args=(u8, Arg)>, M: Mode> BinRead for MultiReference<BR, M> {
     type Args = Arg;
 
     fn read_options<R: Read + Seek>(reader: &mut R, ro: &ReadOptions, args: Self::Args) -> BinResult<Self> {
@@ -117,7 +302,7 @@ impl<Arg: Any + Copy, BR: BinRead<Args=(u8, Arg)>> BinRead for MultiReference<BR
             Ok(MultiReference::Relative(layout.ty, r32::read_options(reader, ro, (layout.ty, args))?))
         } else {
             println!("Warning: absolute reference at pos: {:X}", reader.seek(SeekFrom::Current(0))? - 4);
-            let abs: a32<BR> = a32::read_options(reader, ro, (layout.ty, args))?;
+            let abs: a32<BR, M> = a32::read_options(reader, ro, (layout.ty, args))?;
             // todo: move into AbsPtr?
             let mut error = Some(||{});
             error = None;
@@ -134,6 +319,28 @@ impl<Arg: Any + Copy, BR: BinRead<Args=(u8, Arg)>> BinRead for MultiReference<BR
     }
 }
 
+impl<BR: BinRead> MultiReference<BR> {
+    /// Position of the target in the file, `base` being what relative references are relative to.
+    pub fn pos(&self, base: u64) -> u64 {
+        match self {
+            MultiReference::Relative(_, rel) => base + rel.ptr() as u64,
+            MultiReference::Absolute(_, abs) => abs.ptr() as u64
+        }
+    }
+}
+
+/// The reference itself, not its target.
+impl<BR: BinRead, M: Mode> Spanned for MultiReference<BR, M> {
+    fn span(&self) -> Span {
+        let ptr = match self {
+            MultiReference::Relative(_, rel) => rel.span(),
+            MultiReference::Absolute(_, abs) => abs.span()
+        };
+        // the layout comes right before the pointer
+        Span { offset: ptr.offset - 4, len: 4 + ptr.len }
+    }
+}
+
 impl<BR: BinRead> Deref for MultiReference<BR> {
     type Target = BR;
 
@@ -154,6 +361,28 @@ impl<BR: BinRead> DerefMut for MultiReference<BR> {
     }
 }
 
+impl<BR: BinRead> MultiReference<BR, Lazy> {
+    pub fn is_resolved(&self) -> bool {
+        match self {
+            MultiReference::Relative(_, rel) => rel.is_resolved(),
+            MultiReference::Absolute(_, abs) => abs.is_resolved()
+        }
+    }
+
+    /// Reads the target the first time, and returns the same value afterwards.
+    pub fn resolve<R: Read + Seek>(&self, reader: &mut R) -> BinResult<&BR> {
+        match self {
+            MultiReference::Relative(_, rel) => rel.resolve(reader),
+            MultiReference::Absolute(_, abs) => abs.resolve(reader)
+        }
+    }
+
+    /// Resolves the reference against the whole file it was read from.
+    pub fn get(&self, data: &[u8]) -> BinResult<&BR> {
+        self.resolve(&mut binread::io::Cursor::new(data))
+    }
+}
+
 pub struct Single<BR: BinRead>(BR);
 
 impl<BR: BinRead> BinRead for Single<BR> {
@@ -178,9 +407,9 @@ impl<BR: BinRead> BinRead for Single<BR> {
     // }
 }
 
-pub struct Reference<BR: BinRead>(MultiReference<Single<BR>>);
+pub struct Reference<BR: BinRead, M: Mode = Eager>(MultiReference<Single<BR>, M>);
 
-impl<BR: BinRead> BinRead for Reference<BR> {
+impl<BR: BinRead, M: Mode> BinRead for Reference<BR, M> {
     type Args = BR::Args;
 
     fn read_options<R: Read + Seek>(reader: &mut R, ro: &ReadOptions, args: Self::Args) -> BinResult<Self> {
@@ -192,6 +421,19 @@ impl<BR: BinRead> BinRead for Reference<BR> {
     }
 }
 
+impl<BR: BinRead> Reference<BR> {
+    pub fn pos(&self, base: u64) -> u64 {
+        self.0.pos(base)
+    }
+}
+
+/// The reference itself, not its target.
+impl<BR: BinRead, M: Mode> Spanned for Reference<BR, M> {
+    fn span(&self) -> Span {
+        self.0.span()
+    }
+}
+
 impl<BR: BinRead> Deref for Reference<BR> {
     type Target = BR;
 
@@ -206,18 +448,71 @@ impl<BR: BinRead> DerefMut for Reference<BR> {
     }
 }
 
-#[derive(BinRead, Debug)]
+impl<BR: BinRead> Reference<BR, Lazy> {
+    pub fn is_resolved(&self) -> bool {
+        self.0.is_resolved()
+    }
+
+    /// Reads the target the first time, and returns the same value afterwards.
+    pub fn resolve<R: Read + Seek>(&self, reader: &mut R) -> BinResult<&BR> {
+        self.0.resolve(reader).map(|single| &single.0)
+    }
+
+    /// Resolves the reference against the whole file it was read from.
+    pub fn get(&self, data: &[u8]) -> BinResult<&BR> {
+        self.0.get(data).map(|single| &single.0)
+    }
+}
+
+#[derive(BinRead, Clone, Copy, Debug)]
 pub struct ReferenceLayout {
     pub is_relative: u8,
     pub ty: u8,
     pub padding: u16
 }
 
-#[derive(BinRead)]
+#[derive(BinRead, Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
 pub enum SoundEncoding {
-    SPcm8 = 0,
-    SPcm16 = 1,
-    DspAdpcm = 2
+    #[br(magic = 0u8)] SPcm8 = 0,
+    #[br(magic = 1u8)] SPcm16 = 1,
+    #[br(magic = 2u8)] DspAdpcm = 2
+}
+
+// shared by RSTM channel info and RWAV/RWSD wave info
+#[derive(BinRead, Clone, Debug, Default)]
+pub struct AdpcmInfo {
+    pub coefs: [i16; 16],
+    pub gain: u16, // always 0?
+    pub pred_scale: u16,
+    pub hist1: i16,
+    pub hist2: i16,
+    pub loop_pred_scale: u16,
+    pub loop_hist1: i16,
+    pub loop_hist2: i16,
+    pub padding: u16
+}
+
+impl AdpcmInfo {
+    pub const LENGTH: usize = 0x30;
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for coef in &self.coefs {
+            writer.write_all(&coef.to_be_bytes())?;
+        }
+        writer.write_all(&self.gain.to_be_bytes())?;
+        writer.write_all(&self.pred_scale.to_be_bytes())?;
+        writer.write_all(&self.hist1.to_be_bytes())?;
+        writer.write_all(&self.hist2.to_be_bytes())?;
+        writer.write_all(&self.loop_pred_scale.to_be_bytes())?;
+        writer.write_all(&self.loop_hist1.to_be_bytes())?;
+        writer.write_all(&self.loop_hist2.to_be_bytes())?;
+        writer.write_all(&self.padding.to_be_bytes())
+    }
+}
+
+pub fn align_up(value: usize, align: usize) -> usize {
+    value.div_ceil(align) * align
 }
 
 #[derive(BinRead, Debug)]
@@ -226,6 +521,16 @@ pub struct TypedId {
     id: [u8; 3] // u24
 }
 
+impl TypedId {
+    pub fn ty(&self) -> u8 {
+        self.ty
+    }
+
+    pub fn index(&self) -> u32 {
+        u32::from_be_bytes([0, self.id[0], self.id[1], self.id[2]])
+    }
+}
+
 
 pub struct DerefTest<BR: BinRead>(pub BR);
 
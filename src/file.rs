@@ -0,0 +1,114 @@
+use crate::common::*;
+use crate::brsar::BRSAR;
+use crate::rbnk::Rbnk;
+use crate::rseq::Rseq;
+use crate::rstm::Rstm;
+use crate::rwar::Rwar;
+use crate::rwav::Rwav;
+use crate::rwsd::Rwsd;
+use binread::{BinRead, BinReaderExt, BinResult, ReadOptions};
+use binread::io::{Read, Seek, SeekFrom};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Any of the sound formats, picked by the magic at the start of the file.
+pub enum NintendoFile {
+    // boxed, these are much bigger than the rest
+    Rsar(Box<BRSAR>),
+    Rseq(Rseq),
+    Rbnk(Rbnk),
+    Rwsd(Rwsd),
+    Rwar(Rwar),
+    Rwav(Rwav),
+    Rstm(Box<Rstm>),
+    // some other NW4R file, only the blocks are read
+    Unknown(GenericFile),
+}
+
+impl BinRead for NintendoFile {
+    type Args = ();
+
+    fn read_options<R: Read + Seek>(reader: &mut R, ro: &ReadOptions, _: Self::Args) -> BinResult<Self> {
+        let pos = reader.seek(SeekFrom::Current(0))?;
+        let magic: [u8; 4] = reader.read_be()?;
+        reader.seek(SeekFrom::Start(pos))?;
+
+        Ok(match &magic {
+            b"RSAR" => NintendoFile::Rsar(Box::new(BRSAR::read_options(reader, ro, ())?)),
+            b"RSEQ" => NintendoFile::Rseq(Rseq::read_options(reader, ro, ())?),
+            b"RBNK" => NintendoFile::Rbnk(Rbnk::read_options(reader, ro, ())?),
+            b"RWSD" => NintendoFile::Rwsd(Rwsd::read_options(reader, ro, ())?),
+            b"RWAR" => NintendoFile::Rwar(Rwar::read_options(reader, ro, ())?),
+            b"RWAV" => NintendoFile::Rwav(Rwav::read_options(reader, ro, ())?),
+            b"RSTM" => NintendoFile::Rstm(Box::new(Rstm::read_options(reader, ro, ())?)),
+            _ => NintendoFile::Unknown(GenericFile::read_options(reader, ro, ())?),
+        })
+    }
+}
+
+impl NintendoFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> BinResult<NintendoFile> {
+        BufReader::new(File::open(path)?).read_be()
+    }
+
+    pub fn header(&self) -> &FileHeader {
+        match self {
+            NintendoFile::Rsar(file) => &file.header,
+            NintendoFile::Rseq(file) => &file.header,
+            NintendoFile::Rbnk(file) => &file.header,
+            NintendoFile::Rwsd(file) => &file.header,
+            NintendoFile::Rwar(file) => &file.header,
+            NintendoFile::Rwav(file) => &file.header,
+            NintendoFile::Rstm(file) => &file.header,
+            NintendoFile::Unknown(file) => &file.header,
+        }
+    }
+
+    pub fn magic(&self) -> [u8; 4] {
+        self.header().magic
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rseq::write_rseq;
+    use crate::rwar::RwarBuilder;
+    use binread::io::Cursor;
+
+    fn read(bytes: &[u8]) -> NintendoFile {
+        Cursor::new(bytes).read_be().unwrap()
+    }
+
+    #[test]
+    fn dispatch() {
+        let mut rseq = Vec::new();
+        write_rseq(&mut rseq, &[0xFF], &[("SEQ".to_string(), 0)]).unwrap();
+        match read(&rseq) {
+            NintendoFile::Rseq(rseq) => assert!(rseq.find_label("SEQ").is_some()),
+            _ => panic!("not read as an RSEQ"),
+        }
+
+        let rwar = RwarBuilder::new().to_bytes();
+        assert!(matches!(read(&rwar), NintendoFile::Rwar(_)));
+
+        // unknown formats still get their blocks read
+        let mut other = rseq.clone();
+        other[..4].copy_from_slice(b"RFOO");
+        let file = read(&other);
+        assert_eq!(&file.magic(), b"RFOO");
+        match file {
+            NintendoFile::Unknown(file) => {
+                assert_eq!(file.blocks.len(), 2);
+                // the block sizes include their headers
+                let data = &file.blocks[0].block;
+                assert_eq!(&data.body(&other).unwrap()[..4], &[0, 0, 0, 0xC]);
+                assert_eq!(data.body.end - data.body.start + 8, data.header.size as u64);
+            }
+            _ => panic!("not read as a generic file"),
+        }
+
+        assert!(Cursor::new(b"RIFF\0\0\0\0").read_be::<NintendoFile>().is_err());
+    }
+}
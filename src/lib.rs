@@ -1,5 +1,16 @@
 pub mod common;
+pub mod file;
 pub mod brsar;
+pub mod codec;
+pub mod rbnk;
+pub mod rseq;
+pub mod rstm;
+pub mod rwar;
+pub mod rwav;
+pub mod rwsd;
+pub mod sf2;
+pub mod synth;
+pub mod wav;
 
 #[cfg(test)]
 mod tests {
@@ -7,4 +18,4 @@ mod tests {
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,501 @@
+#![allow(unused)]
+
+use crate::common::*;
+use crate::rwav::{self, WaveInfo};
+use crate::synth::envelope::Adsr;
+use crate::wav::Wav;
+use binread::{BinRead, BinResult, ReadOptions};
+use binread::io::{Read, Seek, SeekFrom};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::ops::{Deref, RangeInclusive};
+
+/// Sound bank, the instruments used by sequences.
+#[derive(BinRead)]
+pub struct Rbnk {
+    #[br(assert(&header.magic == b"RBNK"))]
+    pub header: FileHeader,
+    #[br(is_big = header.endian == Endian::Big)]
+    pub data: BlockPtr<DataBlock>,
+    #[br(is_big = header.endian == Endian::Big)]
+    pub wave: BlockPtr<WaveBlock>,
+}
+
+#[derive(BinRead)]
+pub struct DataBlock {
+    #[br(assert(&header.magic == b"DATA"))]
+    pub header: BlockHeader,
+    // indexed by program number
+    pub instruments: Table<RegionRef>,
+}
+
+// TODO: newer versions seem to leave this empty, and index into the RWAR instead.
+#[derive(BinRead)]
+pub struct WaveBlock {
+    #[br(assert(&header.magic == b"WAVE"))]
+    pub header: BlockHeader,
+    pub waves: Table<r32<WaveInfo>>,
+}
+
+/// Instruments are split by key, then by velocity, the reference type tells which kind of
+/// split (if any) follows.
+#[derive(BinRead)]
+#[br(import(ty: u8, a: ()))]
+pub enum Region {
+    #[br(pre_assert(ty == 1))] Inst(InstParam),
+    #[br(pre_assert(ty == 2))] Range(RangeTable),
+    #[br(pre_assert(ty == 3))] Index(IndexTable),
+}
+
+/// Empty regions are stored as null references, which MultiReference doesn't accept.
+pub enum RegionRef {
+    None,
+    Some(MultiReference<Region>),
+}
+
+impl BinRead for RegionRef {
+    type Args = ();
+
+    fn read_options<R: Read + Seek>(reader: &mut R, ro: &ReadOptions, args: Self::Args) -> BinResult<Self> {
+        let pos = reader.seek(SeekFrom::Current(0))?;
+        let layout = ReferenceLayout::read_options(reader, ro, ())?;
+        let offset = u32::read_options(reader, ro, ())?;
+
+        if layout.ty == 0 || (layout.is_relative == 0 && offset == 0) {
+            Ok(RegionRef::None)
+        } else {
+            reader.seek(SeekFrom::Start(pos))?;
+            Ok(RegionRef::Some(MultiReference::read_options(reader, ro, args)?))
+        }
+    }
+
+    fn after_parse<R: Read + Seek>(&mut self, reader: &mut R, ro: &ReadOptions, args: Self::Args) -> BinResult<()> {
+        match self {
+            RegionRef::Some(region) => region.after_parse(reader, ro, args),
+            RegionRef::None => Ok(())
+        }
+    }
+}
+
+/// Splits by key or velocity, `keys` holds the inclusive upper bound of each region.
+#[derive(BinRead)]
+pub struct RangeTable {
+    pub count: u8,
+    #[br(count = count)]
+    pub keys: Vec<u8>,
+    // the references are aligned to 4 bytes, counting from the start of the table
+    #[br(pad_before = (4 - (count as usize + 1) % 4) % 4, count = count)]
+    pub regions: Vec<RegionRef>,
+}
+
+/// Splits by key or velocity, with one region per value in `min..=max`.
+#[derive(BinRead)]
+pub struct IndexTable {
+    pub min: u8,
+    pub max: u8,
+    reserved: u16,
+    #[br(count = (max as usize + 1).saturating_sub(min as usize))]
+    pub regions: Vec<RegionRef>,
+}
+
+#[derive(BinRead, Clone, Debug)]
+pub struct InstParam {
+    pub wave_index: s32, // into the WAVE block, or the RWAR for newer versions
+    pub attack: u8,
+    pub decay: u8,
+    pub sustain: u8,
+    pub release: u8,
+    pub hold: u8,
+    pub wave_data_location_type: u8,
+    pub note_off_type: u8, // 0 = release, 1 = ignore (play the whole sample)
+    pub alternate_assign: u8,
+    pub original_key: u8,
+    pub volume: u8,
+    pub pan: u8,
+    pub surround_pan: u8,
+    pub tune: f32, // frequency ratio
+    lfo_table: u64 /*Reference<()>*/,
+    graph_env_table: u64 /*Reference<()>*/,
+    randomizer_table: u64 /*Reference<()>*/,
+    reserved: u32
+}
+
+/// A leaf of an instrument's region tree, along with the keys and velocities it covers.
+#[derive(Clone, Debug)]
+pub struct KeyRegion<'a> {
+    pub keys: RangeInclusive<u8>,
+    pub velocities: RangeInclusive<u8>,
+    pub inst: &'a InstParam,
+}
+
+impl RegionRef {
+    pub fn region(&self) -> Option<&Region> {
+        match self {
+            RegionRef::Some(region) => Some(region.deref()),
+            RegionRef::None => None
+        }
+    }
+
+    /// Resolves the split for `value` (key or velocity), or returns the region itself if it isn't split.
+    fn resolve(&self, value: u8) -> Option<&RegionRef> {
+        match self.region()? {
+            Region::Inst(_) => Some(self),
+            Region::Range(table) => {
+                let idx = table.keys.iter().position(|&upper| value <= upper)?;
+                table.regions.get(idx)
+            }
+            Region::Index(table) => {
+                let idx = value.checked_sub(table.min)?;
+                table.regions.get(idx as usize)
+            }
+        }
+    }
+
+    /// Every split of this region, with the ranges of values (keys or velocities) they cover.
+    fn splits(&self, range: RangeInclusive<u8>) -> Vec<(RangeInclusive<u8>, &RegionRef)> {
+        match self.region() {
+            None => Vec::new(),
+            Some(Region::Inst(_)) => vec![(range, self)],
+            Some(Region::Range(table)) => {
+                let mut low = *range.start();
+                let mut splits = Vec::new();
+                for (&upper, region) in table.keys.iter().zip(&table.regions) {
+                    if upper >= low {
+                        splits.push((low..=upper, region));
+                    }
+                    low = upper.saturating_add(1);
+                }
+                splits
+            }
+            Some(Region::Index(table)) => table.regions.iter()
+                .enumerate()
+                .map(|(idx, region)| {
+                    let value = table.min.saturating_add(idx as u8);
+                    (value..=value, region)
+                })
+                .collect()
+        }
+    }
+
+    fn inst(&self) -> Option<&InstParam> {
+        match self.region()? {
+            Region::Inst(inst) => Some(inst),
+            _ => None
+        }
+    }
+}
+
+impl Rbnk {
+    pub fn instruments(&self) -> &[RegionRef] {
+        &self.data.block.instruments.0
+    }
+
+    /// Looks up the instrument region played by a note, like the sound library does.
+    pub fn instrument(&self, program: usize, key: u8, velocity: u8) -> Option<&InstParam> {
+        self.instruments().get(program)?
+            .resolve(key)?
+            .resolve(velocity)?
+            .inst()
+    }
+
+    /// Flattens the key and velocity splits of an instrument.
+    pub fn regions(&self, program: usize) -> Vec<KeyRegion<'_>> {
+        let instrument = match self.instruments().get(program) {
+            Some(instrument) => instrument,
+            None => return Vec::new()
+        };
+
+        let mut regions = Vec::new();
+        for (keys, key_region) in instrument.splits(0..=127) {
+            for (velocities, region) in key_region.splits(0..=127) {
+                if let Some(inst) = region.inst() {
+                    regions.push(KeyRegion { keys: keys.clone(), velocities, inst });
+                }
+            }
+        }
+        regions
+    }
+
+    /// Decodes a wave used by this bank, given the wave data belonging to it
+    /// (see `GroupEntry::read_archive_bytes`).
+    pub fn decode_wave(&self, wave_index: usize, wave_data: &[u8]) -> Option<Wav> {
+        rwav::decode_indexed_wave(&self.wave.block.waves.0, wave_index, wave_data)
+    }
+
+    /// Decodes every wave used by this bank, indexed by `InstParam::wave_index`.
+    pub fn decode_waves(&self, wave_data: &[u8]) -> Vec<Option<Wav>> {
+        rwav::decode_indexed_waves(&self.wave.block.waves.0, wave_data)
+    }
+
+    /// Number of waves in the WAVE block, which may be empty if the waves are stored in an RWAR.
+    pub fn wave_count(&self) -> usize {
+        self.wave.block.waves.0.len()
+    }
+}
+
+impl InstParam {
+    pub const LENGTH: usize = 0x30;
+
+    pub fn new(wave_index: s32, adsr: Adsr, original_key: u8, volume: u8, pan: u8, tune: f32) -> InstParam {
+        InstParam {
+            wave_index,
+            attack: adsr.attack,
+            decay: adsr.decay,
+            sustain: adsr.sustain,
+            release: adsr.release,
+            hold: adsr.hold,
+            wave_data_location_type: 0,
+            note_off_type: 0,
+            alternate_assign: 0,
+            original_key,
+            volume,
+            pan,
+            surround_pan: 0,
+            tune,
+            lfo_table: 0,
+            graph_env_table: 0,
+            randomizer_table: 0,
+            reserved: 0,
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.wave_index.to_be_bytes())?;
+        writer.write_all(&[
+            self.attack, self.decay, self.sustain, self.release,
+            self.hold, self.wave_data_location_type, self.note_off_type, self.alternate_assign,
+            self.original_key, self.volume, self.pan, self.surround_pan,
+        ])?;
+        writer.write_all(&self.tune.to_be_bytes())?;
+        // lfo, graph envelope and randomizer tables are left empty
+        writer.write_all(&[0; 3 * 8 + 4])
+    }
+}
+
+/// An instrument region for `BankBuilder`, covering a range of keys and velocities.
+#[derive(Clone, Debug)]
+pub struct BankRegion {
+    pub keys: RangeInclusive<u8>,
+    pub velocities: RangeInclusive<u8>,
+    pub inst: InstParam,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Node {
+    Empty,
+    Inst(usize), // index of the region
+    Split(Vec<(u8, Node)>), // (inclusive upper bound, region)
+}
+
+impl Node {
+    /// Splits 0-127 at every boundary of `ranges`, and builds a node for each part.
+    fn split(ranges: impl Iterator<Item = RangeInclusive<u8>>, mut build: impl FnMut(u8) -> Node) -> Node {
+        let mut bounds: Vec<u16> = ranges
+            .flat_map(|range| vec![*range.start() as u16, *range.end() as u16 + 1])
+            .chain(vec![0, 128])
+            .filter(|&bound| bound <= 128)
+            .collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        let mut parts: Vec<(u8, Node)> = Vec::new();
+        for pair in bounds.windows(2) {
+            let node = build(pair[0] as u8);
+            let upper = (pair[1] - 1) as u8;
+            match parts.last_mut() {
+                Some((last_upper, last)) if *last == node => *last_upper = upper,
+                _ => parts.push((upper, node))
+            }
+        }
+
+        if parts.len() == 1 {
+            parts.pop().unwrap().1
+        } else {
+            Node::Split(parts)
+        }
+    }
+}
+
+/// Builds a new sound bank from key and velocity regions, with the waves stored in an RWAR.
+#[derive(Clone, Debug, Default)]
+pub struct BankBuilder {
+    /// Regions of each instrument, indexed by program number. Earlier regions take priority where they overlap.
+    pub instruments: Vec<Vec<BankRegion>>,
+}
+
+impl BankBuilder {
+    pub fn new() -> BankBuilder {
+        BankBuilder::default()
+    }
+
+    pub fn set_instrument(&mut self, program: usize, regions: Vec<BankRegion>) {
+        if self.instruments.len() <= program {
+            self.instruments.resize(program + 1, Vec::new());
+        }
+        self.instruments[program] = regions;
+    }
+
+    /// Splits by key first, then by velocity, like the sound library expects.
+    fn instrument_node(regions: &[BankRegion]) -> Node {
+        Node::split(regions.iter().map(|region| region.keys.clone()), |key| {
+            let candidates: Vec<usize> = (0..regions.len()).filter(|&idx| regions[idx].keys.contains(&key)).collect();
+            Node::split(candidates.iter().map(|&idx| regions[idx].velocities.clone()), |velocity| {
+                match candidates.iter().find(|&&idx| regions[idx].velocities.contains(&velocity)) {
+                    Some(&idx) => Node::Inst(idx),
+                    None => Node::Empty
+                }
+            })
+        })
+    }
+
+    /// Writes a node (children first) to the DATA block body, and returns a reference to it.
+    fn write_node(node: &Node, regions: &[BankRegion], written: &mut HashMap<usize, u32>, body: &mut Vec<u8>) -> [u8; 8] {
+        let (ty, offset) = match node {
+            Node::Empty => return [0; 8],
+            Node::Inst(idx) => {
+                let offset = *written.entry(*idx).or_insert_with(|| {
+                    let offset = body.len() as u32;
+                    regions[*idx].inst.write(body).unwrap();
+                    offset
+                });
+                (1, offset)
+            }
+            Node::Split(parts) => {
+                let refs: Vec<[u8; 8]> = parts.iter().map(|(_, node)| Self::write_node(node, regions, written, body)).collect();
+                let offset = body.len() as u32;
+                body.push(parts.len() as u8);
+                body.extend(parts.iter().map(|(upper, _)| *upper));
+                body.resize(align_up(body.len(), 4), 0);
+                for reference in refs {
+                    body.extend_from_slice(&reference);
+                }
+                (2, offset)
+            }
+        };
+
+        let mut reference = [1, ty, 0, 0, 0, 0, 0, 0];
+        reference[4..].copy_from_slice(&offset.to_be_bytes());
+        reference
+    }
+
+    /// Body of the DATA block, offsets are relative to its start.
+    fn data_body(&self) -> Vec<u8> {
+        let table_len = 4 + self.instruments.len() * 8;
+        let mut body = vec![0; table_len];
+        body[0..4].copy_from_slice(&(self.instruments.len() as u32).to_be_bytes());
+
+        for (program, regions) in self.instruments.iter().enumerate() {
+            let node = if regions.is_empty() { Node::Empty } else { Self::instrument_node(regions) };
+            let mut written = HashMap::new();
+            let reference = Self::write_node(&node, regions, &mut written, &mut body);
+            body[4 + program * 8..12 + program * 8].copy_from_slice(&reference);
+        }
+        body
+    }
+
+    pub fn write_rbnk<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let data_body = self.data_body();
+        let mut container = ContainerWriter::new(*b"RBNK", 0x0101);
+        let data = container.add_block(*b"DATA", data_body.len());
+        let wave = container.add_block(*b"WAVE", 4);
+
+        container.write_header(writer)?;
+        container.write_block(writer, data, |body| body.write_all(&data_body))?;
+        // empty, the waves are in the RWAR
+        container.write_block(writer, wave, |body| body.write_all(&0u32.to_be_bytes()))
+    }
+
+    pub fn to_rbnk(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_rbnk(&mut out).unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binread::BinReaderExt;
+    use binread::io::Cursor;
+
+    // instrument 0 split by index, 60 playing the instrument and 61 nothing, instrument 1 split
+    // by range into a single region, both using the same instrument
+    fn test_rbnk() -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&2u32.to_be_bytes());
+        body.extend_from_slice(&[1, 3, 0, 0, 0, 0, 0, 0x14]);
+        body.extend_from_slice(&[1, 2, 0, 0, 0, 0, 0, 0x28]);
+        body.extend_from_slice(&[60, 61, 0, 0]); // index table
+        body.extend_from_slice(&[1, 1, 0, 0, 0, 0, 0, 0x34]);
+        body.extend_from_slice(&[0; 8]);
+        body.extend_from_slice(&[1, 127, 0, 0]); // range table, padded to 4 bytes
+        body.extend_from_slice(&[1, 1, 0, 0, 0, 0, 0, 0x34]);
+        body.extend_from_slice(&5u32.to_be_bytes()); // instrument
+        body.extend_from_slice(&[127, 100, 90, 80, 0, 0, 1, 0, 60, 100, 64, 0]);
+        body.extend_from_slice(&1.0f32.to_be_bytes());
+        body.resize(0x64, 0);
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"RBNK");
+        data.extend_from_slice(&[0xFE, 0xFF, 0x01, 0x01]);
+        data.extend_from_slice(&0xC0u32.to_be_bytes());
+        data.extend_from_slice(&[0, 0x20, 0, 2]);
+        for block in [0x20u32, 0x80, 0xA0, 0x20] {
+            data.extend_from_slice(&block.to_be_bytes());
+        }
+        data.extend_from_slice(b"DATA");
+        data.extend_from_slice(&0x80u32.to_be_bytes());
+        data.extend_from_slice(&body);
+        data.resize(0xA0, 0);
+        data.extend_from_slice(b"WAVE");
+        data.extend_from_slice(&0x20u32.to_be_bytes());
+        data.resize(0xC0, 0);
+        data
+    }
+
+    #[test]
+    fn parse() {
+        let rbnk: Rbnk = Cursor::new(test_rbnk()).read_be().unwrap();
+        assert_eq!(rbnk.instruments().len(), 2);
+        assert_eq!(rbnk.wave_count(), 0);
+
+        let inst = rbnk.instrument(0, 60, 100).unwrap();
+        assert_eq!(inst.wave_index, 5);
+        assert_eq!((inst.attack, inst.decay, inst.sustain, inst.release), (127, 100, 90, 80));
+        assert_eq!((inst.note_off_type, inst.original_key, inst.volume, inst.pan), (1, 60, 100, 64));
+        assert_eq!(inst.tune, 1.0);
+        assert!(rbnk.instrument(0, 61, 100).is_none());
+        assert!(rbnk.instrument(0, 59, 100).is_none());
+        assert_eq!(rbnk.instrument(1, 0, 0).unwrap().wave_index, 5);
+
+        let regions = rbnk.regions(0);
+        assert_eq!(regions.len(), 1);
+        assert_eq!((regions[0].keys.clone(), regions[0].velocities.clone()), (60..=60, 0..=127));
+        let regions = rbnk.regions(1);
+        assert_eq!((regions[0].keys.clone(), regions[0].velocities.clone()), (0..=127, 0..=127));
+    }
+
+    fn region(keys: RangeInclusive<u8>, velocities: RangeInclusive<u8>, wave_index: s32) -> BankRegion {
+        BankRegion { keys, velocities, inst: InstParam::new(wave_index, Adsr::default(), 60, 127, 64, 1.0) }
+    }
+
+    #[test]
+    fn builder_round_trip() {
+        let mut builder = BankBuilder::new();
+        builder.set_instrument(0, vec![region(0..=127, 0..=127, 0)]);
+        builder.set_instrument(2, vec![
+            region(0..=59, 0..=127, 1),
+            region(60..=127, 0..=63, 2),
+            region(60..=127, 64..=127, 3),
+        ]);
+
+        let rbnk: Rbnk = Cursor::new(builder.to_rbnk()).read_be().unwrap();
+        assert_eq!(rbnk.instruments().len(), 3);
+        assert_eq!(rbnk.instrument(0, 100, 100).unwrap().wave_index, 0);
+        assert!(rbnk.instrument(1, 60, 100).is_none());
+        assert_eq!(rbnk.instrument(2, 20, 20).unwrap().wave_index, 1);
+        assert_eq!(rbnk.instrument(2, 60, 20).unwrap().wave_index, 2);
+        assert_eq!(rbnk.instrument(2, 127, 127).unwrap().wave_index, 3);
+        assert_eq!(rbnk.regions(2).len(), 3);
+    }
+}
@@ -0,0 +1,493 @@
+//! Decoding and encoding of the RSEQ command stream.
+//!
+//! Commands are a single opcode byte followed by big endian arguments. Opcodes below 0x80 are notes
+//! (the opcode is the key). Three prefixes modify the next command: 0xA0 replaces its last argument
+//! with a random range, 0xA1 with a variable, and 0xA2 only runs it if the last comparison was true.
+
+use std::error::Error;
+use std::fmt;
+
+/// Sequence variables 0-15 are local to the sequence, 16-31 are global, and 32-47 belong to the track.
+pub const VARIABLE_COUNT: u8 = 48;
+
+const PREFIX_RANDOM: u8 = 0xA0;
+const PREFIX_VARIABLE: u8 = 0xA1;
+const PREFIX_IF: u8 = 0xA2;
+const EXTENDED: u8 = 0xF0;
+
+/// Encoding of a command argument.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Arg {
+    U8,
+    S8,
+    S16,
+    VarLen, // MIDI style, 7 bits per byte with the high bit set on all but the last
+}
+
+/// The last argument of a command, which can be replaced by one of the prefixes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Value {
+    Const(i32),
+    Random { min: i16, max: i16 },
+    Variable(u8),
+}
+
+macro_rules! opcodes {
+    ($(#[$attr:meta])* $ty:ident { $($name:ident = $op:literal, $arg:ident, $text:literal;)* }) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+        pub enum $ty {
+            $($name),*
+        }
+
+        impl $ty {
+            pub fn from_op(op: u8) -> Option<$ty> {
+                match op {
+                    $($op => Some($ty::$name),)*
+                    _ => None
+                }
+            }
+
+            pub fn op(self) -> u8 {
+                match self {
+                    $($ty::$name => $op),*
+                }
+            }
+
+            pub fn arg(self) -> Arg {
+                match self {
+                    $($ty::$name => Arg::$arg),*
+                }
+            }
+
+            /// Name used by the text format.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $($ty::$name => $text),*
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<$ty> {
+                match name {
+                    $($text => Some($ty::$name),)*
+                    _ => None
+                }
+            }
+        }
+    };
+}
+
+opcodes! {
+    /// Commands that set a single track or sequence parameter.
+    Param {
+        Timebase = 0xB0, U8, "timebase";
+        EnvHold = 0xB1, U8, "env_hold";
+        Monophonic = 0xB2, U8, "monophonic";
+        VelocityRange = 0xB3, U8, "velocity_range";
+        Pan = 0xC0, U8, "pan";
+        Volume = 0xC1, U8, "volume";
+        MainVolume = 0xC2, U8, "main_volume";
+        Transpose = 0xC3, S8, "transpose";
+        PitchBend = 0xC4, S8, "pitch_bend";
+        BendRange = 0xC5, U8, "bend_range";
+        Priority = 0xC6, U8, "priority";
+        NoteWait = 0xC7, U8, "note_wait";
+        Tie = 0xC8, U8, "tie";
+        Portamento = 0xC9, U8, "porta";
+        ModDepth = 0xCA, U8, "mod_depth";
+        ModSpeed = 0xCB, U8, "mod_speed";
+        ModType = 0xCC, U8, "mod_type";
+        ModRange = 0xCD, U8, "mod_range";
+        PortaSwitch = 0xCE, U8, "porta_on";
+        PortaTime = 0xCF, U8, "porta_time";
+        Attack = 0xD0, U8, "attack";
+        Decay = 0xD1, U8, "decay";
+        Sustain = 0xD2, U8, "sustain";
+        Release = 0xD3, U8, "release";
+        LoopStart = 0xD4, U8, "loop_start";
+        Volume2 = 0xD5, U8, "volume2";
+        PrintVar = 0xD6, U8, "printvar";
+        SurroundPan = 0xD7, S8, "span";
+        LpfCutoff = 0xD8, S8, "lpf_cutoff";
+        FxSendA = 0xD9, U8, "fxsend_a";
+        FxSendB = 0xDA, U8, "fxsend_b";
+        MainSend = 0xDB, U8, "mainsend";
+        InitPan = 0xDC, U8, "init_pan";
+        Mute = 0xDD, U8, "mute";
+        FxSendC = 0xDE, U8, "fxsend_c";
+        Damper = 0xDF, U8, "damper";
+        ModDelay = 0xE0, S16, "mod_delay";
+        Tempo = 0xE1, S16, "tempo";
+        SweepPitch = 0xE3, S16, "sweep_pitch";
+    }
+}
+
+opcodes! {
+    /// Extended (0xF0 prefixed) commands operating on a variable.
+    VarOp {
+        Set = 0x80, S16, "setvar";
+        Add = 0x81, S16, "addvar";
+        Sub = 0x82, S16, "subvar";
+        Mul = 0x83, S16, "mulvar";
+        Div = 0x84, S16, "divvar";
+        Shift = 0x85, S16, "shiftvar";
+        Rand = 0x86, S16, "randvar";
+        And = 0x87, S16, "andvar";
+        Or = 0x88, S16, "orvar";
+        Xor = 0x89, S16, "xorvar";
+        Not = 0x8A, S16, "notvar";
+        Mod = 0x8B, S16, "modvar";
+        Eq = 0x90, S16, "cmp_eq";
+        Ge = 0x91, S16, "cmp_ge";
+        Gt = 0x92, S16, "cmp_gt";
+        Le = 0x93, S16, "cmp_le";
+        Lt = 0x94, S16, "cmp_lt";
+        Ne = 0x95, S16, "cmp_ne";
+    }
+}
+
+impl VarOp {
+    pub fn is_comparison(self) -> bool {
+        self.op() >= 0x90
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+    Note { key: u8, velocity: u8, length: Value },
+    Wait(Value),
+    Program(Value),
+    OpenTrack { track: u8, offset: u32 },
+    Jump(u32),
+    Call(u32),
+    Param(Param, Value),
+    Var { op: VarOp, var: u8, value: Value },
+    UserProc(u16),
+    LoopEnd,
+    Return,
+    AllocTrack(u16), // bitmask
+    Fin,
+    If(Box<Command>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DecodeError {
+    UnexpectedEnd { offset: usize },
+    UnknownCommand { offset: usize, opcode: u8 },
+    /// A random or variable prefix in front of a command without a replaceable argument.
+    InvalidPrefix { offset: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd { offset } => write!(f, "unexpected end of sequence data at 0x{:X}", offset),
+            DecodeError::UnknownCommand { offset, opcode } => write!(f, "unknown command 0x{:02X} at 0x{:X}", opcode, offset),
+            DecodeError::InvalidPrefix { offset } => write!(f, "prefix applied to a command without arguments at 0x{:X}", offset),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Prefix {
+    None,
+    Random,
+    Variable,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        let value = *self.data.get(self.pos).ok_or(DecodeError::UnexpectedEnd { offset: self.pos })?;
+        self.pos += 1;
+        Ok(value)
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn u24(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_be_bytes([0, self.u8()?, self.u8()?, self.u8()?]))
+    }
+
+    fn var_len(&mut self) -> Result<u32, DecodeError> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(value)
+    }
+
+    fn arg(&mut self, arg: Arg) -> Result<i32, DecodeError> {
+        Ok(match arg {
+            Arg::U8 => self.u8()? as i32,
+            Arg::S8 => self.u8()? as i8 as i32,
+            Arg::S16 => self.u16()? as i16 as i32,
+            Arg::VarLen => self.var_len()? as i32,
+        })
+    }
+
+    fn value(&mut self, arg: Arg, prefix: Prefix) -> Result<Value, DecodeError> {
+        Ok(match prefix {
+            Prefix::None => Value::Const(self.arg(arg)?),
+            Prefix::Random => Value::Random { min: self.u16()? as i16, max: self.u16()? as i16 },
+            Prefix::Variable => Value::Variable(self.u8()?),
+        })
+    }
+
+    fn command(&mut self, prefix: Prefix) -> Result<Command, DecodeError> {
+        let start = self.pos;
+        let opcode = self.u8()?;
+        let unknown = DecodeError::UnknownCommand { offset: start, opcode };
+        let no_prefix = |command: Command| if prefix == Prefix::None {
+            Ok(command)
+        } else {
+            Err(DecodeError::InvalidPrefix { offset: start })
+        };
+
+        match opcode {
+            0x00..=0x7F => Ok(Command::Note { key: opcode, velocity: self.u8()?, length: self.value(Arg::VarLen, prefix)? }),
+            0x80 => Ok(Command::Wait(self.value(Arg::VarLen, prefix)?)),
+            0x81 => Ok(Command::Program(self.value(Arg::VarLen, prefix)?)),
+            0x88 => no_prefix(Command::OpenTrack { track: self.u8()?, offset: self.u24()? }),
+            0x89 => no_prefix(Command::Jump(self.u24()?)),
+            0x8A => no_prefix(Command::Call(self.u24()?)),
+            PREFIX_RANDOM | PREFIX_VARIABLE if prefix == Prefix::None => {
+                let prefix = if opcode == PREFIX_RANDOM { Prefix::Random } else { Prefix::Variable };
+                self.command(prefix)
+            }
+            PREFIX_IF if prefix == Prefix::None => Ok(Command::If(Box::new(self.command(Prefix::None)?))),
+            0xFC => no_prefix(Command::LoopEnd),
+            0xFD => no_prefix(Command::Return),
+            0xFE => no_prefix(Command::AllocTrack(self.u16()?)),
+            0xFF => no_prefix(Command::Fin),
+            EXTENDED => {
+                let sub = self.u8()?;
+                if sub == 0xE0 {
+                    return no_prefix(Command::UserProc(self.u16()?));
+                }
+                let op = VarOp::from_op(sub).ok_or(DecodeError::UnknownCommand { offset: start + 1, opcode: sub })?;
+                Ok(Command::Var { op, var: self.u8()?, value: self.value(op.arg(), prefix)? })
+            }
+            _ => {
+                let param = Param::from_op(opcode).ok_or(unknown)?;
+                Ok(Command::Param(param, self.value(param.arg(), prefix)?))
+            }
+        }
+    }
+}
+
+fn write_var_len(out: &mut Vec<u8>, value: u32) {
+    let mut shift = 21;
+    while shift > 0 && value >> shift == 0 {
+        shift -= 7;
+    }
+    while shift > 0 {
+        out.push(((value >> shift) & 0x7F) as u8 | 0x80);
+        shift -= 7;
+    }
+    out.push((value & 0x7F) as u8);
+}
+
+fn write_u24(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes()[1..]);
+}
+
+impl Value {
+    pub fn as_const(self) -> Option<i32> {
+        match self {
+            Value::Const(value) => Some(value),
+            _ => None
+        }
+    }
+
+    fn prefix(self) -> Option<u8> {
+        match self {
+            Value::Const(_) => None,
+            Value::Random { .. } => Some(PREFIX_RANDOM),
+            Value::Variable(_) => Some(PREFIX_VARIABLE),
+        }
+    }
+
+    fn write(self, out: &mut Vec<u8>, arg: Arg) {
+        match self {
+            Value::Const(value) => match arg {
+                Arg::U8 | Arg::S8 => out.push(value as u8),
+                Arg::S16 => out.extend_from_slice(&(value as i16).to_be_bytes()),
+                Arg::VarLen => write_var_len(out, value as u32),
+            },
+            Value::Random { min, max } => {
+                out.extend_from_slice(&min.to_be_bytes());
+                out.extend_from_slice(&max.to_be_bytes());
+            }
+            Value::Variable(var) => out.push(var),
+        }
+    }
+}
+
+impl Command {
+    /// Decodes the command at `offset`, returning it along with its length in bytes.
+    pub fn decode(data: &[u8], offset: usize) -> Result<(Command, usize), DecodeError> {
+        let mut reader = Reader { data, pos: offset };
+        let command = reader.command(Prefix::None)?;
+        Ok((command, reader.pos - offset))
+    }
+
+    /// Decodes commands from `offset` until the end of the data, or until a decoding error.
+    pub fn decode_all(data: &[u8], offset: usize) -> impl Iterator<Item = Result<(usize, Command), DecodeError>> + '_ {
+        let mut pos = offset;
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            if failed || pos >= data.len() {
+                return None;
+            }
+            match Command::decode(data, pos) {
+                Ok((command, len)) => {
+                    let start = pos;
+                    pos += len;
+                    Some(Ok((start, command)))
+                }
+                Err(err) => {
+                    failed = true;
+                    Some(Err(err))
+                }
+            }
+        })
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let value = match self {
+            Command::Note { length: value, .. } | Command::Wait(value) | Command::Program(value)
+            | Command::Param(_, value) | Command::Var { value, .. } => Some(*value),
+            _ => None
+        };
+        if let Some(prefix) = value.and_then(Value::prefix) {
+            out.push(prefix);
+        }
+
+        match self {
+            Command::Note { key, velocity, length } => {
+                out.push(*key & 0x7F);
+                out.push(*velocity);
+                length.write(out, Arg::VarLen);
+            }
+            Command::Wait(value) => {
+                out.push(0x80);
+                value.write(out, Arg::VarLen);
+            }
+            Command::Program(value) => {
+                out.push(0x81);
+                value.write(out, Arg::VarLen);
+            }
+            Command::OpenTrack { track, offset } => {
+                out.push(0x88);
+                out.push(*track);
+                write_u24(out, *offset);
+            }
+            Command::Jump(offset) => {
+                out.push(0x89);
+                write_u24(out, *offset);
+            }
+            Command::Call(offset) => {
+                out.push(0x8A);
+                write_u24(out, *offset);
+            }
+            Command::Param(param, value) => {
+                out.push(param.op());
+                value.write(out, param.arg());
+            }
+            Command::Var { op, var, value } => {
+                out.push(EXTENDED);
+                out.push(op.op());
+                out.push(*var);
+                value.write(out, op.arg());
+            }
+            Command::UserProc(proc_id) => {
+                out.push(EXTENDED);
+                out.push(0xE0);
+                out.extend_from_slice(&proc_id.to_be_bytes());
+            }
+            Command::LoopEnd => out.push(0xFC),
+            Command::Return => out.push(0xFD),
+            Command::AllocTrack(mask) => {
+                out.push(0xFE);
+                out.extend_from_slice(&mask.to_be_bytes());
+            }
+            Command::Fin => out.push(0xFF),
+            Command::If(command) => {
+                out.push(PREFIX_IF);
+                command.encode(out);
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let data = [
+            0xFE, 0x00, 0x03, // alloctrack
+            0x88, 0x01, 0x00, 0x00, 0x20, // opentrack 1
+            0xE1, 0x00, 0x78, // tempo 120
+            0x81, 0x05, // prg 5
+            0x3C, 0x64, 0x81, 0x40, // cn4 100, 192
+            0xA0, 0x80, 0x00, 0x10, 0x00, 0x20, // wait random
+            0xA2, 0xC0, 0x40, // if pan 64
+            0xF0, 0x90, 0x20, 0xFF, 0xFE, // cmp_eq var 32, -2
+            0xA1, 0xC3, 0x21, // transpose var 33
+            0x89, 0x00, 0x00, 0x08, // jump
+            0xFF,
+        ];
+
+        let commands: Vec<_> = Command::decode_all(&data, 0).collect::<Result<_, _>>().unwrap();
+        assert_eq!(commands.len(), 11);
+        assert_eq!(commands[4].1, Command::Note { key: 0x3C, velocity: 100, length: Value::Const(192) });
+        assert_eq!(commands[5].1, Command::Wait(Value::Random { min: 0x10, max: 0x20 }));
+        assert_eq!(commands[6].1, Command::If(Box::new(Command::Param(Param::Pan, Value::Const(64)))));
+        assert_eq!(commands[7].1, Command::Var { op: VarOp::Eq, var: 0x20, value: Value::Const(-2) });
+        assert_eq!(commands[8].1, Command::Param(Param::Transpose, Value::Variable(0x21)));
+
+        let mut out = Vec::new();
+        for (_, command) in &commands {
+            command.encode(&mut out);
+        }
+        assert_eq!(&out[..], &data[..]);
+    }
+
+    #[test]
+    fn var_len() {
+        for &value in &[0u32, 0x7F, 0x80, 0x3FFF, 0x4000, 0x0FFF_FFFF] {
+            let command = Command::Wait(Value::Const(value as i32));
+            let bytes = command.to_bytes();
+            assert_eq!(Command::decode(&bytes, 0), Ok((command, bytes.len())));
+        }
+        assert_eq!(Command::Wait(Value::Const(0x80)).to_bytes(), vec![0x80, 0x81, 0x00]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Command::decode(&[0x3C, 0x64], 0), Err(DecodeError::UnexpectedEnd { offset: 2 }));
+        assert_eq!(Command::decode(&[0xB5, 0x00], 0), Err(DecodeError::UnknownCommand { offset: 0, opcode: 0xB5 }));
+        assert_eq!(Command::decode(&[0xA0, 0x89, 0, 0, 0], 0), Err(DecodeError::InvalidPrefix { offset: 1 }));
+    }
+}
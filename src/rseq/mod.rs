@@ -0,0 +1,182 @@
+#![allow(unused)]
+
+pub mod command;
+pub mod text;
+
+use crate::common::*;
+use binread::BinRead;
+use std::io::{self, Write};
+use std::ops::Deref;
+
+pub use command::{Command, Param, Value, VarOp};
+
+/// Sequence, a MIDI-like command stream played with the instruments of an RBNK.
+#[derive(BinRead)]
+pub struct Rseq {
+    #[br(assert(&header.magic == b"RSEQ"))]
+    pub header: FileHeader,
+    #[br(is_big = header.endian == Endian::Big)]
+    pub data: BlockPtr<DataBlock>,
+    #[br(is_big = header.endian == Endian::Big)]
+    pub label: BlockPtr<LabelBlock>,
+}
+
+#[derive(BinRead)]
+pub struct DataBlock {
+    #[br(assert(&header.magic == b"DATA"))]
+    pub header: BlockHeader,
+    pub data_offset: u32, // from the start of the block, usually 0xC
+    // offsets in commands are relative to the start of this
+    #[br(pad_before = data_offset.saturating_sub(0xC), count = header.size.saturating_sub(data_offset))]
+    pub commands: Vec<u8>,
+}
+
+#[derive(BinRead)]
+pub struct LabelBlock {
+    #[br(assert(&header.magic == b"LABL"))]
+    pub header: BlockHeader,
+    // indexed by SeqDetails::seq_label_entry
+    pub labels: Table<r32<Label>>,
+}
+
+#[derive(BinRead, Debug)]
+pub struct Label {
+    pub offset: u32, // into DataBlock::commands
+    pub name_len: u32,
+    #[br(count = name_len)]
+    pub name: Vec<u8>,
+}
+
+impl Label {
+    pub fn name(&self) -> String {
+        String::from_utf8_lossy(&self.name).into_owned()
+    }
+}
+
+impl Rseq {
+    pub fn commands(&self) -> &[u8] {
+        &self.data.block.commands
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = &Label> {
+        self.label.block.labels.0.iter().map(Deref::deref)
+    }
+
+    pub fn label(&self, index: usize) -> Option<&Label> {
+        self.label.block.labels.0.get(index).map(Deref::deref)
+    }
+
+    pub fn find_label(&self, name: &str) -> Option<&Label> {
+        self.labels().find(|label| label.name == name.as_bytes())
+    }
+
+    /// The command data without the zero padding at the end of the DATA block.
+    ///
+    /// Only zeros that `write_rseq` would add back are removed.
+    pub fn trimmed_commands(&self) -> &[u8] {
+        let data = &self.data.block;
+        let mut commands = &data.commands[..];
+        if data.data_offset == DATA_OFFSET {
+            while let Some((0, rest)) = commands.split_last() {
+                if align_up(DATA_OFFSET as usize + rest.len(), 0x20) != data.header.size as usize {
+                    break;
+                }
+                commands = rest;
+            }
+        }
+        commands
+    }
+
+    /// Converts the commands and labels to text, see `text` for the syntax.
+    pub fn disassemble(&self) -> String {
+        let labels: Vec<(String, u32)> = self.labels().map(|label| (label.name(), label.offset)).collect();
+        text::disassemble(self.trimmed_commands(), &labels)
+    }
+}
+
+const DATA_OFFSET: u32 = 0xC;
+
+/// Writes a sequence file from command data and labels (name, offset into the commands).
+pub fn write_rseq<W: Write>(writer: &mut W, commands: &[u8], labels: &[(String, u32)]) -> io::Result<()> {
+    // LABL body: offset table, then the labels, each padded to 4 bytes
+    let mut label_body = Vec::new();
+    label_body.extend_from_slice(&(labels.len() as u32).to_be_bytes());
+    let mut label_offset = 4 + labels.len() * 4;
+    for (name, _) in labels {
+        label_body.extend_from_slice(&(label_offset as u32).to_be_bytes());
+        label_offset += align_up(8 + name.len() + 1, 4);
+    }
+    for (name, offset) in labels {
+        label_body.extend_from_slice(&offset.to_be_bytes());
+        label_body.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        label_body.extend_from_slice(name.as_bytes());
+        label_body.resize(align_up(label_body.len() + 1, 4), 0);
+    }
+
+    let mut container = ContainerWriter::new(*b"RSEQ", 0x0100);
+    let data = container.add_block(*b"DATA", DATA_OFFSET as usize - 8 + commands.len());
+    let label = container.add_block(*b"LABL", label_body.len());
+
+    container.write_header(writer)?;
+    container.write_block(writer, data, |body| {
+        body.write_all(&DATA_OFFSET.to_be_bytes())?;
+        body.write_all(commands)
+    })?;
+    container.write_block(writer, label, |body| body.write_all(&label_body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binread::BinReaderExt;
+    use binread::io::Cursor;
+
+    #[test]
+    fn parse() {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"RSEQ");
+        data.extend_from_slice(&[0xFE, 0xFF, 0x01, 0x00]);
+        data.extend_from_slice(&0x60u32.to_be_bytes());
+        data.extend_from_slice(&[0, 0x20, 0, 2]);
+        for block in [0x20u32, 0x20, 0x40, 0x20] {
+            data.extend_from_slice(&block.to_be_bytes());
+        }
+        data.extend_from_slice(b"DATA");
+        data.extend_from_slice(&0x20u32.to_be_bytes());
+        data.extend_from_slice(&0xCu32.to_be_bytes());
+        data.extend_from_slice(&[0x80, 0x30, 0x3C, 0x64, 0x30, 0xFF]); // wait, note, fin
+        data.resize(0x40, 0);
+        data.extend_from_slice(b"LABL");
+        data.extend_from_slice(&0x20u32.to_be_bytes());
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&8u32.to_be_bytes());
+        data.extend_from_slice(&2u32.to_be_bytes()); // label offset
+        data.extend_from_slice(&5u32.to_be_bytes());
+        data.extend_from_slice(b"SEQ_A");
+        data.resize(0x60, 0);
+
+        let rseq: Rseq = Cursor::new(&data).read_be().unwrap();
+        assert_eq!(rseq.commands().len(), 0x14);
+        assert_eq!(rseq.trimmed_commands(), &data[0x2C..0x32]);
+        assert_eq!(rseq.labels().count(), 1);
+        let label = rseq.label(0).unwrap();
+        assert_eq!((label.name(), label.offset), ("SEQ_A".to_string(), 2));
+        assert_eq!(rseq.find_label("SEQ_A").map(|label| label.offset), Some(2));
+    }
+
+    #[test]
+    fn write_read_back() {
+        let commands = [0xE1, 0x00, 0x78, 0x3C, 0x64, 0x30, 0x00, 0xFF, 0x00];
+        let labels = vec![("SEQ_A".to_string(), 0), ("SEQ_LONG_NAME".to_string(), 3)];
+        let mut out = Vec::new();
+        write_rseq(&mut out, &commands, &labels).unwrap();
+
+        let rseq: Rseq = Cursor::new(&out).read_be().unwrap();
+        assert_eq!(rseq.trimmed_commands(), &commands[..8]);
+        assert_eq!(rseq.find_label("SEQ_LONG_NAME").map(|label| label.offset), Some(3));
+        assert_eq!(rseq.labels().count(), 2);
+
+        let assembled = text::assemble(&rseq.disassemble()).unwrap();
+        assert_eq!(assembled.to_rseq(), out);
+    }
+}
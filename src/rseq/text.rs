@@ -0,0 +1,504 @@
+//! A text assembly syntax for RSEQ command streams.
+//!
+//! Each line holds a label (`name:`), a command, or a directive. Commands use the names from
+//! `command.rs`, with notes written as `cn4 100, 48` (key, velocity, length; `cn4` is key 60).
+//! The last argument can be a constant, a variable (`$32`) or a random range (`rand(0, 16)`),
+//! and a command can be made conditional with an `if` in front of it.
+//!
+//! Directives:
+//! - `.export name` adds a sequence label to the LABL block, in the order they're declared.
+//!   `.export name 0x1234` gives the offset directly, for labels outside of the command data.
+//! - `.byte 0x12, 0x34` inserts raw bytes, used for data that can't be decoded or wouldn't
+//!   be encoded the same way (like lengths that don't use the shortest encoding).
+//!
+//! `; comments` are ignored. Disassembling and assembling an unmodified sequence gives back the
+//! same bytes.
+
+use super::command::{Command, Param, Value, VarOp};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt::{self, Write};
+
+const NOTE_NAMES: [&str; 12] = ["cn", "cs", "dn", "ds", "en", "fn", "fs", "gn", "gs", "an", "as", "bn"];
+
+// bytes per `.byte` line
+const BYTES_PER_LINE: usize = 16;
+
+/// Name of a key, in octaves starting at -1 (`m1`).
+pub fn note_name(key: u8) -> String {
+    let octave = key as i32 / 12 - 1;
+    let octave = if octave < 0 { format!("m{}", -octave) } else { octave.to_string() };
+    format!("{}{}", NOTE_NAMES[key as usize % 12], octave)
+}
+
+pub fn parse_note(name: &str) -> Option<u8> {
+    if name.len() < 3 || !name.is_char_boundary(2) {
+        return None;
+    }
+    let note = NOTE_NAMES.iter().position(|&note| note == &name[..2])? as i32;
+    let octave = match &name[2..] {
+        "m1" => -1,
+        octave if octave.len() == 1 => octave.parse::<i32>().ok()?,
+        _ => return None
+    };
+    let key = (octave + 1) * 12 + note;
+    if (0..=127).contains(&key) { Some(key as u8) } else { None }
+}
+
+fn branch_target(command: &Command) -> Option<u32> {
+    match command {
+        Command::OpenTrack { offset, .. } | Command::Jump(offset) | Command::Call(offset) => Some(*offset),
+        Command::If(command) => branch_target(command),
+        _ => None
+    }
+}
+
+enum Line {
+    Command(Command),
+    Bytes(usize, usize), // range of the command data
+}
+
+/// Splits the command data into commands and raw bytes, never crossing a boundary.
+fn decode_lines(data: &[u8], boundaries: &BTreeSet<u32>) -> Vec<(usize, Line)> {
+    let mut lines = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let next = boundaries.range(pos as u32 + 1..).next()
+            .map(|&next| next as usize)
+            .unwrap_or(data.len())
+            .min(data.len());
+        match Command::decode(&data[..next], pos) {
+            Ok((command, len)) if command.to_bytes() == data[pos..pos + len] => {
+                lines.push((pos, Line::Command(command)));
+                pos += len;
+            }
+            Ok((_, len)) => {
+                lines.push((pos, Line::Bytes(pos, pos + len)));
+                pos += len;
+            }
+            Err(_) => {
+                lines.push((pos, Line::Bytes(pos, next)));
+                pos = next;
+            }
+        }
+    }
+    lines
+}
+
+fn format_value(value: Value) -> String {
+    match value {
+        Value::Const(value) => value.to_string(),
+        Value::Random { min, max } => format!("rand({}, {})", min, max),
+        Value::Variable(var) => format!("${}", var),
+    }
+}
+
+fn format_command(command: &Command, names: &BTreeMap<u32, Vec<String>>) -> String {
+    let target = |offset: &u32| match names.get(offset) {
+        Some(names) => names[0].clone(),
+        None => format!("0x{:06X}", offset)
+    };
+    match command {
+        Command::Note { key, velocity, length } => format!("{} {}, {}", note_name(*key), velocity, format_value(*length)),
+        Command::Wait(value) => format!("wait {}", format_value(*value)),
+        Command::Program(value) => format!("prg {}", format_value(*value)),
+        Command::OpenTrack { track, offset } => format!("opentrack {}, {}", track, target(offset)),
+        Command::Jump(offset) => format!("jump {}", target(offset)),
+        Command::Call(offset) => format!("call {}", target(offset)),
+        Command::Param(param, value) => format!("{} {}", param.name(), format_value(*value)),
+        Command::Var { op, var, value } => format!("{} ${}, {}", op.name(), var, format_value(*value)),
+        Command::UserProc(proc_id) => format!("userproc 0x{:04X}", proc_id),
+        Command::LoopEnd => "loop_end".to_string(),
+        Command::Return => "ret".to_string(),
+        Command::AllocTrack(mask) => format!("alloctrack 0x{:04X}", mask),
+        Command::Fin => "fin".to_string(),
+        Command::If(command) => format!("if {}", format_command(command, names)),
+    }
+}
+
+/// Converts command data and its labels (name, offset) to text.
+pub fn disassemble(data: &[u8], labels: &[(String, u32)]) -> String {
+    // every label and branch target has to start a line, so decode again until none are missed
+    let mut boundaries: BTreeSet<u32> = labels.iter()
+        .map(|(_, offset)| *offset)
+        .filter(|&offset| offset as usize <= data.len())
+        .collect();
+    let lines = loop {
+        let lines = decode_lines(data, &boundaries);
+        let missed: Vec<u32> = lines.iter()
+            .filter_map(|(_, line)| match line {
+                Line::Command(command) => branch_target(command),
+                Line::Bytes(..) => None
+            })
+            .filter(|&offset| offset as usize <= data.len() && !boundaries.contains(&offset))
+            .collect();
+        if missed.is_empty() {
+            break lines;
+        }
+        boundaries.extend(missed);
+    };
+
+    let mut names: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+    for (name, offset) in labels {
+        if *offset as usize <= data.len() {
+            names.entry(*offset).or_default().push(name.clone());
+        }
+    }
+    for &offset in &boundaries {
+        names.entry(offset).or_insert_with(|| vec![format!("loc_{:06X}", offset)]);
+    }
+
+    let mut out = String::new();
+    for (name, offset) in labels {
+        if *offset as usize <= data.len() {
+            writeln!(out, ".export {}", name).unwrap();
+        } else {
+            writeln!(out, ".export {} 0x{:06X}", name, offset).unwrap();
+        }
+    }
+
+    let label_lines = |out: &mut String, offset: usize| {
+        if let Some(names) = names.get(&(offset as u32)) {
+            out.push('\n');
+            for name in names {
+                writeln!(out, "{}:", name).unwrap();
+            }
+        }
+    };
+    for (offset, line) in &lines {
+        label_lines(&mut out, *offset);
+        match line {
+            Line::Command(command) => writeln!(out, "    {}", format_command(command, &names)).unwrap(),
+            Line::Bytes(start, end) => {
+                for chunk in data[*start..*end].chunks(BYTES_PER_LINE) {
+                    let bytes: Vec<String> = chunk.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                    writeln!(out, "    .byte {}", bytes.join(", ")).unwrap();
+                }
+            }
+        }
+    }
+    label_lines(&mut out, data.len());
+    out
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AsmError {
+    pub line: usize, // 1-based
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// Assembled command data and sequence labels, see `Assembled::to_rseq` for a complete file.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Assembled {
+    pub commands: Vec<u8>,
+    pub labels: Vec<(String, u32)>,
+}
+
+impl Assembled {
+    pub fn to_rseq(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        super::write_rseq(&mut out, &self.commands, &self.labels).unwrap();
+        out
+    }
+}
+
+/// Splits arguments at commas that aren't inside parentheses.
+fn split_args(text: &str) -> Vec<&str> {
+    let mut args = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (idx, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(text[start..idx].trim());
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    if !text.trim().is_empty() {
+        args.push(text[start..].trim());
+    }
+    args
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn parse_int(text: &str) -> Result<i64, String> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text)
+    };
+    let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse::<i64>()
+    }.map_err(|_| format!("invalid number '{}'", text))?;
+    Ok(if negative { -value } else { value })
+}
+
+fn parse_ranged(text: &str, min: i64, max: i64) -> Result<i64, String> {
+    let value = parse_int(text)?;
+    if value < min || value > max {
+        return Err(format!("{} is out of range ({} to {})", value, min, max));
+    }
+    Ok(value)
+}
+
+fn parse_var(text: &str) -> Result<u8, String> {
+    let var = text.strip_prefix('$').ok_or_else(|| format!("expected a variable like $0, found '{}'", text))?;
+    Ok(parse_ranged(var, 0, 255)? as u8)
+}
+
+fn parse_value(text: &str, arg: super::command::Arg) -> Result<Value, String> {
+    use super::command::Arg;
+    if text.starts_with('$') {
+        return Ok(Value::Variable(parse_var(text)?));
+    }
+    if let Some(range) = text.strip_prefix("rand(").and_then(|rest| rest.strip_suffix(')')) {
+        return match split_args(range)[..] {
+            [min, max] => Ok(Value::Random {
+                min: parse_ranged(min, i16::MIN as i64, i16::MAX as i64)? as i16,
+                max: parse_ranged(max, i16::MIN as i64, i16::MAX as i64)? as i16,
+            }),
+            _ => Err("rand takes a minimum and a maximum".to_string())
+        };
+    }
+    let (min, max) = match arg {
+        Arg::U8 => (0, 255),
+        Arg::S8 => (-128, 127),
+        Arg::S16 => (i16::MIN as i64, i16::MAX as i64),
+        Arg::VarLen => (0, 0x0FFF_FFFF),
+    };
+    Ok(Value::Const(parse_ranged(text, min, max)? as i32))
+}
+
+/// A branch target, either a label or an offset.
+enum Target<'a> {
+    Label(&'a str),
+    Offset(u32),
+}
+
+fn parse_target(text: &str) -> Result<Target<'_>, String> {
+    if is_identifier(text) {
+        Ok(Target::Label(text))
+    } else {
+        Ok(Target::Offset(parse_ranged(text, 0, 0xFF_FFFF)? as u32))
+    }
+}
+
+/// Parses a command, returning it with its branch target if it has one.
+fn parse_command(text: &str) -> Result<(Command, Option<Target<'_>>), String> {
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(idx) => (&text[..idx], text[idx..].trim()),
+        None => (text, "")
+    };
+    if mnemonic == "if" {
+        let (command, target) = parse_command(rest)?;
+        return Ok((Command::If(Box::new(command)), target));
+    }
+
+    let args = split_args(rest);
+    let expect = |count: usize| if args.len() == count {
+        Ok(())
+    } else {
+        Err(format!("{} takes {} argument(s), found {}", mnemonic, count, args.len()))
+    };
+    use super::command::Arg;
+
+    if let Some(key) = parse_note(mnemonic) {
+        expect(2)?;
+        let velocity = parse_ranged(args[0], 0, 127)? as u8;
+        return Ok((Command::Note { key, velocity, length: parse_value(args[1], Arg::VarLen)? }, None));
+    }
+    if let Some(param) = Param::from_name(mnemonic) {
+        expect(1)?;
+        return Ok((Command::Param(param, parse_value(args[0], param.arg())?), None));
+    }
+    if let Some(op) = VarOp::from_name(mnemonic) {
+        expect(2)?;
+        return Ok((Command::Var { op, var: parse_var(args[0])?, value: parse_value(args[1], op.arg())? }, None));
+    }
+
+    let command = match mnemonic {
+        "wait" => { expect(1)?; Command::Wait(parse_value(args[0], Arg::VarLen)?) }
+        "prg" => { expect(1)?; Command::Program(parse_value(args[0], Arg::VarLen)?) }
+        "opentrack" => {
+            expect(2)?;
+            let track = parse_ranged(args[0], 0, 15)? as u8;
+            return Ok((Command::OpenTrack { track, offset: 0 }, Some(parse_target(args[1])?)));
+        }
+        "jump" | "call" => {
+            expect(1)?;
+            let command = if mnemonic == "jump" { Command::Jump(0) } else { Command::Call(0) };
+            return Ok((command, Some(parse_target(args[0])?)));
+        }
+        "userproc" => { expect(1)?; Command::UserProc(parse_ranged(args[0], 0, 0xFFFF)? as u16) }
+        "loop_end" => { expect(0)?; Command::LoopEnd }
+        "ret" => { expect(0)?; Command::Return }
+        "alloctrack" => { expect(1)?; Command::AllocTrack(parse_ranged(args[0], 0, 0xFFFF)? as u16) }
+        "fin" => { expect(0)?; Command::Fin }
+        _ => return Err(format!("unknown command '{}'", mnemonic))
+    };
+    Ok((command, None))
+}
+
+fn set_target(command: &mut Command, target: u32) {
+    match command {
+        Command::OpenTrack { offset, .. } | Command::Jump(offset) | Command::Call(offset) => *offset = target,
+        Command::If(command) => set_target(command, target),
+        _ => {}
+    }
+}
+
+/// Assembles text into command data and sequence labels.
+pub fn assemble(text: &str) -> Result<Assembled, AsmError> {
+    let mut commands = Vec::new();
+    let mut labels: HashMap<&str, u32> = HashMap::new();
+    let mut exports: Vec<(usize, &str, Option<u32>)> = Vec::new();
+    // (line, position of the command, command, label)
+    let mut fixups: Vec<(usize, usize, Command, &str)> = Vec::new();
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_number = idx + 1;
+        let error = |message: String| AsmError { line: line_number, message };
+        let line = line.split(';').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_suffix(':') {
+            if !is_identifier(name) {
+                return Err(error(format!("invalid label name '{}'", name)));
+            }
+            if labels.insert(name, commands.len() as u32).is_some() {
+                return Err(error(format!("label '{}' is defined twice", name)));
+            }
+        } else if let Some(directive) = line.strip_prefix('.') {
+            let (name, rest) = match directive.find(char::is_whitespace) {
+                Some(idx) => (&directive[..idx], directive[idx..].trim()),
+                None => (directive, "")
+            };
+            match name {
+                "byte" => for byte in split_args(rest) {
+                    commands.push(parse_ranged(byte, 0, 255).map_err(error)? as u8);
+                },
+                "export" => {
+                    let mut parts = rest.split_whitespace();
+                    let label = parts.next().filter(|label| is_identifier(label))
+                        .ok_or_else(|| error("expected a label name".to_string()))?;
+                    let offset = match parts.next() {
+                        Some(offset) => Some(parse_ranged(offset, 0, u32::MAX as i64).map_err(error)? as u32),
+                        None => None
+                    };
+                    exports.push((line_number, label, offset));
+                }
+                _ => return Err(error(format!("unknown directive '.{}'", name)))
+            }
+        } else {
+            let (command, target) = parse_command(line).map_err(error)?;
+            let mut command = command;
+            match target {
+                Some(Target::Label(label)) => {
+                    // the target is filled in once every label is known
+                    fixups.push((line_number, commands.len(), command.clone(), label));
+                }
+                Some(Target::Offset(offset)) => set_target(&mut command, offset),
+                None => {}
+            }
+            command.encode(&mut commands);
+        }
+    }
+
+    for (line, position, mut command, label) in fixups {
+        let offset = *labels.get(label).ok_or_else(|| AsmError { line, message: format!("unknown label '{}'", label) })?;
+        set_target(&mut command, offset);
+        let bytes = command.to_bytes();
+        commands[position..position + bytes.len()].copy_from_slice(&bytes);
+    }
+
+    let labels = exports.into_iter().map(|(line, label, offset)| {
+        let offset = match offset {
+            Some(offset) => offset,
+            None => *labels.get(label).ok_or_else(|| AsmError { line, message: format!("unknown label '{}'", label) })?
+        };
+        Ok((label.to_string(), offset))
+    }).collect::<Result<_, AsmError>>()?;
+
+    Ok(Assembled { commands, labels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notes() {
+        assert_eq!(note_name(60), "cn4");
+        assert_eq!(note_name(0), "cnm1");
+        assert_eq!(note_name(127), "gn9");
+        for key in 0..=127 {
+            assert_eq!(parse_note(&note_name(key)), Some(key));
+        }
+        assert_eq!(parse_note("an9"), None);
+        assert_eq!(parse_note("pan"), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = [
+            0xFE, 0x00, 0x03, // alloctrack
+            0x88, 0x01, 0x00, 0x00, 0x1C, // opentrack 1
+            0xE1, 0x00, 0x78, // tempo 120
+            0x3C, 0x64, 0x80, 0x30, // note with a length that isn't minimally encoded
+            0xA0, 0x80, 0x00, 0x10, 0x00, 0x20, // wait random
+            0xA2, 0xF0, 0x90, 0x20, 0xFF, 0xFE, // if cmp_eq
+            0x89, 0x00, 0x00, 0x08, // jump, split by the opentrack target
+            0xFF,
+            0xA1, 0xC3, 0x21, // transpose $33
+            0xB5, 0x01, // unknown
+        ];
+        let labels = vec![("SEQ_A".to_string(), 0), ("SEQ_FAR".to_string(), 0x100)];
+        let text = disassemble(&data, &labels);
+        assert!(text.contains("    if cmp_eq $32, -2\n"), "{}", text);
+        assert!(text.contains("    wait rand(16, 32)\n"), "{}", text);
+        assert!(text.contains("opentrack 1, loc_00001C"), "{}", text);
+        assert!(text.contains(".byte 0x3C, 0x64, 0x80, 0x30"), "{}", text);
+
+        let assembled = assemble(&text).unwrap();
+        assert_eq!(&assembled.commands[..], &data[..]);
+        assert_eq!(assembled.labels, labels);
+    }
+
+    #[test]
+    fn hand_written() {
+        let text = "
+            .export SEQ_MAIN
+            SEQ_MAIN:
+                tempo 150
+            loop:
+                prg 3       ; piano
+                if an3 100, $2
+                jump loop
+        ";
+        let assembled = assemble(text).unwrap();
+        assert_eq!(assembled.commands, vec![0xE1, 0x00, 0x96, 0x81, 0x03, 0xA2, 0xA1, 0x39, 0x64, 0x02, 0x89, 0x00, 0x00, 0x03]);
+        assert_eq!(assembled.labels, vec![("SEQ_MAIN".to_string(), 0)]);
+
+        assert_eq!(assemble("jump nowhere").unwrap_err(), AsmError { line: 1, message: "unknown label 'nowhere'".into() });
+        assert_eq!(assemble("\npan 300").unwrap_err().line, 2);
+        assert!(assemble("tempo").is_err());
+    }
+}
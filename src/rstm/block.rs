@@ -0,0 +1,120 @@
+#![allow(unused)]
+
+use crate::common::*;
+use binread::BinRead;
+
+#[derive(BinRead)]
+pub struct HeadBlock {
+    #[br(assert(&header.magic == b"HEAD"))]
+    pub header: BlockHeader,
+    pub stream_info: Reference<StreamInfo>,
+    pub track_table: Reference<TrackTable>,
+    pub channel_table: Reference<ChannelTable>,
+}
+
+#[derive(BinRead, Clone, Debug)]
+pub struct StreamInfo {
+    pub encoding: SoundEncoding,
+    #[br(map = |x: u8| x != 0)]
+    pub looping: bool,
+    pub channel_count: u8,
+    pub padding: u8,
+    pub sample_rate: u16,
+    pub padding2: u16,
+    pub loop_start: u32,
+    pub sample_count: u32,
+    pub data_offset: u32, // absolute, points past the DATA block header
+    pub block_count: u32,
+    pub block_size: u32, // per channel
+    pub block_samples: u32,
+    pub final_block_size: u32, // without padding
+    pub final_block_samples: u32,
+    pub final_block_padded_size: u32,
+    pub adpc_interval_samples: u32, // samples per ADPC entry, usually == block_samples
+    pub adpc_interval_bytes: u32 // bytes per ADPC entry (per channel)
+}
+
+impl StreamInfo {
+    /// Size of a single channel's slice of the given block, including padding.
+    pub fn block_len(&self, block: u32) -> u32 {
+        if block + 1 == self.block_count {
+            self.final_block_padded_size
+        } else {
+            self.block_size
+        }
+    }
+
+    pub fn samples_in_block(&self, block: u32) -> u32 {
+        if block + 1 == self.block_count {
+            self.final_block_samples
+        } else {
+            self.block_samples
+        }
+    }
+}
+
+#[derive(BinRead)]
+pub struct TrackTable {
+    pub count: u8,
+    pub track_type: u8, // 0 = simple, 1 = extended (volume + pan)
+    #[br(pad_before = 2, count = count)]
+    pub tracks: Vec<MultiReference<TrackInfo>>,
+}
+
+#[derive(BinRead, Clone, Debug)]
+#[br(import(ty: u8, a: ()))]
+pub struct TrackInfo {
+    #[br(if(ty == 1))]
+    pub extended: Option<TrackVolume>,
+    pub channel_count: u8,
+    #[br(count = channel_count)]
+    pub channels: Vec<u8>,
+}
+
+#[derive(BinRead, Clone, Debug)]
+pub struct TrackVolume {
+    pub volume: u8,
+    pub pan: u8,
+    pub padding: u16,
+    pub reserved: u32
+}
+
+#[derive(BinRead)]
+pub struct ChannelTable {
+    #[br(pad_after = 3)]
+    pub count: u8,
+    #[br(count = count)]
+    pub channels: Vec<Reference<ChannelInfo>>,
+}
+
+#[derive(BinRead)]
+pub struct ChannelInfo {
+    // still present for PCM streams, just zeroed out
+    pub adpcm: Reference<AdpcmInfo>,
+}
+
+#[derive(BinRead)]
+pub struct AdpcBlock {
+    #[br(assert(&header.magic == b"ADPC"), assert(header.size >= 8))]
+    pub header: BlockHeader,
+    // indexed by (block * channel_count + channel), may contain trailing padding entries
+    #[br(count = (header.size - 8) / 4)]
+    pub history: Vec<AdpcHistory>,
+}
+
+#[derive(BinRead, Clone, Copy, Debug, Default)]
+pub struct AdpcHistory {
+    pub hist1: i16,
+    pub hist2: i16,
+}
+
+#[derive(BinRead)]
+pub struct DataBlock {
+    #[br(assert(&header.magic == b"DATA"))]
+    pub header: BlockHeader,
+    #[br(assert(data_offset >= 4))]
+    pub data_offset: u32, // from the end of the block header, usually 0x18
+    // interleaved per block: [block 0 ch 0][block 0 ch 1]...[block 1 ch 0]...
+    #[br(pad_before = data_offset - 4, args(header.size, data_offset.saturating_add(8)), parse_with = read_block_rest)]
+    pub data: Vec<u8>,
+}
@@ -0,0 +1,389 @@
+use crate::common::*;
+use crate::codec::{self, adpcm, adpcm_encoder, pcm};
+use crate::wav::{Wav, LoopPoints};
+use super::block::AdpcHistory;
+use std::io::{self, Write};
+
+/// Bytes per channel in each interleaved block, except for the last one.
+pub const BLOCK_SIZE: usize = 0x2000;
+
+/// A stream ready to be written as an RSTM, with each channel's data stored contiguously.
+pub struct EncodedStream {
+    pub encoding: SoundEncoding,
+    pub sample_rate: u16,
+    pub sample_count: u32,
+    pub loop_start: Option<u32>,
+    pub tracks: Vec<StreamTrack>,
+    pub channels: Vec<EncodedStreamChannel>,
+}
+
+#[derive(Clone, Debug)]
+pub struct StreamTrack {
+    pub volume: u8,
+    pub pan: u8,
+    /// Indices into `EncodedStream::channels`.
+    pub channels: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct EncodedStreamChannel {
+    pub data: Vec<u8>,
+    pub adpcm: AdpcmInfo,
+    /// History at the start of each block, see the ADPC block.
+    pub history: Vec<AdpcHistory>,
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+impl StreamTrack {
+    pub fn new(channels: Vec<u8>) -> StreamTrack {
+        StreamTrack { volume: 0x7F, pan: 0x40, channels }
+    }
+}
+
+impl EncodedStream {
+    /// Encodes each WAV as its own track. All inputs need the same sample rate, shorter ones
+    /// are padded with silence. The loop is taken from the first WAV that has one.
+    pub fn encode(wavs: &[Wav], encoding: SoundEncoding) -> io::Result<EncodedStream> {
+        let first = wavs.first().ok_or_else(|| invalid_input("no input"))?;
+        if wavs.iter().any(|wav| wav.sample_rate != first.sample_rate) {
+            return Err(invalid_input("all tracks need the same sample rate"));
+        }
+        if first.sample_rate > u16::MAX as u32 {
+            return Err(invalid_input("sample rate too high for RSTM"));
+        }
+
+        let loop_points = wavs.iter().filter_map(|wav| wav.loop_points).next();
+        let sample_count = wavs.iter().map(Wav::sample_count).max().unwrap_or(0);
+        let sample_count = loop_points.map(|p| (p.end as usize).min(sample_count)).unwrap_or(sample_count);
+        let loop_start = loop_points.map(|p| p.start).filter(|&start| (start as usize) < sample_count);
+
+        let mut tracks = Vec::new();
+        let mut samples = Vec::new();
+        for wav in wavs {
+            let start = samples.len() as u8;
+            for channel in &wav.channels {
+                let mut channel = channel.clone();
+                channel.resize(sample_count, 0);
+                samples.push(channel);
+            }
+            tracks.push(StreamTrack::new((start..samples.len() as u8).collect()));
+        }
+        if samples.len() > u8::MAX as usize {
+            return Err(invalid_input("too many channels"));
+        }
+
+        let block_samples = block_samples(encoding);
+        let block_count = sample_count.div_ceil(block_samples);
+
+        let channels = samples.iter().map(|samples| match encoding {
+            SoundEncoding::DspAdpcm => {
+                let encoded = adpcm_encoder::encode(samples, loop_start.map(|s| s as usize));
+                let history = (0..block_count).map(|block| {
+                    let history = encoded.history_at(block * block_samples);
+                    AdpcHistory { hist1: history.hist1, hist2: history.hist2 }
+                }).collect();
+                EncodedStreamChannel { data: encoded.data, adpcm: encoded.info, history }
+            }
+            SoundEncoding::SPcm16 => EncodedStreamChannel {
+                data: pcm::encode_pcm16(samples), adpcm: AdpcmInfo::default(), history: Vec::new()
+            },
+            SoundEncoding::SPcm8 => EncodedStreamChannel {
+                data: pcm::encode_pcm8(samples), adpcm: AdpcmInfo::default(), history: Vec::new()
+            }
+        }).collect();
+
+        Ok(EncodedStream {
+            encoding,
+            sample_rate: first.sample_rate as u16,
+            sample_count: sample_count as u32,
+            loop_start,
+            tracks,
+            channels,
+        })
+    }
+
+    /// Changes the loop without re-encoding, recomputing the ADPCM loop context of each channel.
+    ///
+    /// The loop end is also the end of the stream, so moving it earlier cuts off the rest.
+    pub fn set_loop(&mut self, loop_points: Option<LoopPoints>) -> io::Result<()> {
+        if let Some(points) = loop_points {
+            if points.start >= points.end {
+                return Err(invalid_input("the loop has to start before it ends"));
+            }
+            if points.end > self.sample_count {
+                return Err(invalid_input("the loop can't end after the stream"));
+            }
+            self.sample_count = points.end;
+            let len = codec::encoded_len(self.encoding, self.sample_count as usize);
+            let block_count = self.block_count();
+            for channel in &mut self.channels {
+                channel.data.truncate(len);
+                channel.history.truncate(block_count);
+            }
+        }
+
+        self.loop_start = loop_points.map(|points| points.start);
+        if self.encoding == SoundEncoding::DspAdpcm {
+            for channel in &mut self.channels {
+                adpcm::set_loop_context(&mut channel.adpcm, &channel.data, self.loop_start.map(|start| start as usize));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn block_samples(&self) -> usize {
+        block_samples(self.encoding)
+    }
+
+    pub fn block_count(&self) -> usize {
+        (self.sample_count as usize).div_ceil(self.block_samples())
+    }
+
+    fn final_block_samples(&self) -> usize {
+        match self.block_count() {
+            0 => 0,
+            count => self.sample_count as usize - (count - 1) * self.block_samples()
+        }
+    }
+
+    fn final_block_size(&self) -> usize {
+        codec::encoded_len(self.encoding, self.final_block_samples())
+    }
+
+    /// Whether an ADPC block is written.
+    fn has_history(&self) -> bool {
+        self.encoding == SoundEncoding::DspAdpcm
+    }
+
+    fn head_len(&self) -> usize {
+        let tracks: usize = self.tracks.iter().map(|track| align_up(9 + track.channels.len(), 4)).sum();
+        let track_table = 4 + self.tracks.len() * 8 + tracks;
+        let channel_table = 4 + self.channels.len() * (8 + 8 + AdpcmInfo::LENGTH);
+        3 * 8 + STREAM_INFO_LEN + track_table + channel_table
+    }
+
+    fn adpc_len(&self) -> usize {
+        if self.has_history() {
+            self.block_count() * self.channels.len() * 4
+        } else {
+            0
+        }
+    }
+
+    fn data_len(&self) -> usize {
+        match self.block_count() {
+            0 => 0,
+            count => ((count - 1) * BLOCK_SIZE + align_up(self.final_block_size(), 0x20)) * self.channels.len()
+        }
+    }
+
+    pub fn write_rstm<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut container = ContainerWriter::new(*b"RSTM", 0x0100);
+        let head = container.add_block(*b"HEAD", self.head_len());
+        // PCM streams leave the ADPC slot empty
+        let adpc = if self.has_history() {
+            container.add_block(*b"ADPC", self.adpc_len())
+        } else {
+            container.reserve_block()
+        };
+        let data = container.add_block(*b"DATA", 0x18 + self.data_len());
+        let data_location = (container.block_offset(data) + 0x20) as u32;
+
+        container.write_header(writer)?;
+        container.write_block(writer, head, |body| self.write_head(body, data_location))?;
+
+        if self.has_history() {
+            container.write_block(writer, adpc, |body| {
+                for block in 0..self.block_count() {
+                    for channel in &self.channels {
+                        let history = channel.history.get(block).copied().unwrap_or_default();
+                        body.write_all(&history.hist1.to_be_bytes())?;
+                        body.write_all(&history.hist2.to_be_bytes())?;
+                    }
+                }
+                Ok(())
+            })?;
+        }
+
+        container.write_block(writer, data, |body| {
+            body.write_all(&0x18u32.to_be_bytes())?;
+            body.write_all(&[0; 0x14])?;
+            self.write_data(body)
+        })
+    }
+
+    fn write_head<W: Write>(&self, writer: &mut W, data_location: u32) -> io::Result<()> {
+        fn reference<W: Write>(writer: &mut W, ty: u8, offset: usize) -> io::Result<()> {
+            writer.write_all(&[1, ty, 0, 0])?;
+            writer.write_all(&(offset as u32).to_be_bytes())
+        }
+
+        let track_sizes: Vec<usize> = self.tracks.iter().map(|track| align_up(9 + track.channels.len(), 4)).collect();
+        let stream_info_offset = 3 * 8;
+        let track_table_offset = stream_info_offset + STREAM_INFO_LEN;
+        let channel_table_offset = track_table_offset + 4 + self.tracks.len() * 8 + track_sizes.iter().sum::<usize>();
+
+        reference(writer, 0, stream_info_offset)?;
+        reference(writer, 0, track_table_offset)?;
+        reference(writer, 0, channel_table_offset)?;
+
+        // stream info
+        let block_count = self.block_count();
+        writer.write_all(&[self.encoding as u8, self.loop_start.is_some() as u8, self.channels.len() as u8, 0])?;
+        writer.write_all(&self.sample_rate.to_be_bytes())?;
+        writer.write_all(&[0, 0])?;
+        for value in &[
+            self.loop_start.unwrap_or(0),
+            self.sample_count,
+            data_location,
+            block_count as u32,
+            BLOCK_SIZE as u32,
+            self.block_samples() as u32,
+            self.final_block_size() as u32,
+            self.final_block_samples() as u32,
+            align_up(self.final_block_size(), 0x20) as u32,
+            self.block_samples() as u32,
+            4,
+        ] {
+            writer.write_all(&value.to_be_bytes())?;
+        }
+
+        // track table, always using the extended track info
+        writer.write_all(&[self.tracks.len() as u8, 1, 0, 0])?;
+        let mut offset = track_table_offset + 4 + self.tracks.len() * 8;
+        for size in &track_sizes {
+            reference(writer, 1, offset)?;
+            offset += size;
+        }
+        for (track, size) in self.tracks.iter().zip(&track_sizes) {
+            writer.write_all(&[track.volume, track.pan, 0, 0, 0, 0, 0, 0, track.channels.len() as u8])?;
+            writer.write_all(&track.channels)?;
+            writer.write_all(&vec![0; size - 9 - track.channels.len()])?;
+        }
+
+        // channel table
+        let channel_count = self.channels.len();
+        writer.write_all(&[channel_count as u8, 0, 0, 0])?;
+        let channel_info_offset = channel_table_offset + 4 + channel_count * 8;
+        let adpcm_offset = channel_info_offset + channel_count * 8;
+        for idx in 0..channel_count {
+            reference(writer, 0, channel_info_offset + idx * 8)?;
+        }
+        for idx in 0..channel_count {
+            reference(writer, 0, adpcm_offset + idx * AdpcmInfo::LENGTH)?;
+        }
+        for channel in &self.channels {
+            channel.adpcm.write(writer)?;
+        }
+        Ok(())
+    }
+
+    fn write_data<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let block_count = self.block_count();
+        for block in 0..block_count {
+            let start = block * BLOCK_SIZE;
+            let (len, padded) = if block + 1 == block_count {
+                (self.final_block_size(), align_up(self.final_block_size(), 0x20))
+            } else {
+                (BLOCK_SIZE, BLOCK_SIZE)
+            };
+
+            for channel in &self.channels {
+                let bytes = channel.data.get(start..(start + len).min(channel.data.len())).unwrap_or(&[]);
+                writer.write_all(bytes)?;
+                writer.write_all(&vec![0; padded - bytes.len()])?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_rstm(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_rstm(&mut out).expect("writing to a Vec can't fail");
+        out
+    }
+}
+
+const STREAM_INFO_LEN: usize = 0x34;
+
+fn block_samples(encoding: SoundEncoding) -> usize {
+    match encoding {
+        SoundEncoding::DspAdpcm => BLOCK_SIZE / adpcm::BYTES_PER_FRAME * adpcm::SAMPLES_PER_FRAME,
+        SoundEncoding::SPcm16 => BLOCK_SIZE / 2,
+        SoundEncoding::SPcm8 => BLOCK_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Rstm;
+    use binread::BinReaderExt;
+    use binread::io::Cursor;
+
+    fn tone(len: usize, freq: f64) -> Vec<i16> {
+        (0..len).map(|i| ((i as f64 * freq / 32000.0 * std::f64::consts::TAU).sin() * 12000.0) as i16).collect()
+    }
+
+    #[test]
+    fn multi_track_layout() {
+        let len = 40000; // a few blocks
+        let wavs = vec![
+            Wav::new(32000, vec![tone(len, 440.0), tone(len, 660.0)]).with_loop(1000, len as u32),
+            Wav::new(32000, vec![tone(len / 2, 220.0)]),
+        ];
+        let encoded = EncodedStream::encode(&wavs, SoundEncoding::DspAdpcm).unwrap();
+        assert_eq!(encoded.block_count(), 3);
+
+        let rstm: Rstm = Cursor::new(encoded.to_rstm()).read_be().unwrap();
+        let info = rstm.info();
+        assert_eq!(info.channel_count, 3);
+        assert_eq!(info.sample_count, len as u32);
+        assert!(info.looping);
+        assert_eq!(info.loop_start, 1000);
+
+        let tracks: Vec<_> = rstm.tracks().map(|track| track.channels.clone()).collect();
+        assert_eq!(tracks, vec![vec![0, 1], vec![2]]);
+
+        let decoded = rstm.decode().unwrap();
+        for (channel, encoded) in decoded.iter().zip(&encoded.channels) {
+            let expected = adpcm::decode(&encoded.data, &encoded.adpcm.coefs, Default::default(), len);
+            assert_eq!(channel, &expected);
+        }
+    }
+
+    #[test]
+    fn edit_loop() {
+        let len = 20000;
+        let samples = vec![tone(len, 440.0), tone(len, 330.0)];
+        let encoded = EncodedStream::encode(&[Wav::new(32000, samples.clone()).with_loop(1000, len as u32)], SoundEncoding::DspAdpcm).unwrap();
+        let rstm: Rstm = Cursor::new(encoded.to_rstm()).read_be().unwrap();
+
+        let mut edited = EncodedStream::from_rstm(&rstm).unwrap();
+        edited.set_loop(Some(LoopPoints { start: 5000, end: len as u32 })).unwrap();
+        let expected = EncodedStream::encode(&[Wav::new(32000, samples).with_loop(5000, len as u32)], SoundEncoding::DspAdpcm).unwrap();
+        assert_eq!(edited.to_rstm(), expected.to_rstm());
+
+        edited.set_loop(Some(LoopPoints { start: 100, end: 15000 })).unwrap();
+        let rstm: Rstm = Cursor::new(edited.to_rstm()).read_be().unwrap();
+        assert_eq!(rstm.loop_points(), Some(LoopPoints { start: 100, end: 15000 }));
+        let decoded = rstm.decode().unwrap();
+        assert_eq!(decoded[1].len(), 15000);
+
+        edited.set_loop(None).unwrap();
+        assert_eq!(edited.channels[0].adpcm.loop_hist1, 0);
+        assert!(edited.set_loop(Some(LoopPoints { start: 0, end: 20000 })).is_err());
+    }
+
+    #[test]
+    fn pcm16() {
+        let samples = tone(5000, 440.0);
+        let encoded = EncodedStream::encode(&[Wav::new(48000, vec![samples.clone()])], SoundEncoding::SPcm16).unwrap();
+        let rstm: Rstm = Cursor::new(encoded.to_rstm()).read_be().unwrap();
+        assert!(rstm.adpc.is_none());
+        assert_eq!(rstm.decode().unwrap(), vec![samples]);
+    }
+}
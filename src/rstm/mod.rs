@@ -0,0 +1,215 @@
+pub mod block;
+pub mod encode;
+pub mod stream;
+pub mod tracks;
+
+use crate::common::*;
+use crate::codec;
+use crate::wav::{Wav, LoopPoints};
+use block::{HeadBlock, AdpcBlock, DataBlock, StreamInfo, TrackInfo};
+use binread::BinRead;
+use std::io;
+use std::ops::Deref;
+
+#[derive(BinRead)]
+pub struct Rstm {
+    #[br(assert(&header.magic == b"RSTM"))]
+    pub header: FileHeader,
+    #[br(is_big = header.endian == Endian::Big)]
+    pub head: BlockPtr<HeadBlock>,
+    // PCM streams don't need an ADPC block, but still reserve the slot for it.
+    #[br(is_big = header.endian == Endian::Big, if(header.block_count >= 3))]
+    pub adpc: Option<BlockPtr<AdpcBlock>>,
+    #[br(is_big = header.endian == Endian::Big, pad_before = if header.block_count >= 3 { 0 } else { 8 })]
+    pub data: BlockPtr<DataBlock>,
+}
+
+impl Rstm {
+    pub fn info(&self) -> &StreamInfo {
+        self.head.block.stream_info.deref()
+    }
+
+    /// The loop, which always ends at the end of the stream.
+    pub fn loop_points(&self) -> Option<LoopPoints> {
+        let info = self.info();
+        if info.looping {
+            Some(LoopPoints { start: info.loop_start, end: info.sample_count })
+        } else {
+            None
+        }
+    }
+
+    pub fn tracks(&self) -> impl Iterator<Item = &TrackInfo> {
+        self.head.block.track_table.tracks.iter().map(Deref::deref)
+    }
+
+    pub fn adpcm_info(&self, channel: usize) -> Option<&AdpcmInfo> {
+        self.head.block.channel_table.channels.get(channel).map(|c| c.adpcm.deref())
+    }
+
+    /// Gathers the (still encoded) data of a single channel from the interleaved blocks.
+    pub fn channel_data(&self, channel: usize) -> Vec<u8> {
+        let info = self.info();
+        let data = &self.data.block.data;
+        let stride = info.block_size as usize * info.channel_count as usize;
+
+        let mut out = Vec::new();
+        for block in 0..info.block_count {
+            let len = info.block_len(block) as usize;
+            let start = block as usize * stride + channel * len;
+            match data.get(start..start + len) {
+                Some(bytes) => out.extend_from_slice(bytes),
+                None => break
+            }
+        }
+        out
+    }
+
+    pub fn decode_channel(&self, channel: usize) -> io::Result<Vec<i16>> {
+        let info = self.info();
+        let data = self.channel_data(channel);
+        let count = info.sample_count as usize;
+
+        codec::decode(info.encoding, &data, self.adpcm_info(channel), count)
+    }
+
+    pub fn decode(&self) -> io::Result<Vec<Vec<i16>>> {
+        (0..self.info().channel_count as usize).map(|ch| self.decode_channel(ch)).collect()
+    }
+
+    /// A single WAV containing every channel in the stream.
+    pub fn to_wav(&self) -> io::Result<Wav> {
+        Ok(self.with_loop(Wav::new(self.info().sample_rate as u32, self.decode()?)))
+    }
+
+    fn with_loop(&self, wav: Wav) -> Wav {
+        Wav { loop_points: self.loop_points(), ..wav }
+    }
+
+    /// One WAV per track, containing only the channels that belong to it.
+    pub fn track_wavs(&self) -> io::Result<Vec<Wav>> {
+        let decoded = self.decode()?;
+        Ok(self.tracks().map(|track| {
+            let channels = track.channels.iter()
+                .filter_map(|&ch| decoded.get(ch as usize).cloned())
+                .collect();
+            self.with_loop(Wav::new(self.info().sample_rate as u32, channels))
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::LoopPoints;
+    use binread::BinReaderExt;
+    use binread::io::Cursor;
+    use std::io::Write;
+
+    fn reference<W: Write>(writer: &mut W, offset: u32) {
+        writer.write_all(&[1, 0, 0, 0]).unwrap();
+        writer.write_all(&offset.to_be_bytes()).unwrap();
+    }
+
+    // block header plus body, padded to 0x20
+    fn block_size(body_len: usize) -> usize {
+        (8 + body_len + 0x1F) & !0x1F
+    }
+
+    // looping PCM16 stream with two channels in one track, split into a block of 4 samples
+    // and a final block of 2 (padded to 4)
+    fn test_rstm(data_size: Option<u32>) -> Vec<u8> {
+        let mut head = Vec::new();
+        reference(&mut head, 0x18);
+        reference(&mut head, 0x4C);
+        reference(&mut head, 0x5C);
+        head.extend_from_slice(&[1, 1, 2, 0]); // PCM16, looping, 2 channels
+        head.extend_from_slice(&32000u16.to_be_bytes());
+        head.extend_from_slice(&[0, 0]);
+        for value in &[2u32, 6, 0, 2, 8, 4, 4, 2, 8, 4, 4] {
+            head.extend_from_slice(&value.to_be_bytes());
+        }
+        head.extend_from_slice(&[1, 0, 0, 0]); // track table
+        reference(&mut head, 0x58);
+        head.extend_from_slice(&[2, 0, 1, 0]);
+        head.extend_from_slice(&[2, 0, 0, 0]); // channel table
+        reference(&mut head, 0x70);
+        reference(&mut head, 0x78);
+        reference(&mut head, 0x80);
+        reference(&mut head, 0x80);
+        head.extend_from_slice(&[0; 0x30]); // AdpcmInfo, unused for PCM
+
+        let samples: [i16; 16] = [1, 2, 3, 4, -1, -2, -3, -4, 5, 6, 0, 0, -5, -6, 0, 0];
+        let mut data = Vec::new();
+        data.extend_from_slice(&0x18u32.to_be_bytes());
+        data.extend_from_slice(&[0; 0x14]);
+        for sample in &samples {
+            data.extend_from_slice(&sample.to_be_bytes());
+        }
+
+        // header and block table padded to 0x40, then HEAD, the empty ADPC slot and DATA
+        let head_size = block_size(head.len());
+        let data_offset = 0x40 + head_size;
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RSTM");
+        out.extend_from_slice(&[0xFE, 0xFF, 0x01, 0x00]);
+        out.extend_from_slice(&((data_offset + block_size(data.len())) as u32).to_be_bytes());
+        out.extend_from_slice(&[0x00, 0x40, 0x00, 0x02]);
+        for value in &[0x40, head_size as u32, 0, 0, data_offset as u32, block_size(data.len()) as u32] {
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+        out.resize(0x40, 0);
+        for (magic, body) in &[(b"HEAD", &head), (b"DATA", &data)] {
+            let size = block_size(body.len());
+            out.extend_from_slice(*magic);
+            out.extend_from_slice(&(size as u32).to_be_bytes());
+            out.extend_from_slice(body);
+            out.resize(out.len() + size - 8 - body.len(), 0);
+        }
+
+        if let Some(size) = data_size {
+            out[data_offset + 4..data_offset + 8].copy_from_slice(&size.to_be_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn parse() {
+        let rstm: Rstm = Cursor::new(test_rstm(None)).read_be().unwrap();
+        assert!(rstm.adpc.is_none());
+        let info = rstm.info();
+        assert_eq!((info.encoding, info.channel_count, info.sample_rate), (SoundEncoding::SPcm16, 2, 32000));
+        assert_eq!((info.block_count, info.block_len(0), info.block_len(1)), (2, 8, 8));
+        assert_eq!((info.looping, info.loop_start, info.sample_count), (true, 2, 6));
+        assert_eq!(rstm.tracks().map(|track| track.channels.clone()).collect::<Vec<_>>(), vec![vec![0, 1]]);
+        assert_eq!(rstm.decode().unwrap(), vec![vec![1, 2, 3, 4, 5, 6], vec![-1, -2, -3, -4, -5, -6]]);
+    }
+
+    #[test]
+    fn to_wav() {
+        let rstm: Rstm = Cursor::new(test_rstm(None)).read_be().unwrap();
+        let mut bytes = Vec::new();
+        rstm.to_wav().unwrap().write(&mut bytes).unwrap();
+
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes([bytes[24], bytes[25], bytes[26], bytes[27]]), 32000);
+        // interleaved after the 44 byte header
+        let samples: Vec<i16> = bytes[44..68].chunks(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect();
+        assert_eq!(samples, vec![1, -1, 2, -2, 3, -3, 4, -4, 5, -5, 6, -6]);
+        // followed by the loop
+        assert_eq!(&bytes[68..72], b"smpl");
+        let wav = rstm.to_wav().unwrap();
+        assert_eq!(wav.loop_points, Some(LoopPoints { start: 2, end: 6 }));
+
+        let tracks = rstm.track_wavs().unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].channels.len(), 2);
+    }
+
+    #[test]
+    fn truncated_data_block() {
+        // smaller than the block header and data offset
+        assert!(Cursor::new(test_rstm(Some(0x10))).read_be::<Rstm>().is_err());
+        assert!(Cursor::new(test_rstm(Some(4))).read_be::<Rstm>().is_err());
+    }
+}
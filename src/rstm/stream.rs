@@ -0,0 +1,256 @@
+//! Decoding a stream straight from a reader one block at a time, so that long streams can be
+//! played or converted without holding the whole DATA block in memory.
+
+use super::block::{HeadBlock, AdpcBlock, AdpcHistory, StreamInfo, TrackInfo};
+use crate::codec;
+use crate::codec::adpcm::{Decoder, History};
+use crate::common::*;
+use crate::wav::LoopPoints;
+use binread::{BinRead, BinReaderExt, BinResult};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Deref;
+
+/// Everything in front of the DATA block.
+#[derive(BinRead)]
+pub struct RstmHead {
+    #[br(assert(&header.magic == b"RSTM"))]
+    pub header: FileHeader,
+    #[br(is_big = header.endian == Endian::Big)]
+    pub head: BlockPtr<HeadBlock>,
+    #[br(is_big = header.endian == Endian::Big, if(header.block_count >= 3))]
+    pub adpc: Option<BlockPtr<AdpcBlock>>,
+}
+
+/// Decodes the samples of a stream in order, reading one block of every channel at a time.
+///
+/// Iterating yields one sample per channel, [`StreamReader::read_samples`] whole chunks.
+pub struct StreamReader<R: Read + Seek> {
+    reader: R,
+    info: StreamInfo,
+    tracks: Vec<TrackInfo>,
+    decoders: Vec<Decoder>,
+    // history at the start of every block, from the ADPC block
+    history: Vec<AdpcHistory>,
+    // the block the decoders are positioned at
+    next_block: u32,
+    // decoded samples of the current block, one Vec per channel
+    buffers: Vec<Vec<i16>>,
+    block_start: usize,
+    position: usize, // into `buffers`
+}
+
+impl<R: Read + Seek> StreamReader<R> {
+    pub fn new(mut reader: R) -> BinResult<StreamReader<R>> {
+        reader.seek(SeekFrom::Start(0))?;
+        let head: RstmHead = reader.read_be()?;
+
+        let info = head.head.block.stream_info.deref().clone();
+        let tracks = head.head.block.track_table.tracks.iter().map(|track| track.deref().clone()).collect();
+        let decoders = head.head.block.channel_table.channels.iter()
+            .map(|channel| Decoder::from_info(&channel.adpcm))
+            .collect::<Vec<_>>();
+        let mut history = head.adpc.map(|adpc| adpc.block.history.clone()).unwrap_or_default();
+        // the first entries are usually zero anyway, but the channel info is authoritative
+        let start = decoders.iter().map(|decoder| AdpcHistory { hist1: decoder.history.hist1, hist2: decoder.history.hist2 });
+        history.splice(..decoders.len().min(history.len()), start);
+
+        Ok(StreamReader {
+            reader,
+            info,
+            tracks,
+            decoders,
+            history,
+            next_block: 0,
+            buffers: Vec::new(),
+            block_start: 0,
+            position: 0,
+        })
+    }
+
+    pub fn info(&self) -> &StreamInfo {
+        &self.info
+    }
+
+    pub fn tracks(&self) -> &[TrackInfo] {
+        &self.tracks
+    }
+
+    pub fn loop_points(&self) -> Option<LoopPoints> {
+        if self.info.looping {
+            Some(LoopPoints { start: self.info.loop_start, end: self.info.sample_count })
+        } else {
+            None
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Index of the next sample to be returned.
+    pub fn position(&self) -> usize {
+        self.block_start + self.position
+    }
+
+    /// Moves to the given sample. For ADPCM the decoder history is taken from the ADPC block,
+    /// so only the block containing `sample` is read. Without one, every block before it has
+    /// to be decoded again.
+    pub fn seek(&mut self, sample: usize) -> io::Result<()> {
+        if sample >= self.info.sample_count as usize {
+            self.next_block = self.info.block_count;
+            self.buffers.clear();
+            self.block_start = self.info.sample_count as usize;
+            self.position = 0;
+            return Ok(());
+        }
+
+        let block_samples = self.info.block_samples.max(1) as usize;
+        let block = (sample / block_samples) as u32;
+        if self.info.encoding == SoundEncoding::DspAdpcm && block != self.next_block && !self.restore_history(block) {
+            self.restore_history(0);
+            for skipped in 0..block {
+                self.load_block(skipped)?;
+            }
+        }
+        self.load_block(block)?;
+        self.position = sample - self.block_start;
+        Ok(())
+    }
+
+    /// Positions the decoders at the start of `block`, if the history for it is known.
+    fn restore_history(&mut self, block: u32) -> bool {
+        if block != 0 && self.info.adpc_interval_samples != self.info.block_samples {
+            return false;
+        }
+        let start = block as usize * self.decoders.len();
+        let entries = match self.history.get(start..start + self.decoders.len()) {
+            Some(entries) => entries,
+            None => return false
+        };
+        for (decoder, entry) in self.decoders.iter_mut().zip(entries) {
+            decoder.history = History::new(entry.hist1, entry.hist2);
+        }
+        self.next_block = block;
+        true
+    }
+
+    fn load_block(&mut self, block: u32) -> io::Result<()> {
+        let len = self.info.block_len(block) as usize;
+        let count = self.info.samples_in_block(block) as usize;
+        let stride = self.info.block_size as u64 * self.info.channel_count as u64;
+        let offset = self.info.data_offset as u64 + block as u64 * stride;
+
+        let mut data = vec![0; len];
+        self.buffers.resize(self.decoders.len(), Vec::new());
+        for channel in 0..self.decoders.len() {
+            self.reader.seek(SeekFrom::Start(offset + (channel * len) as u64))?;
+            self.reader.read_exact(&mut data)?;
+            self.buffers[channel] = match self.info.encoding {
+                SoundEncoding::DspAdpcm => self.decoders[channel].decode(&data, count),
+                encoding => codec::decode(encoding, &data, None, count)?,
+            };
+        }
+
+        self.block_start = block as usize * self.info.block_samples as usize;
+        self.position = 0;
+        self.next_block = block + 1;
+        Ok(())
+    }
+
+    /// Makes sure there's something left in the buffers, returns false at the end of the stream.
+    fn fill(&mut self) -> io::Result<bool> {
+        while self.buffers.first().is_none_or(|buffer| self.position >= buffer.len()) {
+            if self.next_block >= self.info.block_count {
+                return Ok(false);
+            }
+            self.load_block(self.next_block)?;
+        }
+        Ok(true)
+    }
+
+    /// Reads up to `max` samples per channel, returning one Vec per channel.
+    /// Chunks never span blocks, and are empty at the end of the stream.
+    pub fn read_samples(&mut self, max: usize) -> io::Result<Vec<Vec<i16>>> {
+        if !self.fill()? {
+            return Ok(vec![Vec::new(); self.decoders.len()]);
+        }
+        let start = self.position;
+        let end = (start + max).min(self.buffers[0].len());
+        self.position = end;
+        Ok(self.buffers.iter().map(|buffer| buffer[start..end].to_vec()).collect())
+    }
+}
+
+impl<R: Read + Seek> Iterator for StreamReader<R> {
+    type Item = io::Result<Vec<i16>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.fill() {
+            Ok(true) => {
+                let frame = self.buffers.iter().map(|buffer| buffer[self.position]).collect();
+                self.position += 1;
+                Some(Ok(frame))
+            }
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Rstm;
+    use super::super::encode::EncodedStream;
+    use crate::wav::Wav;
+    use binread::io::Cursor;
+
+    fn tone(len: usize, freq: f64) -> Vec<i16> {
+        (0..len).map(|i| ((i as f64 * freq / 32000.0 * std::f64::consts::TAU).sin() * 12000.0) as i16).collect()
+    }
+
+    fn frames(decoded: &[Vec<i16>], range: std::ops::Range<usize>) -> Vec<Vec<i16>> {
+        range.map(|idx| decoded.iter().map(|channel| channel[idx]).collect()).collect()
+    }
+
+    #[test]
+    fn streamed_matches_decoded() {
+        let len = 40000;
+        let wav = Wav::new(32000, vec![tone(len, 440.0), tone(len, 330.0)]).with_loop(1000, len as u32);
+        let bytes = EncodedStream::encode(&[wav], SoundEncoding::DspAdpcm).unwrap().to_rstm();
+        let decoded = Cursor::new(&bytes).read_be::<Rstm>().unwrap().decode().unwrap();
+
+        let mut reader = StreamReader::new(Cursor::new(&bytes)).unwrap();
+        assert!(reader.info().block_count > 2);
+        assert_eq!(reader.loop_points(), Some(LoopPoints { start: 1000, end: len as u32 }));
+        let streamed: Vec<Vec<i16>> = reader.by_ref().collect::<io::Result<_>>().unwrap();
+        assert_eq!(streamed, frames(&decoded, 0..len));
+        assert_eq!(reader.position(), len);
+
+        // forwards and backwards across blocks, using the ADPC history
+        for &start in &[30000, 20000, 15000, 5] {
+            reader.seek(start).unwrap();
+            let chunk = reader.read_samples(100).unwrap();
+            assert_eq!(chunk, vec![decoded[0][start..start + 100].to_vec(), decoded[1][start..start + 100].to_vec()]);
+            assert_eq!(reader.position(), start + 100);
+        }
+
+        // and by decoding every block up to it without one
+        reader.history.truncate(2);
+        reader.seek(35000).unwrap();
+        assert_eq!(reader.take(10).collect::<io::Result<Vec<_>>>().unwrap(), frames(&decoded, 35000..35010));
+    }
+
+    #[test]
+    fn pcm() {
+        let wav = Wav::new(22050, vec![tone(20000, 440.0)]);
+        let bytes = EncodedStream::encode(&[wav], SoundEncoding::SPcm16).unwrap().to_rstm();
+        let decoded = Cursor::new(&bytes).read_be::<Rstm>().unwrap().decode().unwrap();
+
+        let mut reader = StreamReader::new(Cursor::new(&bytes)).unwrap();
+        reader.seek(19990).unwrap();
+        assert_eq!(reader.read_samples(50).unwrap(), vec![decoded[0][19990..].to_vec()]);
+        assert_eq!(reader.read_samples(50).unwrap(), vec![Vec::<i16>::new()]);
+        assert_eq!(reader.loop_points(), None);
+    }
+}
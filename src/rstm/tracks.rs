@@ -0,0 +1,151 @@
+//! Splitting a stream into its tracks and muxing tracks into a stream, without re-encoding.
+
+use super::Rstm;
+use super::block::AdpcHistory;
+use super::encode::{EncodedStream, EncodedStreamChannel, StreamTrack};
+use crate::codec;
+use crate::common::*;
+use std::io;
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+impl EncodedStream {
+    /// Takes the still encoded channels out of an RSTM, so it can be written again with a
+    /// different track layout.
+    pub fn from_rstm(rstm: &Rstm) -> io::Result<EncodedStream> {
+        let info = rstm.info();
+        let sample_count = info.sample_count as usize;
+        let len = codec::encoded_len(info.encoding, sample_count);
+
+        let mut stream = EncodedStream {
+            encoding: info.encoding,
+            sample_rate: info.sample_rate,
+            sample_count: info.sample_count,
+            loop_start: if info.looping { Some(info.loop_start) } else { None },
+            tracks: rstm.tracks().map(|track| StreamTrack {
+                volume: track.extended.as_ref().map(|extended| extended.volume).unwrap_or(0x7F),
+                pan: track.extended.as_ref().map(|extended| extended.pan).unwrap_or(0x40),
+                channels: track.channels.clone(),
+            }).collect(),
+            channels: Vec::new(),
+        };
+
+        // the blocks may be laid out differently when written, so the history at the start of
+        // each block is taken from the decoded samples instead of the ADPC block
+        let block_samples = stream.block_samples();
+        for channel in 0..info.channel_count as usize {
+            let mut data = rstm.channel_data(channel);
+            data.truncate(len);
+            let adpcm = rstm.adpcm_info(channel).cloned().unwrap_or_default();
+            let history = if info.encoding == SoundEncoding::DspAdpcm {
+                let decoded = rstm.decode_channel(channel)?;
+                (0..stream.block_count()).map(|block| match block * block_samples {
+                    0 => AdpcHistory { hist1: adpcm.hist1, hist2: adpcm.hist2 },
+                    start => AdpcHistory {
+                        hist1: decoded.get(start - 1).copied().unwrap_or(0),
+                        hist2: decoded.get(start - 2).copied().unwrap_or(0),
+                    }
+                }).collect()
+            } else {
+                Vec::new()
+            };
+            stream.channels.push(EncodedStreamChannel { data, adpcm, history });
+        }
+        Ok(stream)
+    }
+
+    /// One single-track stream per track, containing only that track's channels.
+    pub fn split_tracks(&self) -> Vec<EncodedStream> {
+        self.tracks.iter().map(|track| {
+            let channels: Vec<EncodedStreamChannel> = track.channels.iter()
+                .filter_map(|&channel| self.channels.get(channel as usize).cloned())
+                .collect();
+            EncodedStream {
+                encoding: self.encoding,
+                sample_rate: self.sample_rate,
+                sample_count: self.sample_count,
+                loop_start: self.loop_start,
+                tracks: vec![StreamTrack { channels: (0..channels.len() as u8).collect(), ..track.clone() }],
+                channels,
+            }
+        }).collect()
+    }
+
+    /// Combines streams into one, keeping the tracks of each in order.
+    ///
+    /// Since nothing is re-encoded, every input needs the same encoding, sample rate, length and loop.
+    pub fn mux(streams: Vec<EncodedStream>) -> io::Result<EncodedStream> {
+        let mut streams = streams.into_iter();
+        let mut muxed = streams.next().ok_or_else(|| invalid_input("no input"))?;
+        for stream in streams {
+            if stream.encoding != muxed.encoding {
+                return Err(invalid_input("all tracks need the same encoding"));
+            }
+            if stream.sample_rate != muxed.sample_rate {
+                return Err(invalid_input("all tracks need the same sample rate"));
+            }
+            if stream.sample_count != muxed.sample_count || stream.loop_start != muxed.loop_start {
+                return Err(invalid_input("all tracks need the same length and loop"));
+            }
+            if muxed.channels.len() + stream.channels.len() > u8::MAX as usize {
+                return Err(invalid_input("too many channels"));
+            }
+
+            let first = muxed.channels.len() as u8;
+            muxed.tracks.extend(stream.tracks.into_iter().map(|track| StreamTrack {
+                channels: track.channels.iter().map(|&channel| channel + first).collect(),
+                ..track
+            }));
+            muxed.channels.extend(stream.channels);
+        }
+        Ok(muxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::Wav;
+    use binread::BinReaderExt;
+    use binread::io::Cursor;
+
+    fn tone(len: usize, freq: f64) -> Vec<i16> {
+        (0..len).map(|i| ((i as f64 * freq / 32000.0 * std::f64::consts::TAU).sin() * 12000.0) as i16).collect()
+    }
+
+    fn read(stream: &EncodedStream) -> Rstm {
+        Cursor::new(stream.to_rstm()).read_be().unwrap()
+    }
+
+    #[test]
+    fn split_and_mux() {
+        let len = 30000;
+        let wavs = vec![
+            Wav::new(32000, vec![tone(len, 440.0), tone(len, 660.0)]).with_loop(500, len as u32),
+            Wav::new(32000, vec![tone(len, 220.0)]),
+        ];
+        let mut encoded = EncodedStream::encode(&wavs, SoundEncoding::DspAdpcm).unwrap();
+        encoded.tracks[1].pan = 0x10;
+        let original = read(&encoded);
+        let decoded = original.decode().unwrap();
+
+        let split = EncodedStream::from_rstm(&original).unwrap().split_tracks();
+        assert_eq!(split.len(), 2);
+        let second = read(&split[1]);
+        assert_eq!(second.decode().unwrap(), vec![decoded[2].clone()]);
+        assert_eq!(second.tracks().map(|track| track.channels.clone()).collect::<Vec<_>>(), vec![vec![0]]);
+        assert_eq!(second.tracks().next().unwrap().extended.as_ref().unwrap().pan, 0x10);
+
+        let muxed = EncodedStream::mux(split.iter().map(|track| EncodedStream::from_rstm(&read(track)).unwrap()).collect()).unwrap();
+        let muxed = read(&muxed);
+        assert_eq!(muxed.decode().unwrap(), decoded);
+        assert_eq!(muxed.tracks().map(|track| track.channels.clone()).collect::<Vec<_>>(), vec![vec![0, 1], vec![2]]);
+        let history = |rstm: &Rstm| rstm.adpc.as_ref().unwrap().block.history.iter().map(|h| (h.hist1, h.hist2)).collect::<Vec<_>>();
+        assert_eq!(history(&muxed), history(&original));
+
+        let short = EncodedStream::encode(&[Wav::new(32000, vec![tone(100, 440.0)])], SoundEncoding::DspAdpcm).unwrap();
+        assert!(EncodedStream::mux(vec![EncodedStream::from_rstm(&original).unwrap(), short]).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+#![allow(unused)]
+
+use crate::common::*;
+use crate::rwav::Rwav;
+use binread::{BinRead, BinReaderExt, BinResult};
+use binread::io::Cursor;
+use std::ops::Deref;
+use std::io::{self, Write};
+
+/// Wave archive, a flat collection of RWAV files referenced by index from RWSD and RBNK files.
+#[derive(BinRead)]
+pub struct Rwar {
+    #[br(assert(&header.magic == b"RWAR"))]
+    pub header: FileHeader,
+    #[br(is_big = header.endian == Endian::Big)]
+    pub table: BlockPtr<TableBlock>,
+    #[br(is_big = header.endian == Endian::Big)]
+    pub data: BlockPtr<DataBlock>,
+}
+
+#[derive(BinRead)]
+pub struct TableBlock {
+    #[br(assert(&header.magic == b"TABL"))]
+    pub header: BlockHeader,
+    pub entries: Table<WaveEntry>,
+}
+
+#[derive(BinRead, Clone, Debug)]
+pub struct WaveEntry {
+    pub layout: ReferenceLayout, // always relative, type 0
+    pub offset: u32, // from the start of the DATA block (including its header)
+    pub size: u32,
+}
+
+#[derive(BinRead)]
+pub struct DataBlock {
+    #[br(assert(&header.magic == b"DATA"))]
+    pub header: BlockHeader,
+    // everything after the block header, RWAVs are usually aligned to 0x20
+    #[br(args(header.size, 8), parse_with = read_block_rest)]
+    pub body: Vec<u8>,
+}
+
+impl Rwar {
+    pub fn entries(&self) -> &[WaveEntry] {
+        &self.table.block.entries.0
+    }
+
+    pub fn wave_count(&self) -> usize {
+        self.entries().len()
+    }
+
+    /// Raw bytes of the RWAV at the given index.
+    pub fn wave(&self, index: usize) -> Option<&[u8]> {
+        let entry = self.entries().get(index)?;
+        let start = (entry.offset as usize).checked_sub(8)?;
+        self.data.block.body.get(start..start + entry.size as usize)
+    }
+
+    pub fn waves(&self) -> impl Iterator<Item = &[u8]> {
+        (0..self.wave_count()).filter_map(move |idx| self.wave(idx))
+    }
+
+    /// Parses the RWAV at the given index.
+    pub fn read_wave(&self, index: usize) -> Option<BinResult<Rwav>> {
+        self.wave(index).map(|bytes| Cursor::new(bytes).read_be())
+    }
+}
+
+/// Builds a new RWAR, either from scratch or starting from the contents of an existing one.
+#[derive(Default, Clone)]
+pub struct RwarBuilder {
+    pub waves: Vec<Vec<u8>>,
+}
+
+impl RwarBuilder {
+    pub fn new() -> RwarBuilder {
+        RwarBuilder::default()
+    }
+
+    pub fn from_rwar(rwar: &Rwar) -> RwarBuilder {
+        RwarBuilder { waves: rwar.waves().map(<[u8]>::to_vec).collect() }
+    }
+
+    /// Appends a wave, returning its index.
+    pub fn push(&mut self, rwav: Vec<u8>) -> usize {
+        self.waves.push(rwav);
+        self.waves.len() - 1
+    }
+
+    /// Replaces the wave at `index`, returning the old one.
+    pub fn replace(&mut self, index: usize, rwav: Vec<u8>) -> Option<Vec<u8>> {
+        self.waves.get_mut(index).map(|wave| std::mem::replace(wave, rwav))
+    }
+
+    fn container(&self) -> ContainerWriter {
+        let mut container = ContainerWriter::new(*b"RWAR", 0x0100);
+        container.add_block(*b"TABL", 4 + self.waves.len() * 12);
+        container.add_block(*b"DATA", 0x18 + self.waves.iter().map(|wave| align_up(wave.len(), 0x20)).sum::<usize>());
+        container
+    }
+
+    pub fn wave_count(&self) -> usize {
+        self.waves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waves.is_empty()
+    }
+
+    /// Size of the RWAR once written.
+    pub fn byte_len(&self) -> usize {
+        self.container().file_len()
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let container = self.container();
+        container.write_header(writer)?;
+
+        container.write_block(writer, 0, |body| {
+            body.write_all(&(self.waves.len() as u32).to_be_bytes())?;
+            let mut offset = 0x20; // from the start of the DATA block
+            for wave in &self.waves {
+                body.write_all(&[1, 0, 0, 0])?;
+                body.write_all(&(offset as u32).to_be_bytes())?;
+                body.write_all(&(wave.len() as u32).to_be_bytes())?;
+                offset += align_up(wave.len(), 0x20);
+            }
+            Ok(())
+        })?;
+
+        container.write_block(writer, 1, |body| {
+            body.write_all(&[0; 0x18])?;
+            for wave in &self.waves {
+                body.write_all(wave)?;
+                body.write_all(&vec![0; align_up(wave.len(), 0x20) - wave.len()])?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.byte_len());
+        self.write(&mut out).expect("writing to a Vec can't fail");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binread::BinReaderExt;
+    use binread::io::Cursor;
+
+    // one wave of 4 bytes, the DATA block header claiming `data_size`
+    fn test_rwar(data_size: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RWAR");
+        out.extend_from_slice(&[0xFE, 0xFF, 0x01, 0x00]);
+        for value in &[0x80u32, 0x0020_0002, 0x20, 0x20, 0x40, 0x40] {
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+        out.resize(0x20, 0);
+        for value in &[u32::from_be_bytes(*b"TABL"), 0x20, 1, 0x0100_0000, 0x20, 4] {
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+        out.resize(0x40, 0);
+        out.extend_from_slice(b"DATA");
+        out.extend_from_slice(&data_size.to_be_bytes());
+        out.resize(0x60, 0);
+        out.extend_from_slice(&[1, 2, 3, 4]);
+        out.resize(0x80, 0);
+        out
+    }
+
+    #[test]
+    fn parse() {
+        let rwar: Rwar = Cursor::new(test_rwar(0x40)).read_be().unwrap();
+        assert_eq!(rwar.wave_count(), 1);
+        assert_eq!(rwar.wave(0), Some(&[1, 2, 3, 4][..]));
+        assert_eq!(rwar.wave(1), None);
+    }
+
+    #[test]
+    fn truncated_data_block() {
+        // smaller than the block header itself
+        assert!(Cursor::new(test_rwar(4)).read_be::<Rwar>().is_err());
+    }
+
+    #[test]
+    fn builder_round_trip() {
+        let mut builder = RwarBuilder::new();
+        builder.push(vec![1; 0x30]);
+        builder.push(vec![2; 0x41]);
+        let bytes = builder.to_bytes();
+        assert_eq!(bytes.len(), builder.byte_len());
+
+        let rwar: Rwar = Cursor::new(&bytes).read_be().unwrap();
+        assert_eq!(rwar.wave_count(), 2);
+        assert_eq!(rwar.wave(0), Some(&[1; 0x30][..]));
+        assert_eq!(rwar.wave(1), Some(&[2; 0x41][..]));
+
+        let mut rebuilt = RwarBuilder::from_rwar(&rwar);
+        assert_eq!(rebuilt.replace(0, vec![3; 4]), Some(vec![1; 0x30]));
+        let rwar: Rwar = Cursor::new(rebuilt.to_bytes()).read_be().unwrap();
+        assert_eq!(rwar.wave(0), Some(&[3; 4][..]));
+        assert_eq!(rwar.wave(1), Some(&[2; 0x41][..]));
+    }
+}
@@ -0,0 +1,486 @@
+#![allow(unused)]
+
+use crate::common::*;
+use crate::codec::{self, adpcm, adpcm_encoder, pcm};
+use crate::wav::{Wav, LoopPoints};
+use crate::rwar::Rwar;
+use binread::{BinRead, BinReaderExt};
+use binread::io::Cursor;
+use std::ops::Deref;
+use std::io::{self, Write};
+
+/// A single wave, either standalone or as an entry in an RWAR.
+#[derive(BinRead)]
+pub struct Rwav {
+    #[br(assert(&header.magic == b"RWAV"))]
+    pub header: FileHeader,
+    #[br(is_big = header.endian == Endian::Big)]
+    pub info: BlockPtr<InfoBlock>,
+    #[br(is_big = header.endian == Endian::Big)]
+    pub data: BlockPtr<DataBlock>,
+}
+
+#[derive(BinRead)]
+pub struct InfoBlock {
+    #[br(assert(&header.magic == b"INFO"))]
+    pub header: BlockHeader,
+    pub wave: WaveInfo,
+}
+
+#[derive(BinRead)]
+pub struct DataBlock {
+    #[br(assert(&header.magic == b"DATA"))]
+    pub header: BlockHeader,
+    // channel data is non-interleaved, see ChannelInfo::data_offset
+    #[br(args(header.size, 8), parse_with = read_block_rest)]
+    pub body: Vec<u8>,
+}
+
+// Also used as-is by the WAVE block of RWSD files.
+#[derive(BinRead)]
+pub struct WaveInfo {
+    // all offsets inside of WaveInfo are relative to its start
+    pub start: binread_utils::CurPos,
+    pub encoding: SoundEncoding,
+    #[br(map = |x: u8| x != 0)]
+    pub looping: bool,
+    pub channel_count: u8,
+    pub sample_rate_high: u8, // bits 16-23 of the sample rate
+    pub sample_rate_low: u16,
+    pub data_location_type: u8, // 0 = offset, 1 = address
+    pub padding: u8,
+    // for DSP-ADPCM, loop_start and loop_end are nibble addresses, otherwise they're sample indices
+    pub loop_start: u32,
+    pub loop_end: u32, // also the length of the wave
+    #[br(offset = start.0, count = channel_count)]
+    pub channels: r32<Vec<r32<WaveChannelInfo>>>,
+    pub data_location: u32,
+    pub reserved: u32,
+}
+
+#[derive(BinRead)]
+pub struct WaveChannelInfo {
+    pub data_offset: u32, // from the start of the wave data
+    pub adpcm: r32<AdpcmInfo>, // zeroed for PCM
+    pub volume_front_left: u32,
+    pub volume_front_right: u32,
+    pub volume_rear_left: u32,
+    pub volume_rear_right: u32,
+    pub reserved: u32,
+}
+
+impl WaveInfo {
+    pub fn sample_rate(&self) -> u32 {
+        ((self.sample_rate_high as u32) << 16) | self.sample_rate_low as u32
+    }
+
+    fn address_to_sample(&self, address: u32) -> u32 {
+        match self.encoding {
+            SoundEncoding::DspAdpcm => adpcm::nibble_to_sample(address as usize) as u32,
+            _ => address
+        }
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.address_to_sample(self.loop_end)
+    }
+
+    pub fn loop_start_sample(&self) -> u32 {
+        self.address_to_sample(self.loop_start)
+    }
+
+    pub fn loop_end_sample(&self) -> u32 {
+        self.sample_count()
+    }
+
+    pub fn loop_points(&self) -> Option<LoopPoints> {
+        if self.looping {
+            Some(LoopPoints { start: self.loop_start_sample(), end: self.loop_end_sample() })
+        } else {
+            None
+        }
+    }
+
+    pub fn channel(&self, channel: usize) -> Option<&WaveChannelInfo> {
+        self.channels.get(channel).map(Deref::deref)
+    }
+
+    /// Decodes one channel, given the wave data that `data_offset`s are relative to.
+    /// Fails if the channel doesn't exist or its data doesn't fit in `data`.
+    pub fn decode_channel(&self, data: &[u8], channel: usize) -> io::Result<Vec<i16>> {
+        let info = self.channel(channel)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no such channel"))?;
+        let count = self.sample_count() as usize;
+        let start = info.data_offset as usize;
+        let bytes = start.checked_add(codec::encoded_len(self.encoding, count))
+            .and_then(|end| data.get(start..end))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "channel data is out of bounds"))?;
+
+        codec::decode(self.encoding, bytes, Some(info.adpcm.deref()), count)
+    }
+
+    pub fn decode(&self, data: &[u8]) -> io::Result<Vec<Vec<i16>>> {
+        (0..self.channel_count as usize).map(|ch| self.decode_channel(data, ch)).collect()
+    }
+
+    pub fn to_wav(&self, data: &[u8]) -> io::Result<Wav> {
+        Ok(Wav { loop_points: self.loop_points(), ..Wav::new(self.sample_rate(), self.decode(data)?) })
+    }
+}
+
+impl Rwav {
+    pub fn wave(&self) -> &WaveInfo {
+        &self.info.block.wave
+    }
+
+    pub fn decode(&self) -> io::Result<Vec<Vec<i16>>> {
+        self.wave().decode(&self.data.block.body)
+    }
+
+    pub fn to_wav(&self) -> io::Result<Wav> {
+        self.wave().to_wav(&self.data.block.body)
+    }
+}
+
+/// Decodes a wave referenced by index from an RWSD or RBNK, given the wave data belonging to it
+/// (see `GroupEntry::read_archive_bytes`).
+///
+/// The wave data can either be an RWAR, or raw data referenced by the file's WAVE block.
+pub(crate) fn decode_indexed_wave(waves: &[r32<WaveInfo>], index: usize, wave_data: &[u8]) -> Option<Wav> {
+    if wave_data.starts_with(b"RWAR") {
+        let rwar: Rwar = Cursor::new(wave_data).read_be().ok()?;
+        let rwav = rwar.read_wave(index)?.ok()?;
+        rwav.to_wav().ok()
+    } else {
+        let wave = waves.get(index)?;
+        let data = wave_data.get(wave.data_location as usize..)?;
+        wave.to_wav(data).ok()
+    }
+}
+
+/// Decodes every wave of an RWSD or RBNK up front, parsing the RWAR only once.
+pub(crate) fn decode_indexed_waves(waves: &[r32<WaveInfo>], wave_data: &[u8]) -> Vec<Option<Wav>> {
+    if wave_data.starts_with(b"RWAR") {
+        let rwar: Rwar = match Cursor::new(wave_data).read_be() {
+            Ok(rwar) => rwar,
+            Err(_) => return Vec::new()
+        };
+        (0..rwar.wave_count())
+            .map(|idx| rwar.read_wave(idx).and_then(Result::ok).and_then(|rwav| rwav.to_wav().ok()))
+            .collect()
+    } else {
+        (0..waves.len()).map(|idx| decode_indexed_wave(waves, idx, wave_data)).collect()
+    }
+}
+
+/// Wave data encoded for the hardware, ready to be written out as an RWAV.
+pub struct EncodedWave {
+    pub encoding: SoundEncoding,
+    pub sample_rate: u32,
+    pub sample_count: u32,
+    pub loop_points: Option<LoopPoints>,
+    pub channels: Vec<EncodedWaveChannel>,
+}
+
+pub struct EncodedWaveChannel {
+    pub data: Vec<u8>,
+    pub adpcm: AdpcmInfo, // default for PCM
+}
+
+const WAVE_INFO_LEN: usize = 0x1C;
+const CHANNEL_INFO_LEN: usize = 0x1C;
+// 1.0 in the channel volume fields
+const UNITY_VOLUME: u32 = 0x0100_0000;
+
+impl EncodedWave {
+    /// Encodes a WAV, keeping its loop if it has one. Anything after the loop end is dropped,
+    /// as the loop end doubles as the end of the wave.
+    pub fn encode(wav: &Wav, encoding: SoundEncoding) -> EncodedWave {
+        let loop_points = wav.loop_points.filter(|points| points.start < points.end);
+        let sample_count = loop_points.map(|points| points.end as usize)
+            .unwrap_or_else(|| wav.sample_count())
+            .min(wav.sample_count());
+        let loop_start = loop_points.map(|points| points.start as usize);
+
+        let channels = wav.channels.iter().map(|samples| {
+            let samples = &samples[..sample_count.min(samples.len())];
+            match encoding {
+                SoundEncoding::SPcm8 => EncodedWaveChannel { data: pcm::encode_pcm8(samples), adpcm: AdpcmInfo::default() },
+                SoundEncoding::SPcm16 => EncodedWaveChannel { data: pcm::encode_pcm16(samples), adpcm: AdpcmInfo::default() },
+                SoundEncoding::DspAdpcm => {
+                    let encoded = adpcm_encoder::encode(samples, loop_start);
+                    EncodedWaveChannel { data: encoded.data, adpcm: encoded.info }
+                }
+            }
+        }).collect();
+
+        EncodedWave {
+            encoding,
+            sample_rate: wav.sample_rate,
+            sample_count: sample_count as u32,
+            loop_points,
+            channels,
+        }
+    }
+
+    /// Takes the still encoded channels out of an RWAV, so it can be written again.
+    pub fn from_rwav(rwav: &Rwav) -> EncodedWave {
+        let wave = rwav.wave();
+        let len = codec::encoded_len(wave.encoding, wave.sample_count() as usize);
+        let data = &rwav.data.block.body;
+        let channels = (0..wave.channel_count as usize).filter_map(|channel| {
+            let info = wave.channel(channel)?;
+            let start = (info.data_offset as usize).min(data.len());
+            let end = (start + len).min(data.len());
+            Some(EncodedWaveChannel { data: data[start..end].to_vec(), adpcm: info.adpcm.deref().clone() })
+        }).collect();
+
+        EncodedWave {
+            encoding: wave.encoding,
+            sample_rate: wave.sample_rate(),
+            sample_count: wave.sample_count(),
+            loop_points: wave.loop_points(),
+            channels,
+        }
+    }
+
+    /// Changes the loop without re-encoding, recomputing the ADPCM loop context of each channel.
+    ///
+    /// The loop end is also the end of the wave, so moving it earlier cuts off the rest.
+    pub fn set_loop(&mut self, loop_points: Option<LoopPoints>) -> io::Result<()> {
+        if let Some(points) = loop_points {
+            if points.start >= points.end {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "the loop has to start before it ends"));
+            }
+            if points.end > self.sample_count {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "the loop can't end after the wave"));
+            }
+            self.sample_count = points.end;
+            let len = codec::encoded_len(self.encoding, self.sample_count as usize);
+            for channel in &mut self.channels {
+                channel.data.truncate(len);
+            }
+        }
+
+        self.loop_points = loop_points;
+        if self.encoding == SoundEncoding::DspAdpcm {
+            for channel in &mut self.channels {
+                adpcm::set_loop_context(&mut channel.adpcm, &channel.data, loop_points.map(|points| points.start as usize));
+            }
+        }
+        Ok(())
+    }
+
+    fn address(&self, sample: u32) -> u32 {
+        match self.encoding {
+            SoundEncoding::DspAdpcm => adpcm::sample_to_nibble(sample as usize) as u32,
+            _ => sample
+        }
+    }
+
+    fn end_address(&self) -> u32 {
+        match self.encoding {
+            SoundEncoding::DspAdpcm => adpcm::sample_count_to_nibbles(self.sample_count as usize) as u32,
+            _ => self.sample_count
+        }
+    }
+
+    /// Length of the INFO block body (WaveInfo and everything it points to).
+    fn info_len(&self) -> usize {
+        WAVE_INFO_LEN + self.channels.len() * (4 + CHANNEL_INFO_LEN + AdpcmInfo::LENGTH)
+    }
+
+    fn channel_offsets(&self) -> Vec<usize> {
+        let mut offset = 0;
+        self.channels.iter().map(|channel| {
+            let tmp = offset;
+            offset += align_up(channel.data.len(), 0x20);
+            tmp
+        }).collect()
+    }
+
+    /// Writes the WaveInfo structure and everything it points to.
+    /// `data_location` is where the wave data begins, `data_offset`s are relative to it.
+    pub fn write_info<W: Write>(&self, writer: &mut W, data_location: u32) -> io::Result<()> {
+        let channel_count = self.channels.len();
+        let table_offset = WAVE_INFO_LEN;
+        let channel_info_offset = table_offset + channel_count * 4;
+        let adpcm_offset = channel_info_offset + channel_count * CHANNEL_INFO_LEN;
+
+        writer.write_all(&[
+            self.encoding as u8,
+            self.loop_points.is_some() as u8,
+            channel_count as u8,
+            (self.sample_rate >> 16) as u8,
+        ])?;
+        writer.write_all(&(self.sample_rate as u16).to_be_bytes())?;
+        writer.write_all(&[0, 0])?; // data location type: offset, padding
+        writer.write_all(&self.address(self.loop_points.map(|p| p.start).unwrap_or(0)).to_be_bytes())?;
+        writer.write_all(&self.end_address().to_be_bytes())?;
+        writer.write_all(&(table_offset as u32).to_be_bytes())?;
+        writer.write_all(&data_location.to_be_bytes())?;
+        writer.write_all(&0u32.to_be_bytes())?;
+
+        for idx in 0..channel_count {
+            writer.write_all(&((channel_info_offset + idx * CHANNEL_INFO_LEN) as u32).to_be_bytes())?;
+        }
+
+        for (idx, data_offset) in self.channel_offsets().into_iter().enumerate() {
+            writer.write_all(&(data_offset as u32).to_be_bytes())?;
+            writer.write_all(&((adpcm_offset + idx * AdpcmInfo::LENGTH) as u32).to_be_bytes())?;
+            for volume in &[UNITY_VOLUME, UNITY_VOLUME, 0, 0, 0] {
+                writer.write_all(&volume.to_be_bytes())?;
+            }
+        }
+
+        for channel in &self.channels {
+            channel.adpcm.write(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the non-interleaved channel data, each channel aligned to 0x20.
+    pub fn write_data<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for channel in &self.channels {
+            writer.write_all(&channel.data)?;
+            writer.write_all(&vec![0; align_up(channel.data.len(), 0x20) - channel.data.len()])?;
+        }
+        Ok(())
+    }
+
+    pub fn data_len(&self) -> usize {
+        self.channels.iter().map(|channel| align_up(channel.data.len(), 0x20)).sum()
+    }
+
+    pub fn write_rwav<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut container = ContainerWriter::new(*b"RWAV", 0x0102);
+        let info = container.add_block(*b"INFO", self.info_len());
+        let data = container.add_block(*b"DATA", self.data_len());
+        let data_location = (container.block_offset(data) + 8) as u32;
+
+        container.write_header(writer)?;
+        container.write_block(writer, info, |body| self.write_info(body, data_location))?;
+        container.write_block(writer, data, |body| self.write_data(body))
+    }
+
+    pub fn to_rwav(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_rwav(&mut out).expect("writing to a Vec can't fail");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binread::BinReaderExt;
+    use binread::io::Cursor;
+
+    // looping mono DSP-ADPCM wave of a single frame, with zeroed coefficients so that every
+    // sample is just its nibble times 2
+    fn test_rwav(data_size: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RWAV");
+        out.extend_from_slice(&[0xFE, 0xFF, 0x01, 0x02]);
+        for value in &[0xC0u32, 0x0020_0002, 0x20, 0x80, 0xA0, 0x20] {
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+
+        out.extend_from_slice(b"INFO");
+        out.extend_from_slice(&0x80u32.to_be_bytes());
+        out.extend_from_slice(&[2, 1, 1, 0]);
+        out.extend_from_slice(&32000u16.to_be_bytes());
+        out.extend_from_slice(&[0, 0]);
+        for value in &[4u32, 16, 0x1C, 0, 0, 0x20, 0, 0x3C, 0x7F, 0x7F, 0, 0, 0] {
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+        out.resize(0xA0, 0); // zeroed AdpcmInfo and padding
+
+        out.extend_from_slice(b"DATA");
+        out.extend_from_slice(&data_size.to_be_bytes());
+        out.extend_from_slice(&[0x01, 0x12, 0x34, 0x56, 0x7F, 0xED, 0xCB, 0xA9]);
+        out.resize(0xC0, 0);
+        out
+    }
+
+    #[test]
+    fn parse() {
+        let rwav: Rwav = Cursor::new(test_rwav(0x20)).read_be().unwrap();
+        let wave = rwav.wave();
+        assert_eq!((wave.encoding, wave.channel_count, wave.sample_rate()), (SoundEncoding::DspAdpcm, 1, 32000));
+        assert_eq!((wave.loop_start_sample(), wave.sample_count()), (2, 14));
+        assert_eq!(wave.channel(0).unwrap().data_offset, 0);
+        assert!(wave.channel(1).is_none());
+
+        let wav = rwav.to_wav().unwrap();
+        assert_eq!(wav.sample_rate, 32000);
+        assert_eq!(wav.loop_points.map(|points| (points.start, points.end)), Some((2, 14)));
+        assert_eq!(wav.channels, vec![vec![2, 4, 6, 8, 10, 12, 14, -2, -4, -6, -8, -10, -12, -14]]);
+    }
+
+    #[test]
+    fn truncated_data_block() {
+        assert!(Cursor::new(test_rwav(4)).read_be::<Rwav>().is_err());
+    }
+
+    #[test]
+    fn decode_channel_errors() {
+        let rwav: Rwav = Cursor::new(test_rwav(0x20)).read_be().unwrap();
+        let wave = rwav.wave();
+        let data = &rwav.data.block.body;
+        assert_eq!(wave.decode_channel(data, 1).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        // the single frame doesn't fit
+        assert_eq!(wave.decode_channel(&data[..7], 0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(wave.decode_channel(&[], 0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn pcm16_round_trip() {
+        let samples: Vec<i16> = (0..100).map(|i| (i * 300 - 15000) as i16).collect();
+        let wav = Wav::new(32000, vec![samples.clone(), samples.iter().map(|s| -s).collect()]).with_loop(10, 90);
+
+        let bytes = EncodedWave::encode(&wav, SoundEncoding::SPcm16).to_rwav();
+        assert_eq!(bytes.len() % 0x20, 0);
+
+        let rwav: Rwav = Cursor::new(bytes).read_be().unwrap();
+        let wave = rwav.wave();
+        assert_eq!(wave.sample_rate(), 32000);
+        assert!(wave.looping);
+        assert_eq!((wave.loop_start_sample(), wave.loop_end_sample()), (10, 90));
+
+        let decoded = rwav.decode().unwrap();
+        assert_eq!(decoded[0], &samples[..90]);
+        assert_eq!(decoded[1][5], -samples[5]);
+    }
+
+    #[test]
+    fn adpcm_loop_addresses() {
+        let samples: Vec<i16> = (0..1000).map(|i| ((i as f64 / 10.0).sin() * 10000.0) as i16).collect();
+        let wav = Wav::new(22050, vec![samples]).with_loop(100, 1000);
+
+        let bytes = EncodedWave::encode(&wav, SoundEncoding::DspAdpcm).to_rwav();
+        let rwav: Rwav = Cursor::new(bytes).read_be().unwrap();
+        let wave = rwav.wave();
+        assert_eq!(wave.loop_start, adpcm::sample_to_nibble(100) as u32);
+        assert_eq!((wave.loop_start_sample(), wave.sample_count()), (100, 1000));
+        assert_eq!(rwav.decode().unwrap()[0].len(), 1000);
+    }
+
+    #[test]
+    fn edit_loop() {
+        let samples: Vec<i16> = (0..1000).map(|i| ((i as f64 / 7.0).sin() * 10000.0) as i16).collect();
+        let bytes = EncodedWave::encode(&Wav::new(32000, vec![samples.clone()]), SoundEncoding::DspAdpcm).to_rwav();
+        let rwav: Rwav = Cursor::new(bytes).read_be().unwrap();
+        assert_eq!(rwav.wave().loop_points(), None);
+
+        let mut edited = EncodedWave::from_rwav(&rwav);
+        edited.set_loop(Some(LoopPoints { start: 300, end: 1000 })).unwrap();
+        let expected = EncodedWave::encode(&Wav::new(32000, vec![samples]).with_loop(300, 1000), SoundEncoding::DspAdpcm);
+        assert_eq!(edited.to_rwav(), expected.to_rwav());
+
+        edited.set_loop(Some(LoopPoints { start: 10, end: 500 })).unwrap();
+        let rwav: Rwav = Cursor::new(edited.to_rwav()).read_be().unwrap();
+        assert_eq!(rwav.wave().loop_points(), Some(LoopPoints { start: 10, end: 500 }));
+        assert!(edited.set_loop(Some(LoopPoints { start: 10, end: 600 })).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+#![allow(unused)]
+
+use crate::common::*;
+use crate::rwav::{self, WaveInfo};
+use crate::wav::Wav;
+use binread::BinRead;
+use std::ops::Deref;
+
+/// Wave sound data, the sound effects referenced by `SoundType::Wave` sounds.
+#[derive(BinRead)]
+pub struct Rwsd {
+    #[br(assert(&header.magic == b"RWSD"))]
+    pub header: FileHeader,
+    #[br(is_big = header.endian == Endian::Big)]
+    pub data: BlockPtr<DataBlock>,
+    #[br(is_big = header.endian == Endian::Big)]
+    pub wave: BlockPtr<WaveBlock>,
+}
+
+#[derive(BinRead)]
+pub struct DataBlock {
+    #[br(assert(&header.magic == b"DATA"))]
+    pub header: BlockHeader,
+    // indexed by WaveDetails::sound_data_node
+    pub sounds: Table<Reference<WaveSoundData>>,
+}
+
+#[derive(BinRead)]
+pub struct WaveSoundData {
+    pub info: Reference<WaveSoundInfo>,
+    pub tracks: Reference<Table<Reference<TrackInfo>>>,
+    pub notes: Reference<Table<Reference<NoteInfo>>>,
+}
+
+#[derive(BinRead, Debug)]
+pub struct WaveSoundInfo {
+    pub pitch: f32, // frequency ratio, 1.0 = unchanged
+    pub pan: u8, // 0 = left, 64 = center, 127 = right
+    pub surround_pan: u8,
+    pub fx_send_a: u8,
+    pub fx_send_b: u8,
+    pub fx_send_c: u8,
+    pub main_send: u8,
+    pub padding: u16,
+    graph_env_table: u64 /*Reference<()>*/,
+    randomizer_table: u64 /*Reference<()>*/,
+    reserved: u32
+}
+
+#[derive(BinRead)]
+pub struct TrackInfo {
+    pub events: Reference<Table<Reference<NoteEvent>>>,
+}
+
+#[derive(BinRead, Clone, Debug)]
+pub struct NoteEvent {
+    pub position: f32, // seconds
+    pub length: f32, // seconds
+    pub note_index: u32, // into WaveSoundData::notes
+    reserved: u32
+}
+
+#[derive(BinRead, Clone, Debug)]
+pub struct NoteInfo {
+    pub wave_index: s32, // into the WAVE block, or the RWAR for newer versions
+    pub attack: u8,
+    pub decay: u8,
+    pub sustain: u8,
+    pub release: u8,
+    pub hold: u8,
+    pub padding: [u8; 3],
+    pub original_key: u8,
+    pub volume: u8,
+    pub pan: u8,
+    pub surround_pan: u8,
+    pub pitch: f32,
+    lfo_table: u64 /*Reference<()>*/,
+    graph_env_table: u64 /*Reference<()>*/,
+    randomizer_table: u64 /*Reference<()>*/,
+    reserved: u32
+}
+
+// TODO: newer versions seem to leave this empty, and index into the RWAR instead.
+#[derive(BinRead)]
+pub struct WaveBlock {
+    #[br(assert(&header.magic == b"WAVE"))]
+    pub header: BlockHeader,
+    pub waves: Table<r32<WaveInfo>>,
+}
+
+impl Rwsd {
+    pub fn sound(&self, index: usize) -> Option<&WaveSoundData> {
+        self.data.block.sounds.0.get(index).map(Deref::deref)
+    }
+
+    /// Decodes the wave used by a note, given the wave data belonging to this RWSD
+    /// (see `GroupEntry::read_archive_bytes`).
+    pub fn decode_wave(&self, wave_index: usize, wave_data: &[u8]) -> Option<Wav> {
+        rwav::decode_indexed_wave(&self.wave.block.waves.0, wave_index, wave_data)
+    }
+}
+
+impl WaveSoundData {
+    pub fn note(&self, index: usize) -> Option<&NoteInfo> {
+        self.notes.0.get(index).map(Deref::deref)
+    }
+
+    /// Every note event of every track.
+    pub fn events(&self) -> impl Iterator<Item = &NoteEvent> {
+        self.tracks.0.iter().flat_map(|track| track.events.0.iter().map(Deref::deref))
+    }
+}
@@ -0,0 +1,251 @@
+use super::{generator, Generator, Instrument, Preset, Sample, SampleType, SoundFont, Zone};
+use crate::rbnk::{InstParam, Rbnk};
+use crate::synth::envelope::{Adsr, SILENCE_DB};
+use crate::wav::Wav;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// Converts milliseconds to timecents, as used by SF2 envelope times.
+pub(crate) fn ms_to_timecents(ms: f32) -> i16 {
+    if ms <= 1.0 {
+        -12000
+    } else {
+        (1200.0 * (ms / 1000.0).log2()).round().clamp(-12000.0, 8000.0) as i16
+    }
+}
+
+/// SF2 decay and release times are how long a fall over the full 100dB range takes.
+fn fall_timecents(rate: f32) -> i16 {
+    ms_to_timecents(100.0 / rate)
+}
+
+fn envelope_generators(adsr: Adsr) -> Vec<Generator> {
+    vec![
+        Generator::new(generator::ATTACK_VOL_ENV, ms_to_timecents(adsr.attack_ms())),
+        Generator::new(generator::HOLD_VOL_ENV, ms_to_timecents(adsr.hold_ms())),
+        Generator::new(generator::DECAY_VOL_ENV, fall_timecents(adsr.decay_rate())),
+        Generator::new(generator::SUSTAIN_VOL_ENV, (-adsr.sustain_db() * 10.0).round().min(-SILENCE_DB * 10.0) as i16),
+        Generator::new(generator::RELEASE_VOL_ENV, fall_timecents(adsr.release_rate())),
+    ]
+}
+
+/// Adds the channels of a wave as SF2 samples, returning their indices.
+fn add_samples(font: &mut SoundFont, name: &str, wav: &Wav) -> Vec<u16> {
+    let first = font.samples.len() as u16;
+    let stereo = wav.channels.len() >= 2;
+    let (loop_start, loop_end) = match wav.loop_points {
+        Some(points) => (points.start, points.end),
+        None => (0, 0)
+    };
+
+    let channels = if stereo { 2 } else { 1 };
+    for (channel, data) in wav.channels.iter().take(channels).enumerate() {
+        let (suffix, sample_type, link) = match (stereo, channel) {
+            (false, _) => ("", SampleType::Mono, 0),
+            (true, 0) => ("L", SampleType::Left, first + 1),
+            (true, _) => ("R", SampleType::Right, first),
+        };
+        font.samples.push(Sample {
+            name: format!("{}{}", name, suffix),
+            data: data.clone(),
+            sample_rate: wav.sample_rate,
+            loop_start,
+            loop_end,
+            original_pitch: 60, // overridden per zone
+            pitch_correction: 0,
+            link,
+            sample_type,
+        });
+    }
+    (first..first + channels as u16).collect()
+}
+
+fn zone_generators(inst: &InstParam, looping: bool) -> Vec<Generator> {
+    let mut generators = envelope_generators(inst.adsr());
+
+    let cents = if inst.tune > 0.0 { (1200.0 * inst.tune.log2()).round() as i32 } else { 0 };
+    generators.push(Generator::new(generator::OVERRIDING_ROOT_KEY, inst.original_key as i16));
+    generators.push(Generator::new(generator::COARSE_TUNE, (cents / 100) as i16));
+    generators.push(Generator::new(generator::FINE_TUNE, (cents % 100) as i16));
+
+    let volume = inst.volume.min(127) as f32 / 127.0;
+    let attenuation = if volume > 0.0 { -400.0 * volume.log10() } else { 1440.0 };
+    generators.push(Generator::new(generator::INITIAL_ATTENUATION, attenuation.round().min(1440.0) as i16));
+    if looping {
+        generators.push(Generator::new(generator::SAMPLE_MODES, 1));
+    }
+    generators
+}
+
+/// Converts a bank and its waves to a SoundFont, with one preset per program.
+///
+/// `wave_data` is the wave data belonging to the bank (see `GroupEntry::read_archive_bytes`).
+pub fn export_bank(rbnk: &Rbnk, wave_data: &[u8], name: &str) -> SoundFont {
+    let waves = rbnk.decode_waves(wave_data);
+    let mut font = SoundFont { name: name.to_string(), ..SoundFont::default() };
+    let mut sample_ids: HashMap<usize, Vec<u16>> = HashMap::new();
+
+    for program in 0..rbnk.instruments().len() {
+        let mut zones = Vec::new();
+        for region in rbnk.regions(program) {
+            let inst = region.inst;
+            let wave_index = match usize::try_from(inst.wave_index) {
+                Ok(wave_index) => wave_index,
+                Err(_) => continue
+            };
+            let wav = match waves.get(wave_index) {
+                Some(Some(wav)) => wav,
+                _ => continue
+            };
+
+            let samples = sample_ids.entry(wave_index)
+                .or_insert_with(|| add_samples(&mut font, &format!("{}_{}", name, wave_index), wav))
+                .clone();
+
+            let pan = (inst.pan as i32 - 64) * 500 / 63;
+            let stereo_pans: &[i32] = if samples.len() == 2 { &[-500, 500] } else { &[0] };
+            for (&sample, &stereo_pan) in samples.iter().zip(stereo_pans) {
+                let mut generators = vec![
+                    Generator::range(generator::KEY_RANGE, region.keys.clone()),
+                    Generator::range(generator::VEL_RANGE, region.velocities.clone()),
+                    Generator::new(generator::PAN, (pan + stereo_pan).clamp(-500, 500) as i16),
+                    Generator::new(generator::SAMPLE_ID, sample as i16),
+                ];
+                generators.extend(zone_generators(inst, wav.loop_points.is_some()));
+                zones.push(Zone { generators });
+            }
+        }
+
+        if zones.is_empty() {
+            continue;
+        }
+
+        let inst_name = format!("{}_{}", name, program);
+        font.presets.push(Preset {
+            name: inst_name.clone(),
+            preset: program as u16,
+            bank: 0,
+            zones: vec![Zone { generators: vec![Generator::new(generator::INSTRUMENT, font.instruments.len() as i16)] }],
+        });
+        font.instruments.push(Instrument { name: inst_name, zones });
+    }
+
+    font
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::SoundEncoding;
+    use crate::rwar::RwarBuilder;
+    use crate::rwav::EncodedWave;
+    use binread::BinReaderExt;
+    use binread::io::Cursor;
+
+    fn reference(ty: u8, offset: u32) -> [u8; 8] {
+        let mut reference = [1, ty, 0, 0, 0, 0, 0, 0];
+        reference[4..].copy_from_slice(&offset.to_be_bytes());
+        reference
+    }
+
+    fn inst(wave_index: u32, original_key: u8, volume: u8, pan: u8, tune: f32) -> Vec<u8> {
+        let adsr = Adsr::default();
+        let mut inst = wave_index.to_be_bytes().to_vec();
+        inst.extend_from_slice(&[adsr.attack, adsr.decay, adsr.sustain, adsr.release, adsr.hold, 0, 0, 0]);
+        inst.extend_from_slice(&[original_key, volume, pan, 0]);
+        inst.extend_from_slice(&tune.to_be_bytes());
+        inst.resize(0x30, 0);
+        inst
+    }
+
+    // instrument 0 split by key at 59, the upper keys split by velocity at 63, instrument 1 only
+    // refers to a missing wave
+    fn test_rbnk() -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&2u32.to_be_bytes());
+        body.extend_from_slice(&reference(2, 0x14));
+        body.extend_from_slice(&reference(1, 0x9C));
+        body.extend_from_slice(&[2, 59, 127, 0]); // key ranges
+        body.extend_from_slice(&reference(1, 0x3C));
+        body.extend_from_slice(&reference(2, 0x28));
+        body.extend_from_slice(&[2, 63, 127, 0]); // velocity ranges
+        body.extend_from_slice(&reference(1, 0x6C));
+        body.extend_from_slice(&reference(1, 0x3C));
+        body.extend_from_slice(&inst(0, 60, 127, 64, 1.0));
+        body.extend_from_slice(&inst(1, 72, 64, 127, 2f32.powf(150.0 / 1200.0)));
+        body.extend_from_slice(&inst(5, 60, 127, 64, 1.0));
+        body.resize(0xD8, 0);
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"RBNK");
+        data.extend_from_slice(&[0xFE, 0xFF, 0x01, 0x01]);
+        data.extend_from_slice(&0x120u32.to_be_bytes());
+        data.extend_from_slice(&[0, 0x20, 0, 2]);
+        for block in [0x20u32, 0xE0, 0x100, 0x20] {
+            data.extend_from_slice(&block.to_be_bytes());
+        }
+        data.extend_from_slice(b"DATA");
+        data.extend_from_slice(&0xE0u32.to_be_bytes());
+        data.extend_from_slice(&body);
+        data.extend_from_slice(b"WAVE");
+        data.extend_from_slice(&0x20u32.to_be_bytes());
+        data.resize(0x120, 0);
+        data
+    }
+
+    #[test]
+    fn export() {
+        let mono = Wav::new(32000, vec![(0..32).collect()]).with_loop(8, 32);
+        let stereo = Wav::new(22050, vec![vec![100; 16], vec![-100; 16]]);
+        let mut rwar = RwarBuilder::new();
+        rwar.push(EncodedWave::encode(&mono, SoundEncoding::SPcm16).to_rwav());
+        rwar.push(EncodedWave::encode(&stereo, SoundEncoding::SPcm16).to_rwav());
+
+        let rbnk: Rbnk = Cursor::new(test_rbnk()).read_be().unwrap();
+
+        let font = export_bank(&rbnk, &rwar.to_bytes(), "bank");
+
+        assert_eq!(font.samples.len(), 3);
+        let sample = &font.samples[0];
+        assert_eq!((sample.name.as_str(), sample.sample_type, sample.sample_rate), ("bank_0", SampleType::Mono, 32000));
+        assert_eq!((sample.loop_start, sample.loop_end), (8, 32));
+        assert_eq!(sample.data, mono.channels[0]);
+        let (left, right) = (&font.samples[1], &font.samples[2]);
+        assert_eq!((left.name.as_str(), left.sample_type, left.link), ("bank_1L", SampleType::Left, 2));
+        assert_eq!((right.name.as_str(), right.sample_type, right.link), ("bank_1R", SampleType::Right, 1));
+        assert_eq!(right.data, vec![-100; 16]);
+
+        assert_eq!(font.presets.len(), 1);
+        assert_eq!(font.presets[0].preset, 0);
+        assert_eq!(font.presets[0].zones[0].get(generator::INSTRUMENT).unwrap().amount, 0);
+
+        let zones = &font.instruments[0].zones;
+        assert_eq!(zones.len(), 4);
+        let amount = |zone: usize, op: u16| zones[zone].get(op).map(Generator::signed);
+
+        assert_eq!(zones[0].get(generator::KEY_RANGE).unwrap().as_range(), (0, 59));
+        assert_eq!(amount(0, generator::SAMPLE_ID), Some(0));
+        assert_eq!(amount(0, generator::OVERRIDING_ROOT_KEY), Some(60));
+        assert_eq!(amount(0, generator::INITIAL_ATTENUATION), Some(0));
+        assert_eq!(amount(0, generator::SAMPLE_MODES), Some(1));
+        assert_eq!(amount(0, generator::ATTACK_VOL_ENV), Some(-12000));
+        assert_eq!(amount(0, generator::HOLD_VOL_ENV), Some(-12000));
+        assert_eq!(amount(0, generator::SUSTAIN_VOL_ENV), Some(0));
+
+        // the stereo wave gets a zone per channel, panned apart
+        for (zone, sample, pan) in [(1, 1, 0), (2, 2, 500)] {
+            assert_eq!(zones[zone].get(generator::KEY_RANGE).unwrap().as_range(), (60, 127));
+            assert_eq!(zones[zone].get(generator::VEL_RANGE).unwrap().as_range(), (0, 63));
+            assert_eq!(amount(zone, generator::SAMPLE_ID), Some(sample));
+            assert_eq!(amount(zone, generator::PAN), Some(pan));
+            assert_eq!(amount(zone, generator::OVERRIDING_ROOT_KEY), Some(72));
+            assert_eq!(amount(zone, generator::COARSE_TUNE), Some(1));
+            assert_eq!(amount(zone, generator::FINE_TUNE), Some(50));
+            assert_eq!(amount(zone, generator::INITIAL_ATTENUATION), Some(119));
+            assert_eq!(amount(zone, generator::SAMPLE_MODES), None);
+        }
+
+        assert_eq!(zones[3].get(generator::VEL_RANGE).unwrap().as_range(), (64, 127));
+        assert_eq!(amount(3, generator::SAMPLE_ID), Some(0));
+    }
+}
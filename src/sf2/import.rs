@@ -0,0 +1,248 @@
+use super::{generator, SampleType, SoundFont, Zone};
+use crate::common::SoundEncoding;
+use crate::rbnk::{BankBuilder, BankRegion, InstParam};
+use crate::rwar::RwarBuilder;
+use crate::rwav::EncodedWave;
+use crate::synth::envelope::Adsr;
+use crate::wav::Wav;
+use std::collections::HashMap;
+use std::io;
+use std::ops::RangeInclusive;
+
+/// A bank converted from a SoundFont, with its waves encoded as DSP-ADPCM in a wave archive.
+pub struct ImportedBank {
+    pub bank: BankBuilder,
+    pub waves: RwarBuilder,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Converts timecents to milliseconds, the lowest value means instant.
+fn timecents_to_ms(timecents: i16) -> f32 {
+    if timecents <= -12000 {
+        0.0
+    } else {
+        1000.0 * 2f32.powf(timecents as f32 / 1200.0)
+    }
+}
+
+/// Generators of an instrument zone, falling back to the instrument's global zone.
+struct ZoneGenerators<'a> {
+    zone: &'a Zone,
+    global: Option<&'a Zone>,
+}
+
+impl ZoneGenerators<'_> {
+    fn get(&self, op: u16) -> Option<i16> {
+        self.zone.get(op).or_else(|| self.global.and_then(|global| global.get(op))).map(|generator| generator.signed())
+    }
+
+    fn range(&self, op: u16) -> RangeInclusive<u8> {
+        let (low, high) = self.zone.get(op)
+            .or_else(|| self.global.and_then(|global| global.get(op)))
+            .map(|generator| generator.as_range())
+            .unwrap_or((0, 127));
+        low..=high.min(127)
+    }
+
+    fn inst_param(&self, root_key: u8, pitch_correction: i8, stereo: bool) -> InstParam {
+        let time = |op| timecents_to_ms(self.get(op).unwrap_or(-12000));
+        // SF2 decay and release times are for a fall over the full 100dB range
+        let fall_rate = |op| 100.0 / time(op).max(1.0);
+        let adsr = Adsr::from_times(
+            time(generator::ATTACK_VOL_ENV),
+            time(generator::HOLD_VOL_ENV),
+            fall_rate(generator::DECAY_VOL_ENV),
+            -(self.get(generator::SUSTAIN_VOL_ENV).unwrap_or(0) as f32) / 10.0,
+            fall_rate(generator::RELEASE_VOL_ENV),
+        );
+
+        let root_key = match self.get(generator::OVERRIDING_ROOT_KEY) {
+            Some(key) if (0..=127).contains(&key) => key as u8,
+            _ => root_key.min(127)
+        };
+        let cents = self.get(generator::COARSE_TUNE).unwrap_or(0) as i32 * 100
+            + self.get(generator::FINE_TUNE).unwrap_or(0) as i32
+            + pitch_correction as i32;
+        let tune = 2f32.powf(cents as f32 / 1200.0);
+
+        // the inverse of the squared volume curve
+        let attenuation = self.get(generator::INITIAL_ATTENUATION).unwrap_or(0).max(0) as f32;
+        let volume = (127.0 * 10f32.powf(-attenuation / 400.0)).round() as u8;
+        // stereo waves are already placed by their channels
+        let pan = if stereo { 0 } else { self.get(generator::PAN).unwrap_or(0) as i32 };
+        let pan = (64 + pan * 63 / 500).clamp(0, 127) as u8;
+
+        InstParam::new(0, adsr, root_key, volume, pan, tune)
+    }
+
+    fn looping(&self) -> bool {
+        matches!(self.get(generator::SAMPLE_MODES), Some(1) | Some(3))
+    }
+}
+
+// a region along with the samples making up its wave, before the waves are numbered
+type SampleRegion = (BankRegion, Vec<usize>);
+
+fn intersect(a: &RangeInclusive<u8>, b: &RangeInclusive<u8>) -> Option<RangeInclusive<u8>> {
+    let range = *a.start().max(b.start())..=*a.end().min(b.end());
+    if range.is_empty() { None } else { Some(range) }
+}
+
+/// The samples a zone plays, as the channels of one wave.
+/// The right half of a stereo pair is played through the zone of its left half, so has none.
+fn wave_channels(font: &SoundFont, sample: usize) -> Option<Vec<usize>> {
+    let linked = |ty| font.samples.get(font.samples[sample].link as usize)
+        .filter(|other| other.sample_type == ty)
+        .is_some();
+    match font.samples[sample].sample_type {
+        SampleType::Left if linked(SampleType::Right) => Some(vec![sample, font.samples[sample].link as usize]),
+        SampleType::Right if linked(SampleType::Left) => None,
+        _ => Some(vec![sample])
+    }
+}
+
+/// Converts the presets of bank 0 to instruments, using the preset number as the program.
+///
+/// Generators in preset zones other than the key and velocity ranges are ignored,
+/// as are modulators and the per-zone sample offsets.
+pub fn import_bank(font: &SoundFont) -> io::Result<ImportedBank> {
+    // looping is set per zone in SF2 but per wave here, so waves loop if any zone loops them
+    let mut wave_loops: HashMap<Vec<usize>, bool> = HashMap::new();
+    let mut instruments: Vec<(usize, Vec<SampleRegion>)> = Vec::new();
+
+    for preset in font.presets.iter().filter(|preset| preset.bank == 0) {
+        let mut regions = Vec::new();
+        for preset_zone in &preset.zones {
+            let instrument = match preset_zone.get(generator::INSTRUMENT) {
+                Some(generator) => font.instruments.get(generator.amount as usize).ok_or_else(|| invalid("instrument index out of range"))?,
+                None => continue // global zone
+            };
+            let preset_keys = preset_zone.get(generator::KEY_RANGE).map(|generator| generator.as_range()).unwrap_or((0, 127));
+            let preset_velocities = preset_zone.get(generator::VEL_RANGE).map(|generator| generator.as_range()).unwrap_or((0, 127));
+
+            let global = instrument.zones.first().filter(|zone| zone.get(generator::SAMPLE_ID).is_none());
+            for zone in &instrument.zones {
+                let sample = match zone.get(generator::SAMPLE_ID) {
+                    Some(generator) => generator.amount as usize,
+                    None => continue
+                };
+                if sample >= font.samples.len() {
+                    return Err(invalid("sample index out of range"));
+                }
+                let channels = match wave_channels(font, sample) {
+                    Some(channels) => channels,
+                    None => continue
+                };
+
+                let generators = ZoneGenerators { zone, global };
+                let keys = intersect(&generators.range(generator::KEY_RANGE), &(preset_keys.0..=preset_keys.1));
+                let velocities = intersect(&generators.range(generator::VEL_RANGE), &(preset_velocities.0..=preset_velocities.1));
+                let (keys, velocities) = match (keys, velocities) {
+                    (Some(keys), Some(velocities)) => (keys, velocities),
+                    _ => continue
+                };
+
+                let looping = wave_loops.entry(channels.clone()).or_insert(false);
+                *looping |= generators.looping();
+                let sample = &font.samples[sample];
+                let inst = generators.inst_param(sample.original_pitch, sample.pitch_correction, channels.len() > 1);
+                regions.push((BankRegion { keys, velocities, inst }, channels));
+            }
+        }
+        instruments.push((preset.preset as usize, regions));
+    }
+
+    // encode the waves in the order they're first used
+    let mut waves = RwarBuilder::new();
+    let mut wave_indices: HashMap<Vec<usize>, usize> = HashMap::new();
+    let mut bank = BankBuilder::new();
+    for (program, regions) in instruments {
+        let regions = regions.into_iter().map(|(mut region, channels)| {
+            let index = *wave_indices.entry(channels.clone()).or_insert_with(|| {
+                let wav = sample_wav(font, &channels, wave_loops[&channels]);
+                waves.push(EncodedWave::encode(&wav, SoundEncoding::DspAdpcm).to_rwav())
+            });
+            region.inst.wave_index = index as i32;
+            region
+        }).collect();
+        bank.set_instrument(program, regions);
+    }
+
+    Ok(ImportedBank { bank, waves })
+}
+
+fn sample_wav(font: &SoundFont, channels: &[usize], looping: bool) -> Wav {
+    let first = &font.samples[channels[0]];
+    let len = channels.iter().map(|&idx| font.samples[idx].data.len()).min().unwrap_or(0);
+    let wav = Wav::new(first.sample_rate, channels.iter().map(|&idx| font.samples[idx].data[..len].to_vec()).collect());
+    if looping && first.loop_start < first.loop_end && first.loop_end as usize <= len {
+        wav.with_loop(first.loop_start, first.loop_end)
+    } else {
+        wav
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sf2::{Generator, Instrument, Preset, Sample};
+
+    fn sample(name: &str, sample_type: SampleType, link: u16) -> Sample {
+        Sample {
+            name: name.into(),
+            data: vec![0; 64],
+            sample_rate: 32000,
+            loop_start: 16,
+            loop_end: 64,
+            original_pitch: 72,
+            pitch_correction: 0,
+            link,
+            sample_type,
+        }
+    }
+
+    #[test]
+    fn import() {
+        let zone = |generators| Zone { generators };
+        let font = SoundFont {
+            name: "test".into(),
+            samples: vec![sample("mono", SampleType::Mono, 0), sample("L", SampleType::Left, 2), sample("R", SampleType::Right, 1)],
+            instruments: vec![Instrument {
+                name: "inst".into(),
+                zones: vec![
+                    zone(vec![Generator::new(generator::INITIAL_ATTENUATION, 200)]),
+                    zone(vec![Generator::range(generator::KEY_RANGE, 0..=59), Generator::new(generator::SAMPLE_ID, 0)]),
+                    zone(vec![
+                        Generator::range(generator::KEY_RANGE, 60..=127),
+                        Generator::new(generator::OVERRIDING_ROOT_KEY, 60),
+                        Generator::new(generator::SAMPLE_MODES, 1),
+                        Generator::new(generator::SAMPLE_ID, 1),
+                    ]),
+                    zone(vec![Generator::range(generator::KEY_RANGE, 60..=127), Generator::new(generator::SAMPLE_ID, 2)]),
+                ],
+            }],
+            presets: vec![Preset {
+                name: "preset".into(),
+                preset: 3,
+                bank: 0,
+                zones: vec![zone(vec![Generator::range(generator::KEY_RANGE, 40..=80), Generator::new(generator::INSTRUMENT, 0)])],
+            }],
+        };
+
+        let imported = import_bank(&font).unwrap();
+        assert_eq!(imported.waves.wave_count(), 2);
+        assert_eq!(imported.bank.instruments.len(), 4);
+        let regions = &imported.bank.instruments[3];
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0].keys, 40..=59);
+        assert_eq!(regions[0].inst.original_key, 72);
+        assert_eq!(regions[0].inst.volume, 40);
+        assert_eq!(regions[1].keys, 60..=80);
+        assert_eq!(regions[1].inst.wave_index, 1);
+        assert_eq!(regions[1].inst.original_key, 60);
+        assert_eq!(regions[1].inst.pan, 64);
+    }
+}
@@ -0,0 +1,476 @@
+//! SoundFont 2 files, used to exchange instruments with music software.
+//!
+//! Only the parts of the format needed to represent NW4R banks are modelled: presets and
+//! instruments are lists of zones made of generators, and modulators are not supported.
+
+pub mod export;
+pub mod import;
+
+use std::io::{self, Write};
+
+/// Generator operators used by the bank conversion.
+pub mod generator {
+    pub const PAN: u16 = 17;
+    pub const HOLD_VOL_ENV: u16 = 35;
+    pub const ATTACK_VOL_ENV: u16 = 34;
+    pub const DECAY_VOL_ENV: u16 = 36;
+    pub const SUSTAIN_VOL_ENV: u16 = 37;
+    pub const RELEASE_VOL_ENV: u16 = 38;
+    pub const INSTRUMENT: u16 = 41;
+    pub const KEY_RANGE: u16 = 43;
+    pub const VEL_RANGE: u16 = 44;
+    pub const INITIAL_ATTENUATION: u16 = 48;
+    pub const COARSE_TUNE: u16 = 51;
+    pub const FINE_TUNE: u16 = 52;
+    pub const SAMPLE_ID: u16 = 53;
+    pub const SAMPLE_MODES: u16 = 54;
+    pub const OVERRIDING_ROOT_KEY: u16 = 58;
+}
+
+// zero samples that have to follow every sample in the smpl chunk
+const SAMPLE_PADDING: usize = 46;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SampleType {
+    Mono = 1,
+    Right = 2,
+    Left = 4,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub name: String,
+    pub data: Vec<i16>,
+    pub sample_rate: u32,
+    /// Loop points relative to the start of `data`, the end is exclusive.
+    pub loop_start: u32,
+    pub loop_end: u32,
+    pub original_pitch: u8,
+    pub pitch_correction: i8, // cents
+    pub link: u16, // index of the other channel of a stereo sample
+    pub sample_type: SampleType,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Generator {
+    pub op: u16,
+    pub amount: u16,
+}
+
+impl Generator {
+    pub fn new(op: u16, amount: i16) -> Generator {
+        Generator { op, amount: amount as u16 }
+    }
+
+    pub fn range(op: u16, range: std::ops::RangeInclusive<u8>) -> Generator {
+        Generator { op, amount: u16::from_le_bytes([*range.start(), *range.end()]) }
+    }
+
+    /// The amount as a (low, high) range.
+    pub fn as_range(self) -> (u8, u8) {
+        let [low, high] = self.amount.to_le_bytes();
+        (low, high)
+    }
+
+    pub fn signed(self) -> i16 {
+        self.amount as i16
+    }
+
+    // key and velocity ranges have to come first, and the instrument or sample last
+    fn order(self) -> u8 {
+        match self.op {
+            generator::KEY_RANGE => 0,
+            generator::VEL_RANGE => 1,
+            generator::INSTRUMENT | generator::SAMPLE_ID => 3,
+            _ => 2
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Zone {
+    pub generators: Vec<Generator>,
+}
+
+impl Zone {
+    pub fn get(&self, op: u16) -> Option<Generator> {
+        self.generators.iter().copied().find(|generator| generator.op == op)
+    }
+
+    fn sorted(&self) -> Vec<Generator> {
+        let mut generators = self.generators.clone();
+        generators.sort_by_key(|generator| generator.order());
+        generators
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Instrument {
+    pub name: String,
+    pub zones: Vec<Zone>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Preset {
+    pub name: String,
+    pub preset: u16,
+    pub bank: u16,
+    pub zones: Vec<Zone>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SoundFont {
+    pub name: String,
+    pub samples: Vec<Sample>,
+    pub instruments: Vec<Instrument>,
+    pub presets: Vec<Preset>,
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    let mut bytes = [0u8; 20];
+    for (dst, src) in bytes.iter_mut().take(19).zip(name.bytes()) {
+        *dst = src;
+    }
+    out.extend_from_slice(&bytes);
+}
+
+fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 9);
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(body);
+    if !body.len().is_multiple_of(2) {
+        out.push(0);
+    }
+    out
+}
+
+fn list(id: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut body = id.to_vec();
+    for chunk in chunks {
+        body.extend_from_slice(chunk);
+    }
+    chunk(b"LIST", &body)
+}
+
+fn zstr(text: &str) -> Vec<u8> {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.push(0);
+    if !bytes.len().is_multiple_of(2) {
+        bytes.push(0);
+    }
+    bytes
+}
+
+/// Writes the bag and generator records for a list of zone lists, returning the first bag of each.
+fn write_zones<'a>(zone_lists: impl Iterator<Item = &'a [Zone]>, bags: &mut Vec<u8>, gens: &mut Vec<u8>) -> Vec<u16> {
+    let mut bag_indices = Vec::new();
+    let mut bag_count = 0u16;
+    let mut gen_count = 0u16;
+    for zones in zone_lists {
+        bag_indices.push(bag_count);
+        for zone in zones {
+            bags.extend_from_slice(&gen_count.to_le_bytes());
+            bags.extend_from_slice(&0u16.to_le_bytes());
+            bag_count += 1;
+            for generator in zone.sorted() {
+                gens.extend_from_slice(&generator.op.to_le_bytes());
+                gens.extend_from_slice(&generator.amount.to_le_bytes());
+                gen_count += 1;
+            }
+        }
+    }
+    // terminal records
+    bag_indices.push(bag_count);
+    bags.extend_from_slice(&gen_count.to_le_bytes());
+    bags.extend_from_slice(&0u16.to_le_bytes());
+    gens.extend_from_slice(&[0; 4]);
+    bag_indices
+}
+
+impl SoundFont {
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let info = list(b"INFO", &[
+            chunk(b"ifil", &[2, 0, 1, 0]),
+            chunk(b"isng", &zstr("EMU8000")),
+            chunk(b"INAM", &zstr(&self.name)),
+        ]);
+
+        let mut smpl = Vec::new();
+        let mut shdr = Vec::new();
+        let mut position = 0u32;
+        for sample in &self.samples {
+            for value in &sample.data {
+                smpl.extend_from_slice(&value.to_le_bytes());
+            }
+            smpl.extend_from_slice(&[0; SAMPLE_PADDING * 2]);
+
+            write_name(&mut shdr, &sample.name);
+            for value in &[position, position + sample.data.len() as u32, position + sample.loop_start, position + sample.loop_end, sample.sample_rate] {
+                shdr.extend_from_slice(&value.to_le_bytes());
+            }
+            shdr.push(sample.original_pitch);
+            shdr.push(sample.pitch_correction as u8);
+            shdr.extend_from_slice(&sample.link.to_le_bytes());
+            shdr.extend_from_slice(&(sample.sample_type as u16).to_le_bytes());
+            position += (sample.data.len() + SAMPLE_PADDING) as u32;
+        }
+        write_name(&mut shdr, "EOS");
+        shdr.extend_from_slice(&[0; 26]);
+        let sdta = list(b"sdta", &[chunk(b"smpl", &smpl)]);
+
+        let (mut pbag, mut pgen) = (Vec::new(), Vec::new());
+        let preset_bags = write_zones(self.presets.iter().map(|preset| &preset.zones[..]), &mut pbag, &mut pgen);
+        let mut phdr = Vec::new();
+        for (preset, bag) in self.presets.iter().zip(&preset_bags) {
+            write_name(&mut phdr, &preset.name);
+            phdr.extend_from_slice(&preset.preset.to_le_bytes());
+            phdr.extend_from_slice(&preset.bank.to_le_bytes());
+            phdr.extend_from_slice(&bag.to_le_bytes());
+            phdr.extend_from_slice(&[0; 12]); // library, genre, morphology
+        }
+        write_name(&mut phdr, "EOP");
+        phdr.extend_from_slice(&[0; 4]);
+        phdr.extend_from_slice(&preset_bags.last().unwrap().to_le_bytes());
+        phdr.extend_from_slice(&[0; 12]);
+
+        let (mut ibag, mut igen) = (Vec::new(), Vec::new());
+        let inst_bags = write_zones(self.instruments.iter().map(|inst| &inst.zones[..]), &mut ibag, &mut igen);
+        let mut inst = Vec::new();
+        for (instrument, bag) in self.instruments.iter().zip(&inst_bags) {
+            write_name(&mut inst, &instrument.name);
+            inst.extend_from_slice(&bag.to_le_bytes());
+        }
+        write_name(&mut inst, "EOI");
+        inst.extend_from_slice(&inst_bags.last().unwrap().to_le_bytes());
+
+        let pdta = list(b"pdta", &[
+            chunk(b"phdr", &phdr),
+            chunk(b"pbag", &pbag),
+            chunk(b"pmod", &[0; 10]),
+            chunk(b"pgen", &pgen),
+            chunk(b"inst", &inst),
+            chunk(b"ibag", &ibag),
+            chunk(b"imod", &[0; 10]),
+            chunk(b"igen", &igen),
+            chunk(b"shdr", &shdr),
+        ]);
+
+        let mut body = b"sfbk".to_vec();
+        body.extend_from_slice(&info);
+        body.extend_from_slice(&sdta);
+        body.extend_from_slice(&pdta);
+        writer.write_all(&chunk(b"RIFF", &body))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(&mut out).unwrap();
+        out
+    }
+
+    pub fn read(data: &[u8]) -> io::Result<SoundFont> {
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"sfbk" {
+            return Err(invalid("not a SoundFont 2 file"));
+        }
+        let riff = chunks(&data[12..]);
+        let info = list_chunks(&riff, b"INFO").unwrap_or_default();
+        let sdta = list_chunks(&riff, b"sdta").ok_or_else(|| invalid("missing sdta list"))?;
+        let pdta = list_chunks(&riff, b"pdta").ok_or_else(|| invalid("missing pdta list"))?;
+        let pdta_chunk = |id: &[u8; 4]| find_chunk(&pdta, id).ok_or_else(|| invalid("missing pdta chunk"));
+
+        let name = find_chunk(&info, b"INAM").map(read_name).unwrap_or_default();
+        let smpl: Vec<i16> = find_chunk(&sdta, b"smpl").unwrap_or_default()
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+
+        let bags = |data: &[u8]| data.chunks_exact(4).map(|bag| u16_at(bag, 0) as usize).collect::<Vec<_>>();
+        let gens = |data: &[u8]| data.chunks_exact(4).map(|gen| Generator { op: u16_at(gen, 0), amount: u16_at(gen, 2) }).collect::<Vec<_>>();
+        let (pbag, pgen) = (bags(pdta_chunk(b"pbag")?), gens(pdta_chunk(b"pgen")?));
+        let (ibag, igen) = (bags(pdta_chunk(b"ibag")?), gens(pdta_chunk(b"igen")?));
+
+        let phdr: Vec<&[u8]> = pdta_chunk(b"phdr")?.chunks_exact(38).collect();
+        let mut presets = Vec::new();
+        for pair in phdr.windows(2) {
+            let (header, next) = (pair[0], pair[1]);
+            presets.push(Preset {
+                name: read_name(&header[0..20]),
+                preset: u16_at(header, 20),
+                bank: u16_at(header, 22),
+                zones: read_zones(&pbag, &pgen, u16_at(header, 24) as usize, u16_at(next, 24) as usize)?,
+            });
+        }
+
+        let inst: Vec<&[u8]> = pdta_chunk(b"inst")?.chunks_exact(22).collect();
+        let mut instruments = Vec::new();
+        for pair in inst.windows(2) {
+            instruments.push(Instrument {
+                name: read_name(&pair[0][0..20]),
+                zones: read_zones(&ibag, &igen, u16_at(pair[0], 20) as usize, u16_at(pair[1], 20) as usize)?,
+            });
+        }
+
+        let shdr: Vec<&[u8]> = pdta_chunk(b"shdr")?.chunks_exact(46).collect();
+        let mut samples = Vec::new();
+        for header in shdr.iter().take(shdr.len().saturating_sub(1)) {
+            let start = u32_at(header, 20);
+            let end = u32_at(header, 24);
+            let data = smpl.get(start as usize..end as usize).ok_or_else(|| invalid("sample outside of the smpl chunk"))?;
+            samples.push(Sample {
+                name: read_name(&header[0..20]),
+                data: data.to_vec(),
+                sample_rate: u32_at(header, 36),
+                loop_start: u32_at(header, 28).saturating_sub(start),
+                loop_end: u32_at(header, 32).saturating_sub(start),
+                original_pitch: header[40],
+                pitch_correction: header[41] as i8,
+                link: u16_at(header, 42),
+                sample_type: match u16_at(header, 44) & 0x7 {
+                    2 => SampleType::Right,
+                    4 => SampleType::Left,
+                    _ => SampleType::Mono
+                },
+            });
+        }
+
+        Ok(SoundFont { name, samples, instruments, presets })
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+fn read_name(data: &[u8]) -> String {
+    let len = data.iter().position(|&byte| byte == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..len]).into_owned()
+}
+
+/// Splits RIFF data into (id, body) pairs.
+fn chunks(mut data: &[u8]) -> Vec<(&[u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    while data.len() >= 8 {
+        let id: &[u8; 4] = std::convert::TryInto::try_into(&data[0..4]).unwrap();
+        let len = (u32_at(data, 4) as usize).min(data.len() - 8);
+        chunks.push((id, &data[8..8 + len]));
+        data = &data[(8 + len + len % 2).min(data.len())..];
+    }
+    chunks
+}
+
+fn find_chunk<'a>(chunks: &[(&[u8; 4], &'a [u8])], id: &[u8; 4]) -> Option<&'a [u8]> {
+    chunks.iter().find(|(chunk_id, _)| *chunk_id == id).map(|(_, body)| *body)
+}
+
+fn list_chunks<'a>(chunks: &[(&[u8; 4], &'a [u8])], list_id: &[u8; 4]) -> Option<Vec<(&'a [u8; 4], &'a [u8])>> {
+    chunks.iter()
+        .find(|(id, body)| *id == b"LIST" && body.len() >= 4 && &body[0..4] == list_id)
+        .map(|(_, body)| self::chunks(&body[4..]))
+}
+
+fn read_zones(bags: &[usize], gens: &[Generator], start: usize, end: usize) -> io::Result<Vec<Zone>> {
+    let mut zones = Vec::new();
+    for bag in start..end {
+        let (first, last) = match (bags.get(bag), bags.get(bag + 1)) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return Err(invalid("zone outside of the bag chunk"))
+        };
+        let generators = gens.get(first..last).ok_or_else(|| invalid("generators outside of the gen chunk"))?;
+        zones.push(Zone { generators: generators.to_vec() });
+    }
+    Ok(zones)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_font() -> SoundFont {
+        SoundFont {
+            name: "test".into(),
+            samples: vec![Sample {
+                name: "wave".into(),
+                data: (0..100).map(|idx| idx * 100).collect(),
+                sample_rate: 32000,
+                loop_start: 10,
+                loop_end: 100,
+                original_pitch: 60,
+                pitch_correction: -5,
+                link: 0,
+                sample_type: SampleType::Mono,
+            }],
+            instruments: vec![Instrument {
+                name: "inst".into(),
+                zones: vec![Zone { generators: vec![
+                    Generator::new(generator::SAMPLE_ID, 0),
+                    Generator::new(generator::PAN, -250),
+                    Generator::range(generator::KEY_RANGE, 0..=63),
+                ] }],
+            }],
+            presets: vec![Preset {
+                name: "preset".into(),
+                preset: 3,
+                bank: 0,
+                zones: vec![Zone { generators: vec![Generator::new(generator::INSTRUMENT, 0)] }],
+            }],
+        }
+    }
+
+    fn find<'a>(data: &'a [u8], id: &[u8; 4]) -> &'a [u8] {
+        let pos = data.windows(4).position(|window| window == id).unwrap();
+        let len = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+        &data[pos + 8..pos + 8 + len]
+    }
+
+    #[test]
+    fn layout() {
+        let bytes = test_font().to_bytes();
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[8..12], b"sfbk");
+
+        assert_eq!(find(&bytes, b"smpl").len(), (100 + SAMPLE_PADDING) * 2);
+        assert_eq!(find(&bytes, b"phdr").len(), 38 * 2);
+        assert_eq!(find(&bytes, b"inst").len(), 22 * 2);
+        assert_eq!(find(&bytes, b"shdr").len(), 46 * 2);
+
+        // generators are sorted, with the key range first and the sample last
+        let igen = find(&bytes, b"igen");
+        assert_eq!(igen.len(), 4 * 4);
+        assert_eq!(&igen[0..4], &[43, 0, 0, 63]);
+        assert_eq!(&igen[8..10], &[53, 0]);
+
+        let shdr = find(&bytes, b"shdr");
+        assert_eq!(&shdr[28..32], &10u32.to_le_bytes()); // loop start
+        assert_eq!(shdr[41], -5i8 as u8);
+    }
+
+    #[test]
+    fn read_back() {
+        let font = test_font();
+        let read = SoundFont::read(&font.to_bytes()).unwrap();
+        assert_eq!(read.name, font.name);
+        assert_eq!(read.presets.len(), 1);
+        assert_eq!(read.presets[0].preset, 3);
+        assert_eq!(read.presets[0].zones[0].get(generator::INSTRUMENT), Some(Generator::new(generator::INSTRUMENT, 0)));
+
+        let zone = &read.instruments[0].zones[0];
+        assert_eq!(zone.generators.len(), 3);
+        assert_eq!(zone.get(generator::KEY_RANGE).unwrap().as_range(), (0, 63));
+        assert_eq!(zone.get(generator::PAN).unwrap().signed(), -250);
+
+        let sample = &read.samples[0];
+        assert_eq!(sample.data, font.samples[0].data);
+        assert_eq!((sample.loop_start, sample.loop_end), (10, 100));
+        assert_eq!(sample.pitch_correction, -5);
+    }
+}
@@ -0,0 +1,285 @@
+//! ADSR envelope, following the behaviour of the NW4R sound library.
+//!
+//! The envelope level is tracked in decibels and updated once per millisecond. Attack approaches
+//! 0 dB exponentially, while decay and release fall linearly in dB.
+
+/// Level below which the envelope is considered silent.
+pub const SILENCE_DB: f32 = -90.4;
+
+/// Envelope parameters as stored in RWSD notes and RBNK instruments, each in the range 0-127.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Adsr {
+    pub attack: u8,
+    pub decay: u8,
+    pub sustain: u8,
+    pub release: u8,
+    pub hold: u8,
+}
+
+impl Default for Adsr {
+    fn default() -> Adsr {
+        Adsr { attack: 127, decay: 127, sustain: 127, release: 127, hold: 0 }
+    }
+}
+
+// attack rates above 108 don't follow the formula
+const ATTACK_TABLE: [u8; 19] = [0, 1, 5, 14, 26, 38, 51, 63, 73, 84, 92, 100, 109, 116, 123, 127, 132, 137, 143];
+
+/// Per-millisecond multiplier applied to the (negative) dB level during the attack.
+fn attack_coef(attack: u8) -> f32 {
+    let attack = attack.min(127);
+    let rate = if attack < 109 {
+        255 - attack as u32
+    } else {
+        ATTACK_TABLE[127 - attack as usize] as u32
+    };
+    // the original rates are applied roughly every 5ms
+    (rate as f32 / 256.0).powf(1.0 / 5.0)
+}
+
+/// dB per millisecond for decay and release.
+fn fall_rate(value: u8) -> f32 {
+    match value.min(127) {
+        127 => 65535.0,
+        126 => 120.0 / 5.0,
+        value if value < 50 => ((value as f32 * 2.0) + 1.0) / 128.0 / 5.0,
+        value => 60.0 / (126 - value) as f32 / 5.0
+    }
+}
+
+/// Sustain level in dB, following a squared volume curve.
+fn sustain_db(sustain: u8) -> f32 {
+    match sustain.min(127) {
+        0 => SILENCE_DB,
+        value => (40.0 * (value as f32 / 127.0).log10()).max(SILENCE_DB)
+    }
+}
+
+fn hold_ms(hold: u8) -> f32 {
+    match hold {
+        0 => 0.0,
+        hold => ((hold as f32 + 1.0) * (hold as f32 + 1.0)) / 4.0
+    }
+}
+
+impl Adsr {
+    /// Time the attack takes to reach full volume, in milliseconds.
+    pub fn attack_ms(&self) -> f32 {
+        let coef = attack_coef(self.attack);
+        if coef <= 0.0 {
+            0.0
+        } else {
+            // level = SILENCE_DB * coef^t, until it's above -0.01dB
+            ((0.01 / -SILENCE_DB).ln() / coef.ln()).ceil()
+        }
+    }
+
+    pub fn hold_ms(&self) -> f32 {
+        hold_ms(self.hold)
+    }
+
+    /// Decay speed in dB per millisecond.
+    pub fn decay_rate(&self) -> f32 {
+        fall_rate(self.decay)
+    }
+
+    pub fn sustain_db(&self) -> f32 {
+        sustain_db(self.sustain)
+    }
+
+    /// Release speed in dB per millisecond.
+    pub fn release_rate(&self) -> f32 {
+        fall_rate(self.release)
+    }
+
+    /// Finds the parameters that come closest to the given times, rates (dB/ms) and sustain level.
+    pub fn from_times(attack_ms: f32, hold_ms: f32, decay_rate: f32, sustain_db: f32, release_rate: f32) -> Adsr {
+        let closest = |f: &dyn Fn(u8) -> f32, target: f32| (0..=127u8)
+            .min_by(|&a, &b| (f(a) - target).abs().partial_cmp(&(f(b) - target).abs()).unwrap())
+            .unwrap();
+        // rates are compared logarithmically, so slow and fast values are matched equally well
+        let log_rate = |rate: f32| rate.max(1e-6).ln();
+
+        Adsr {
+            attack: closest(&|value| Adsr { attack: value, ..Adsr::default() }.attack_ms(), attack_ms),
+            decay: closest(&|value| log_rate(fall_rate(value)), log_rate(decay_rate)),
+            sustain: closest(&self::sustain_db, sustain_db.max(SILENCE_DB)),
+            release: closest(&|value| log_rate(fall_rate(value)), log_rate(release_rate)),
+            hold: closest(&self::hold_ms, hold_ms),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stage {
+    Attack,
+    Hold,
+    Decay,
+    Sustain,
+    Release,
+    Done,
+}
+
+#[derive(Clone, Debug)]
+pub struct Envelope {
+    adsr: Adsr,
+    stage: Stage,
+    level: f32, // dB
+    hold_left: f32, // ms
+    elapsed: f32, // ms since the last update
+}
+
+impl Envelope {
+    pub fn new(adsr: Adsr) -> Envelope {
+        let mut envelope = Envelope {
+            adsr,
+            stage: Stage::Attack,
+            level: SILENCE_DB,
+            hold_left: hold_ms(adsr.hold),
+            elapsed: 0.0,
+        };
+        // an instant attack shouldn't wait a millisecond before sounding
+        envelope.update();
+        envelope
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    pub fn release(&mut self) {
+        if self.stage != Stage::Done {
+            self.stage = Stage::Release;
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.stage == Stage::Done
+    }
+
+    pub fn level_db(&self) -> f32 {
+        self.level
+    }
+
+    /// Linear gain for the current level.
+    pub fn gain(&self) -> f32 {
+        if self.stage == Stage::Done {
+            0.0
+        } else {
+            10f32.powf(self.level / 20.0)
+        }
+    }
+
+    /// Advances the envelope by `ms` milliseconds.
+    pub fn advance(&mut self, ms: f32) {
+        self.elapsed += ms;
+        while self.elapsed >= 1.0 {
+            self.elapsed -= 1.0;
+            self.update();
+        }
+    }
+
+    fn update(&mut self) {
+        match self.stage {
+            Stage::Attack => {
+                self.level *= attack_coef(self.adsr.attack);
+                if self.level > -0.01 {
+                    self.level = 0.0;
+                    self.stage = if self.hold_left > 0.0 { Stage::Hold } else { Stage::Decay };
+                }
+            }
+            Stage::Hold => {
+                self.hold_left -= 1.0;
+                if self.hold_left <= 0.0 {
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                let sustain = sustain_db(self.adsr.sustain);
+                self.level -= fall_rate(self.adsr.decay);
+                if self.level <= sustain {
+                    self.level = sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => {}
+            Stage::Release => {
+                self.level -= fall_rate(self.adsr.release);
+                if self.level <= SILENCE_DB {
+                    self.level = SILENCE_DB;
+                    self.stage = Stage::Done;
+                }
+            }
+            Stage::Done => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instant_envelope() {
+        let mut env = Envelope::new(Adsr::default());
+        assert_eq!(env.gain(), 1.0);
+        env.advance(1.0);
+        assert_eq!(env.stage(), Stage::Sustain);
+        assert_eq!(env.gain(), 1.0);
+
+        env.release();
+        env.advance(1.0);
+        assert!(env.is_done());
+        assert_eq!(env.gain(), 0.0);
+    }
+
+    #[test]
+    fn attack_is_gradual() {
+        let mut env = Envelope::new(Adsr { attack: 100, ..Adsr::default() });
+        let start = env.gain();
+        env.advance(20.0);
+        let mid = env.gain();
+        assert!(start < mid && mid < 1.0);
+
+        env.advance(10_000.0);
+        assert_eq!(env.stage(), Stage::Sustain);
+    }
+
+    #[test]
+    fn attack_time() {
+        let adsr = Adsr { attack: 100, ..Adsr::default() };
+        let mut env = Envelope::new(adsr);
+        env.advance(adsr.attack_ms() - 2.0);
+        assert_eq!(env.stage(), Stage::Attack);
+        env.advance(1.0);
+        assert_ne!(env.stage(), Stage::Attack);
+        assert_eq!(Adsr::default().attack_ms(), 0.0);
+    }
+
+    #[test]
+    fn from_times() {
+        let adsr = Adsr { attack: 90, decay: 100, sustain: 80, release: 60, hold: 10 };
+        let found = Adsr::from_times(adsr.attack_ms(), adsr.hold_ms(), adsr.decay_rate(), adsr.sustain_db(), adsr.release_rate());
+        assert_eq!(found, adsr);
+        assert_eq!(Adsr::from_times(0.0, 0.0, 65535.0, 0.0, 65535.0), Adsr::default());
+    }
+
+    #[test]
+    fn decays_to_sustain() {
+        let mut env = Envelope::new(Adsr { decay: 100, sustain: 64, ..Adsr::default() });
+        env.advance(10_000.0);
+        assert_eq!(env.stage(), Stage::Sustain);
+        assert!((env.level_db() - sustain_db(64)).abs() < 0.001);
+        assert!(env.gain() > 0.2 && env.gain() < 0.3);
+    }
+
+    #[test]
+    fn release_takes_time() {
+        let mut env = Envelope::new(Adsr { release: 100, ..Adsr::default() });
+        env.release();
+        env.advance(100.0);
+        assert!(!env.is_done());
+        env.advance(10_000.0);
+        assert!(env.is_done());
+    }
+}
@@ -0,0 +1,10 @@
+//! Offline rendering of sounds, roughly following the NW4R sound library's voice behaviour.
+
+pub mod envelope;
+pub mod player;
+pub mod sequence;
+pub mod voice;
+pub mod wave_sound;
+
+/// Default output sample rate, matching the Wii's DSP.
+pub const OUTPUT_RATE: u32 = 32000;
@@ -0,0 +1,594 @@
+//! Sequence player, runs the RSEQ command stream tick by tick and drives voices with it.
+//!
+//! Portamento, sweeps, filters and effect sends are ignored.
+
+use super::envelope::Adsr;
+use super::voice::{mix_to_wav, pan_from_u8, volume_ratio, Sample, Voice};
+use crate::rseq::command::{Command, Param, Value, VarOp};
+use crate::wav::Wav;
+
+const TRACK_COUNT: usize = 16;
+const CALL_DEPTH: usize = 3;
+// protects against tracks that loop forever without waiting
+const MAX_COMMANDS_PER_TICK: usize = 1024;
+
+/// Instrument parameters for a single note, as resolved from a bank.
+#[derive(Clone, Debug)]
+pub struct Instrument {
+    pub wave: usize,
+    pub adsr: Adsr,
+    pub original_key: u8,
+    pub volume: u8,
+    pub pan: u8,
+    pub tune: f32, // frequency ratio
+    pub ignore_note_off: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    pub output_rate: u32,
+    /// How many times the looping part of a sequence is played before it ends.
+    pub loop_count: u32,
+    /// Rendering stops after this many seconds, even if the sequence hasn't ended.
+    pub max_seconds: f32,
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions { output_rate: super::OUTPUT_RATE, loop_count: 2, max_seconds: 600.0 }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Frame {
+    pos: usize,
+    loop_count: Option<u8>, // None for calls, Some(0) loops forever
+}
+
+struct Track {
+    pos: usize,
+    wait: i64,
+    stack: Vec<Frame>,
+    compare: bool,
+    loops: u32,
+    vars: [i16; 16],
+
+    program: usize,
+    volume: u8,
+    volume2: u8,
+    pan: u8,
+    init_pan: u8,
+    transpose: i8,
+    pitch_bend: i8,
+    bend_range: u8,
+    note_wait: bool,
+    tie: bool,
+    monophonic: bool,
+    mute: bool,
+    mod_depth: u8,
+    mod_speed: u8,
+    mod_type: u8, // 0 = pitch, 1 = volume, 2 = pan
+    mod_range: u8,
+    mod_delay: i16, // in 5ms units
+    attack: Option<u8>,
+    decay: Option<u8>,
+    sustain: Option<u8>,
+    release: Option<u8>,
+    hold: Option<u8>,
+}
+
+impl Track {
+    fn new(pos: usize) -> Track {
+        Track {
+            pos,
+            wait: 0,
+            stack: Vec::new(),
+            compare: false,
+            loops: 0,
+            vars: [-1; 16],
+            program: 0,
+            volume: 127,
+            volume2: 127,
+            pan: 64,
+            init_pan: 64,
+            transpose: 0,
+            pitch_bend: 0,
+            bend_range: 2,
+            note_wait: true,
+            tie: false,
+            monophonic: false,
+            mute: false,
+            mod_depth: 0,
+            mod_speed: 16,
+            mod_type: 0,
+            mod_range: 1,
+            mod_delay: 0,
+            attack: None,
+            decay: None,
+            sustain: None,
+            release: None,
+            hold: None,
+        }
+    }
+
+    fn adsr(&self, inst: Adsr) -> Adsr {
+        Adsr {
+            attack: self.attack.unwrap_or(inst.attack),
+            decay: self.decay.unwrap_or(inst.decay),
+            sustain: self.sustain.unwrap_or(inst.sustain),
+            release: self.release.unwrap_or(inst.release),
+            hold: self.hold.unwrap_or(inst.hold),
+        }
+    }
+}
+
+struct Channel<'a> {
+    voice: Voice<'a>,
+    track: usize,
+    remaining: i64, // ticks until the note is released
+    key: u8,
+    velocity: u8,
+    inst: Instrument,
+    age: f32, // seconds
+}
+
+pub struct Player<'a, F> {
+    commands: &'a [u8],
+    samples: &'a [Option<Sample>],
+    instruments: F,
+    options: RenderOptions,
+    volume: f32,
+
+    tracks: Vec<Option<Track>>,
+    channels: Vec<Channel<'a>>,
+    vars: [i16; 32], // local and global variables
+    tempo: i32,
+    timebase: i32,
+    main_volume: u8,
+    rng: u32,
+}
+
+impl<'a, F: Fn(usize, u8, u8) -> Option<Instrument>> Player<'a, F> {
+    /// Creates a player starting at `start` in the command data. `instruments` looks up the
+    /// instrument for a program, key and velocity, `volume` is `SoundInfo::volume`.
+    pub fn new(commands: &'a [u8], start: usize, samples: &'a [Option<Sample>], instruments: F, volume: u8, options: RenderOptions) -> Player<'a, F> {
+        let mut tracks: Vec<Option<Track>> = (0..TRACK_COUNT).map(|_| None).collect();
+        tracks[0] = Some(Track::new(start));
+        Player {
+            commands,
+            samples,
+            instruments,
+            options,
+            volume: volume_ratio(volume),
+            tracks,
+            channels: Vec::new(),
+            vars: [-1; 32],
+            tempo: 120,
+            timebase: 48,
+            main_volume: 127,
+            rng: 0x1234_5678,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.tracks.iter().all(Option::is_none) && self.channels.is_empty()
+    }
+
+    /// Renders the whole sequence to a stereo WAV.
+    pub fn render(mut self) -> Wav {
+        let rate = self.options.output_rate as f64;
+        let max_frames = (self.options.max_seconds as f64 * rate) as usize;
+        let mut mix: Vec<f32> = Vec::new();
+        let mut frames = 0.0f64;
+
+        while !self.is_done() && mix.len() / 2 < max_frames {
+            self.run_tracks();
+            if self.is_done() {
+                break;
+            }
+
+            let tick = self.tick_seconds();
+            self.update_channels();
+
+            frames += tick * rate;
+            let count = frames as usize;
+            frames -= count as f64;
+
+            let offset = mix.len();
+            mix.resize(offset + count * 2, 0.0);
+            for channel in &mut self.channels {
+                channel.voice.render(&mut mix[offset..]);
+                channel.age += tick as f32;
+            }
+
+            for channel in &mut self.channels {
+                if channel.remaining > 0 {
+                    channel.remaining -= 1;
+                    if channel.remaining == 0 && !channel.inst.ignore_note_off {
+                        channel.voice.release();
+                    }
+                }
+            }
+            self.channels.retain(|channel| !channel.voice.is_done());
+        }
+
+        mix_to_wav(&mix, self.options.output_rate)
+    }
+
+    fn tick_seconds(&self) -> f64 {
+        let ticks_per_minute = (self.tempo.max(1) * self.timebase.max(1)) as f64;
+        60.0 / ticks_per_minute
+    }
+
+    fn random(&mut self) -> u32 {
+        // xorshift, rendering should be deterministic
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+
+    fn var(&self, track: usize, var: u8) -> i16 {
+        match var {
+            0..=31 => self.vars[var as usize],
+            32..=47 => self.tracks[track].as_ref().map(|t| t.vars[var as usize - 32]).unwrap_or(-1),
+            _ => 0
+        }
+    }
+
+    fn var_mut(&mut self, track: usize, var: u8) -> Option<&mut i16> {
+        match var {
+            0..=31 => Some(&mut self.vars[var as usize]),
+            32..=47 => self.tracks[track].as_mut().map(|t| &mut t.vars[var as usize - 32]),
+            _ => None
+        }
+    }
+
+    fn value(&mut self, track: usize, value: Value) -> i32 {
+        match value {
+            Value::Const(value) => value,
+            Value::Random { min, max } => {
+                let (min, max) = (min.min(max) as i32, min.max(max) as i32);
+                min + (self.random() % (max - min + 1) as u32) as i32
+            }
+            Value::Variable(var) => self.var(track, var) as i32,
+        }
+    }
+
+    fn run_tracks(&mut self) {
+        for idx in 0..TRACK_COUNT {
+            let waiting = match &mut self.tracks[idx] {
+                Some(track) if track.wait > 0 => {
+                    track.wait -= 1;
+                    track.wait > 0
+                }
+                Some(_) => false,
+                None => continue
+            };
+            if waiting {
+                continue;
+            }
+
+            let mut count = 0;
+            while let Some(track) = &self.tracks[idx] {
+                if track.wait > 0 {
+                    break;
+                }
+                count += 1;
+                if count > MAX_COMMANDS_PER_TICK {
+                    self.finish_track(idx);
+                    break;
+                }
+
+                let pos = track.pos;
+                match Command::decode(self.commands, pos) {
+                    Ok((command, len)) => {
+                        self.tracks[idx].as_mut().unwrap().pos = pos + len;
+                        self.execute(idx, pos, command);
+                    }
+                    Err(_) => self.finish_track(idx)
+                }
+            }
+        }
+    }
+
+    fn finish_track(&mut self, idx: usize) {
+        self.tracks[idx] = None;
+        for channel in self.channels.iter_mut().filter(|channel| channel.track == idx) {
+            if !channel.inst.ignore_note_off {
+                channel.voice.release();
+            }
+            channel.remaining = 0;
+        }
+    }
+
+    /// Counts a jump back to an earlier position as a loop of the whole sequence.
+    fn loop_back(&mut self, idx: usize, target: usize) {
+        let loop_count = self.options.loop_count;
+        let track = self.tracks[idx].as_mut().unwrap();
+        track.loops += 1;
+        if track.loops >= loop_count {
+            self.finish_track(idx);
+        } else {
+            track.pos = target;
+        }
+    }
+
+    fn execute(&mut self, idx: usize, pos: usize, command: Command) {
+        match command {
+            Command::Note { key, velocity, length } => {
+                let length = self.value(idx, length) as i64;
+                self.note_on(idx, key, velocity, length);
+                let track = self.tracks[idx].as_mut().unwrap();
+                if track.note_wait {
+                    track.wait = length;
+                }
+            }
+            Command::Wait(value) => {
+                let wait = self.value(idx, value) as i64;
+                self.tracks[idx].as_mut().unwrap().wait = wait;
+            }
+            Command::Program(value) => {
+                let program = self.value(idx, value).max(0) as usize;
+                self.tracks[idx].as_mut().unwrap().program = program;
+            }
+            Command::OpenTrack { track, offset } => {
+                let slot = track as usize;
+                if slot < TRACK_COUNT && self.tracks[slot].is_none() {
+                    self.tracks[slot] = Some(Track::new(offset as usize));
+                }
+            }
+            Command::Jump(offset) => {
+                if (offset as usize) <= pos {
+                    self.loop_back(idx, offset as usize);
+                } else {
+                    self.tracks[idx].as_mut().unwrap().pos = offset as usize;
+                }
+            }
+            Command::Call(offset) => {
+                let track = self.tracks[idx].as_mut().unwrap();
+                if track.stack.len() < CALL_DEPTH {
+                    track.stack.push(Frame { pos: track.pos, loop_count: None });
+                    track.pos = offset as usize;
+                }
+            }
+            Command::Return => {
+                let track = self.tracks[idx].as_mut().unwrap();
+                while let Some(frame) = track.stack.pop() {
+                    if frame.loop_count.is_none() {
+                        track.pos = frame.pos;
+                        break;
+                    }
+                }
+            }
+            Command::LoopEnd => {
+                let track = self.tracks[idx].as_mut().unwrap();
+                match track.stack.last_mut() {
+                    Some(Frame { pos, loop_count: Some(0) }) => {
+                        let target = *pos;
+                        self.loop_back(idx, target);
+                    }
+                    Some(Frame { pos, loop_count: Some(count) }) => {
+                        *count -= 1;
+                        if *count == 0 {
+                            track.stack.pop();
+                        } else {
+                            track.pos = *pos;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Command::Fin => self.finish_track(idx),
+            Command::AllocTrack(_) | Command::UserProc(_) => {}
+            Command::Param(param, value) => {
+                let value = self.value(idx, value);
+                self.set_param(idx, param, value);
+            }
+            Command::Var { op, var, value } => {
+                let value = self.value(idx, value);
+                self.var_op(idx, op, var, value as i16);
+            }
+            Command::If(command) => {
+                if self.tracks[idx].as_ref().unwrap().compare {
+                    self.execute(idx, pos, *command);
+                }
+            }
+        }
+    }
+
+    fn set_param(&mut self, idx: usize, param: Param, value: i32) {
+        let byte = value.clamp(0, 255) as u8;
+        let track = self.tracks[idx].as_mut().unwrap();
+        match param {
+            Param::Timebase => self.timebase = value,
+            Param::Tempo => self.tempo = value,
+            Param::MainVolume => self.main_volume = byte,
+            Param::Volume => track.volume = byte,
+            Param::Volume2 => track.volume2 = byte,
+            Param::Pan => track.pan = byte,
+            Param::InitPan => track.init_pan = byte,
+            Param::Transpose => track.transpose = value as i8,
+            Param::PitchBend => track.pitch_bend = value as i8,
+            Param::BendRange => track.bend_range = byte,
+            Param::NoteWait => track.note_wait = value != 0,
+            Param::Tie => track.tie = value != 0,
+            Param::Monophonic => track.monophonic = value != 0,
+            Param::Mute => track.mute = value != 0,
+            Param::ModDepth => track.mod_depth = byte,
+            Param::ModSpeed => track.mod_speed = byte,
+            Param::ModType => track.mod_type = byte,
+            Param::ModRange => track.mod_range = byte,
+            Param::ModDelay => track.mod_delay = value as i16,
+            Param::Attack => track.attack = Some(byte),
+            Param::Decay => track.decay = Some(byte),
+            Param::Sustain => track.sustain = Some(byte),
+            Param::Release => track.release = Some(byte),
+            Param::EnvHold => track.hold = Some(byte),
+            Param::LoopStart if track.stack.len() < CALL_DEPTH => {
+                track.stack.push(Frame { pos: track.pos, loop_count: Some(byte) });
+            }
+            _ => {}
+        }
+    }
+
+    fn var_op(&mut self, idx: usize, op: VarOp, var: u8, value: i16) {
+        let current = self.var(idx, var);
+        let result = match op {
+            VarOp::Set => value,
+            VarOp::Add => current.wrapping_add(value),
+            VarOp::Sub => current.wrapping_sub(value),
+            VarOp::Mul => current.wrapping_mul(value),
+            VarOp::Div => if value == 0 { current } else { current.wrapping_div(value) },
+            VarOp::Shift => if value >= 0 { current.wrapping_shl(value as u32) } else { current.wrapping_shr(-value as u32) },
+            VarOp::Rand => {
+                let range = (value as i32).unsigned_abs() + 1;
+                let random = (self.random() % range) as i16;
+                if value < 0 { -random } else { random }
+            }
+            VarOp::And => current & value,
+            VarOp::Or => current | value,
+            VarOp::Xor => current ^ value,
+            VarOp::Not => !value,
+            VarOp::Mod => if value == 0 { current } else { current.wrapping_rem(value) },
+            comparison => {
+                let result = match comparison {
+                    VarOp::Eq => current == value,
+                    VarOp::Ge => current >= value,
+                    VarOp::Gt => current > value,
+                    VarOp::Le => current <= value,
+                    VarOp::Lt => current < value,
+                    _ => current != value,
+                };
+                self.tracks[idx].as_mut().unwrap().compare = result;
+                return;
+            }
+        };
+        if let Some(var) = self.var_mut(idx, var) {
+            *var = result;
+        }
+    }
+
+    fn note_on(&mut self, idx: usize, key: u8, velocity: u8, length: i64) {
+        let track = self.tracks[idx].as_ref().unwrap();
+        if track.mute {
+            return;
+        }
+        let key = (key as i32 + track.transpose as i32).clamp(0, 127) as u8;
+
+        if track.tie {
+            if let Some(channel) = self.channels.iter_mut().find(|channel| channel.track == idx && !channel.voice.is_releasing()) {
+                channel.key = key;
+                channel.velocity = velocity;
+                channel.remaining = length;
+                return;
+            }
+        } else if track.monophonic {
+            for channel in self.channels.iter_mut().filter(|channel| channel.track == idx) {
+                channel.voice.release();
+                channel.remaining = 0;
+            }
+        }
+
+        let inst = match (self.instruments)(track.program, key, velocity) {
+            Some(inst) => inst,
+            None => return
+        };
+        let sample = match self.samples.get(inst.wave) {
+            Some(Some(sample)) => sample,
+            _ => return
+        };
+
+        let voice = Voice::new(sample, self.options.output_rate, track.adsr(inst.adsr));
+        self.channels.push(Channel { voice, track: idx, remaining: length, key, velocity, inst, age: 0.0 });
+    }
+
+    /// Applies the current track parameters to every playing note.
+    fn update_channels(&mut self) {
+        let main_volume = volume_ratio(self.main_volume) * self.volume;
+        for channel in &mut self.channels {
+            let track = match &self.tracks[channel.track] {
+                Some(track) => track,
+                None => continue // finished, leave the note as it was
+            };
+
+            let delay = track.mod_delay.max(0) as f32 * 0.005;
+            let lfo = if track.mod_depth > 0 && channel.age >= delay {
+                let hz = track.mod_speed as f32 * 0.390625;
+                (2.0 * std::f32::consts::PI * hz * (channel.age - delay)).sin() * (track.mod_depth as f32 / 128.0)
+            } else {
+                0.0
+            };
+
+            let mut semitones = channel.key as f32 - channel.inst.original_key as f32
+                + track.pitch_bend as f32 / 128.0 * track.bend_range as f32;
+            let mut volume = volume_ratio(channel.velocity) * volume_ratio(track.volume) * volume_ratio(track.volume2)
+                * volume_ratio(channel.inst.volume) * main_volume;
+            let mut pan = pan_from_u8(track.pan) + pan_from_u8(track.init_pan) + pan_from_u8(channel.inst.pan);
+            match track.mod_type {
+                0 => semitones += lfo * track.mod_range as f32,
+                1 => volume *= 10f32.powf(lfo * 6.0 / 20.0),
+                _ => pan += lfo,
+            }
+
+            let ratio = 2f64.powf(semitones as f64 / 12.0) * channel.inst.tune as f64;
+            channel.voice.set_pitch(ratio);
+            channel.voice.volume = volume;
+            channel.voice.pan = pan.clamp(-1.0, 1.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<Option<Sample>> {
+        vec![Some(Sample { channels: vec![vec![8192; 64]], sample_rate: 32000, loop_start: Some(0), end: 64 })]
+    }
+
+    fn instrument(_program: usize, _key: u8, _velocity: u8) -> Option<Instrument> {
+        Some(Instrument { wave: 0, adsr: Adsr::default(), original_key: 60, volume: 127, pan: 64, tune: 1.0, ignore_note_off: false })
+    }
+
+    fn render(commands: &[u8], loop_count: u32) -> Wav {
+        let samples = sample();
+        let options = RenderOptions { loop_count, ..RenderOptions::default() };
+        Player::new(commands, 0, &samples, instrument, 127, options).render()
+    }
+
+    #[test]
+    fn note_length() {
+        // tempo 120, timebase 48: one quarter note (48 ticks) lasts half a second
+        let wav = render(&[0x3C, 127, 48, 0xFF], 1);
+        let quarter = 16000;
+        assert!(wav.sample_count() >= quarter && wav.sample_count() < quarter + 400);
+        assert!(wav.channels[0][quarter / 2] != 0);
+    }
+
+    #[test]
+    fn loops_end() {
+        // note, then jump back to the start
+        let commands = [0x3C, 127, 48, 0x89, 0x00, 0x00, 0x00];
+        let once = render(&commands, 1).sample_count();
+        let twice = render(&commands, 2).sample_count();
+        assert_eq!(twice - once, 16000);
+    }
+
+    #[test]
+    fn open_track_and_variables() {
+        let commands = [
+            0x88, 0x01, 0x00, 0x00, 0x0D, // opentrack 1 at 0xD
+            0xF0, 0x80, 0x00, 0x00, 0x05, // setvar 0, 5
+            0x80, 0x30, // wait 48
+            0xFF,
+            // track 1
+            0xF0, 0x90, 0x00, 0x00, 0x05, // cmp_eq var 0, 5
+            0xA2, 0x80, 0x60, // if: wait 96
+            0xFF,
+        ];
+        let wav = render(&commands, 1);
+        // track 1 waits 96 ticks, so the sequence lasts a second
+        assert!(wav.sample_count() >= 31900 && wav.sample_count() <= 32100);
+    }
+}
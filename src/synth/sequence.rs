@@ -0,0 +1,48 @@
+use super::envelope::Adsr;
+use super::player::{Instrument, Player, RenderOptions};
+use super::voice::Sample;
+use crate::rbnk::{InstParam, Rbnk};
+use crate::rseq::Rseq;
+use crate::wav::Wav;
+
+impl InstParam {
+    pub fn adsr(&self) -> Adsr {
+        Adsr {
+            attack: self.attack,
+            decay: self.decay,
+            sustain: self.sustain,
+            release: self.release,
+            hold: self.hold,
+        }
+    }
+
+    fn to_instrument(&self) -> Option<Instrument> {
+        if self.wave_index < 0 {
+            return None;
+        }
+        Some(Instrument {
+            wave: self.wave_index as usize,
+            adsr: self.adsr(),
+            original_key: self.original_key,
+            volume: self.volume,
+            pan: self.pan,
+            tune: if self.tune > 0.0 { self.tune } else { 1.0 },
+            ignore_note_off: self.note_off_type == 1,
+        })
+    }
+}
+
+/// Renders a sequence to a stereo WAV, starting at the label with index `label` (`SeqDetails::seq_label_entry`).
+///
+/// `wave_data` is the wave data belonging to the bank (see `GroupEntry::read_archive_bytes`), and
+/// `volume` is `SoundInfo::volume`.
+pub fn render_sequence(rseq: &Rseq, label: usize, rbnk: &Rbnk, wave_data: &[u8], volume: u8, options: RenderOptions) -> Option<Wav> {
+    let start = rseq.label(label)?.offset as usize;
+    let samples: Vec<Option<Sample>> = rbnk.decode_waves(wave_data)
+        .iter()
+        .map(|wav| wav.as_ref().map(Sample::from_wav))
+        .collect();
+
+    let instruments = |program: usize, key: u8, velocity: u8| rbnk.instrument(program, key, velocity)?.to_instrument();
+    Some(Player::new(rseq.commands(), start, &samples, instruments, volume, options).render())
+}
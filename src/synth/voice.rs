@@ -0,0 +1,207 @@
+use super::envelope::{Adsr, Envelope};
+use crate::wav::Wav;
+
+/// Decoded sample data that voices play back.
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub channels: Vec<Vec<i16>>,
+    pub sample_rate: u32,
+    pub loop_start: Option<usize>,
+    /// Loop end for looping samples, otherwise the length of the sample.
+    pub end: usize,
+}
+
+impl Sample {
+    pub fn from_wav(wav: &Wav) -> Sample {
+        let end = wav.loop_points.map(|points| points.end as usize).unwrap_or_else(|| wav.sample_count());
+        Sample {
+            channels: wav.channels.clone(),
+            sample_rate: wav.sample_rate,
+            loop_start: wav.loop_points.map(|points| points.start as usize),
+            end: end.min(wav.sample_count()),
+        }
+    }
+
+    fn get(&self, channel: usize, idx: usize) -> f32 {
+        self.channels[channel].get(idx).copied().unwrap_or(0) as f32 / 32768.0
+    }
+
+    /// Linearly interpolated value at a fractional position.
+    fn at(&self, channel: usize, pos: f64) -> f32 {
+        let idx = pos as usize;
+        let frac = (pos - idx as f64) as f32;
+        let mut next = idx + 1;
+        if next >= self.end {
+            next = self.loop_start.unwrap_or(self.end);
+        }
+        self.get(channel, idx) * (1.0 - frac) + self.get(channel, next) * frac
+    }
+}
+
+/// Equal power pan, `pan` ranges from -1.0 (left) to 1.0 (right).
+pub fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    (angle.cos(), angle.sin())
+}
+
+/// Converts a 0-127 pan value (64 = center) to -1.0..=1.0.
+pub fn pan_from_u8(pan: u8) -> f32 {
+    ((pan as f32 - 64.0) / 63.0).clamp(-1.0, 1.0)
+}
+
+/// Converts a 0-127 volume to a gain, the sound library uses a roughly squared curve.
+pub fn volume_ratio(volume: u8) -> f32 {
+    let ratio = volume.min(127) as f32 / 127.0;
+    ratio * ratio
+}
+
+/// A single playing note: a sample, an envelope, and its pitch, volume and pan.
+pub struct Voice<'a> {
+    sample: &'a Sample,
+    output_rate: u32,
+    pos: f64,
+    pitch: f64, // frequency ratio
+    pub volume: f32,
+    pub pan: f32,
+    envelope: Envelope,
+    finished: bool,
+}
+
+impl<'a> Voice<'a> {
+    pub fn new(sample: &'a Sample, output_rate: u32, adsr: Adsr) -> Voice<'a> {
+        Voice {
+            sample,
+            output_rate,
+            pos: 0.0,
+            pitch: 1.0,
+            volume: 1.0,
+            pan: 0.0,
+            envelope: Envelope::new(adsr),
+            finished: sample.end == 0,
+        }
+    }
+
+    pub fn set_pitch(&mut self, ratio: f64) {
+        self.pitch = ratio;
+    }
+
+    pub fn release(&mut self) {
+        self.envelope.release();
+    }
+
+    pub fn is_releasing(&self) -> bool {
+        self.envelope.stage() == super::envelope::Stage::Release
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.finished || self.envelope.is_done()
+    }
+
+    /// Mixes the voice into an interleaved stereo buffer.
+    pub fn render(&mut self, out: &mut [f32]) {
+        let step = self.pitch * self.sample.sample_rate as f64 / self.output_rate as f64;
+        let ms_per_frame = 1000.0 / self.output_rate as f32;
+        let channel_count = self.sample.channels.len();
+
+        for frame in out.chunks_exact_mut(2) {
+            if self.is_done() {
+                return;
+            }
+
+            let gain = self.envelope.gain() * self.volume;
+            if channel_count == 1 {
+                let (left, right) = pan_gains(self.pan);
+                let value = self.sample.at(0, self.pos) * gain;
+                frame[0] += value * left;
+                frame[1] += value * right;
+            } else {
+                // stereo (or more) samples keep their own placement, shifted by the pan
+                for channel in 0..channel_count.min(2) {
+                    let base = if channel == 0 { -1.0 } else { 1.0 };
+                    let (left, right) = pan_gains(base + self.pan);
+                    let value = self.sample.at(channel, self.pos) * gain;
+                    frame[0] += value * left;
+                    frame[1] += value * right;
+                }
+            }
+
+            self.envelope.advance(ms_per_frame);
+            self.pos += step;
+            if self.pos >= self.sample.end as f64 {
+                match self.sample.loop_start {
+                    Some(start) if start < self.sample.end => {
+                        let len = (self.sample.end - start) as f64;
+                        self.pos = start as f64 + (self.pos - start as f64) % len;
+                    }
+                    _ => self.finished = true
+                }
+            }
+        }
+    }
+}
+
+/// Converts an interleaved stereo mix to a 16-bit WAV.
+pub fn mix_to_wav(mix: &[f32], sample_rate: u32) -> Wav {
+    let to_i16 = |value: f32| (value * 32767.0).round().clamp(-32768.0, 32767.0) as i16;
+    let left = mix.iter().step_by(2).copied().map(to_i16).collect();
+    let right = mix.iter().skip(1).step_by(2).copied().map(to_i16).collect();
+    Wav::new(sample_rate, vec![left, right])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(loop_start: Option<usize>) -> Sample {
+        Sample { channels: vec![vec![16384; 100]], sample_rate: 1000, loop_start, end: 100 }
+    }
+
+    #[test]
+    fn one_shot_stops() {
+        let sample = sample(None);
+        let mut voice = Voice::new(&sample, 1000, Adsr::default());
+        voice.pan = -1.0;
+        let mut out = vec![0.0; 400];
+        voice.render(&mut out);
+        assert!(voice.is_done());
+        assert!((out[0] - 0.5).abs() < 0.001);
+        assert!(out[1].abs() < 0.001);
+        assert_eq!(out[200], 0.0);
+    }
+
+    #[test]
+    fn loop_continues_until_release() {
+        let sample = sample(Some(50));
+        let mut voice = Voice::new(&sample, 1000, Adsr::default());
+        voice.set_pitch(2.0);
+        let mut out = vec![0.0; 2000];
+        voice.render(&mut out);
+        assert!(!voice.is_done());
+        assert!(out[1998] > 0.0);
+
+        voice.release();
+        let mut out = vec![0.0; 20];
+        voice.render(&mut out);
+        assert!(voice.is_done());
+    }
+
+    #[test]
+    fn center_pan_is_equal_power() {
+        let (left, right) = pan_gains(0.0);
+        assert!((left - right).abs() < 0.0001);
+        assert!((left * left + right * right - 1.0).abs() < 0.0001);
+        assert_eq!(pan_from_u8(64), 0.0);
+        assert_eq!(pan_from_u8(0), -1.0);
+        assert_eq!(pan_from_u8(127), 1.0);
+        assert_eq!(volume_ratio(127), 1.0);
+        assert_eq!(volume_ratio(0), 0.0);
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        assert_eq!(pan_gains(-2.0), pan_gains(-1.0));
+        assert_eq!(pan_gains(3.0), pan_gains(1.0));
+        let wav = mix_to_wav(&[2.0, -2.0, 0.5, -0.5], 32000);
+        assert_eq!(wav.channels, vec![vec![32767, 16384], vec![-32768, -16384]]);
+    }
+}
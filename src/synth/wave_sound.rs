@@ -0,0 +1,170 @@
+use super::envelope::Adsr;
+use super::voice::{mix_to_wav, pan_from_u8, volume_ratio, Sample, Voice};
+use crate::rwsd::{NoteInfo, Rwsd, WaveSoundData};
+use crate::wav::Wav;
+use std::collections::HashMap;
+
+/// How long looping waves are played for when the note event doesn't say.
+const DEFAULT_LOOP_SECONDS: f32 = 2.0;
+/// Upper bound for a single render, in case an envelope never finishes.
+const MAX_SECONDS: f32 = 60.0;
+
+impl NoteInfo {
+    pub fn adsr(&self) -> Adsr {
+        Adsr {
+            attack: self.attack,
+            decay: self.decay,
+            sustain: self.sustain,
+            release: self.release,
+            hold: self.hold,
+        }
+    }
+}
+
+/// Renders a wave sound to a stereo WAV.
+///
+/// `volume` is `SoundInfo::volume`, and `wave_data` the wave data of the RWSD the sound belongs to
+/// (see `GroupEntry::read_archive_bytes`).
+pub fn render_wave_sound(rwsd: &Rwsd, sound: &WaveSoundData, volume: u8, wave_data: &[u8], output_rate: u32) -> Wav {
+    let info = &*sound.info;
+    let mut samples = HashMap::new();
+    let mut mix: Vec<f32> = Vec::new();
+
+    for event in sound.events() {
+        let note = match sound.note(event.note_index as usize) {
+            Some(note) => note,
+            None => continue
+        };
+        if note.wave_index < 0 {
+            continue;
+        }
+
+        let sample = samples.entry(note.wave_index)
+            .or_insert_with(|| rwsd.decode_wave(note.wave_index as usize, wave_data).map(|wav| Sample::from_wav(&wav)));
+        let sample = match sample {
+            Some(sample) => sample,
+            None => continue
+        };
+
+        let mut voice = Voice::new(sample, output_rate, note.adsr());
+        voice.set_pitch(info.pitch as f64 * note.pitch as f64);
+        voice.volume = volume_ratio(note.volume) * volume_ratio(volume);
+        voice.pan = (pan_from_u8(note.pan) + pan_from_u8(info.pan)).clamp(-1.0, 1.0);
+
+        let start = (event.position.max(0.0) * output_rate as f32) as usize;
+        let mut length = event.length;
+        if length <= 0.0 && sample.loop_start.is_some() {
+            length = DEFAULT_LOOP_SECONDS;
+        }
+
+        let max_frames = (MAX_SECONDS * output_rate as f32) as usize;
+        let mut buffer = Vec::new();
+        if length > 0.0 {
+            // played until the note ends, then released
+            let held = ((length * output_rate as f32) as usize).min(max_frames);
+            buffer.resize(held * 2, 0.0);
+            voice.render(&mut buffer);
+            voice.release();
+        }
+        // let the release (or the rest of a one-shot wave) play out
+        const CHUNK: usize = 1024;
+        while !voice.is_done() && buffer.len() / 2 < max_frames {
+            let offset = buffer.len();
+            buffer.resize(offset + CHUNK * 2, 0.0);
+            voice.render(&mut buffer[offset..]);
+        }
+
+        let begin = start * 2;
+        if mix.len() < begin + buffer.len() {
+            mix.resize(begin + buffer.len(), 0.0);
+        }
+        for (out, value) in mix[begin..].iter_mut().zip(buffer) {
+            *out += value;
+        }
+    }
+
+    mix_to_wav(&mix, output_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::SoundEncoding;
+    use crate::rwar::RwarBuilder;
+    use crate::rwav::EncodedWave;
+    use binread::BinReaderExt;
+    use binread::io::Cursor;
+
+    fn reference(offset: u32) -> [u8; 8] {
+        let mut reference = [1, 0, 0, 0, 0, 0, 0, 0];
+        reference[4..].copy_from_slice(&offset.to_be_bytes());
+        reference
+    }
+
+    // one sound with a single one-shot note of wave 0, the waves being in an RWAR
+    fn test_rwsd() -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&1u32.to_be_bytes());
+        body.extend_from_slice(&reference(0x0C));
+        for offset in [0x24, 0x44, 0x74] {
+            body.extend_from_slice(&reference(offset));
+        }
+        body.extend_from_slice(&1.0f32.to_be_bytes()); // sound info, centered
+        body.extend_from_slice(&[64, 0, 0, 0, 0, 127, 0, 0]);
+        body.resize(0x44, 0);
+        body.extend_from_slice(&1u32.to_be_bytes()); // tracks
+        body.extend_from_slice(&reference(0x50));
+        body.extend_from_slice(&reference(0x58));
+        body.extend_from_slice(&1u32.to_be_bytes()); // events
+        body.extend_from_slice(&reference(0x64));
+        body.resize(0x74, 0); // at 0 seconds, until the wave ends
+        body.extend_from_slice(&1u32.to_be_bytes()); // notes
+        body.extend_from_slice(&reference(0x80));
+        body.extend_from_slice(&0u32.to_be_bytes());
+        body.extend_from_slice(&[127, 127, 127, 127, 0, 0, 0, 0, 60, 127, 64, 0]);
+        body.extend_from_slice(&1.0f32.to_be_bytes());
+        body.resize(0xB0, 0);
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"RWSD");
+        data.extend_from_slice(&[0xFE, 0xFF, 0x01, 0x03]);
+        data.extend_from_slice(&0x100u32.to_be_bytes());
+        data.extend_from_slice(&[0, 0x20, 0, 2]);
+        for block in [0x20u32, 0xC0, 0xE0, 0x20] {
+            data.extend_from_slice(&block.to_be_bytes());
+        }
+        data.extend_from_slice(b"DATA");
+        data.extend_from_slice(&0xC0u32.to_be_bytes());
+        data.extend_from_slice(&body);
+        data.resize(0xE0, 0);
+        data.extend_from_slice(b"WAVE");
+        data.extend_from_slice(&0x20u32.to_be_bytes());
+        data.resize(0x100, 0);
+        data
+    }
+
+    #[test]
+    fn render() {
+        let rwsd: Rwsd = Cursor::new(test_rwsd()).read_be().unwrap();
+        let wav = Wav::new(1000, vec![vec![16384; 100]]);
+        let mut rwar = RwarBuilder::new();
+        rwar.push(EncodedWave::encode(&wav, SoundEncoding::SPcm16).to_rwav());
+        let wave_data = rwar.to_bytes();
+
+        let sound = rwsd.sound(0).unwrap();
+        let rendered = render_wave_sound(&rwsd, sound, 127, &wave_data, 1000);
+        assert_eq!(rendered.channels.len(), 2);
+        assert_eq!(rendered.channels[0], rendered.channels[1]);
+        // half scale, panned to the center
+        let expected = (0.5 * std::f32::consts::FRAC_1_SQRT_2 * 32767.0) as i16;
+        assert!((rendered.channels[0][0] - expected).abs() <= 2);
+        assert!((rendered.channels[0][99] - expected).abs() <= 2);
+        assert_eq!(rendered.channels[0][100], 0);
+
+        let quieter = render_wave_sound(&rwsd, sound, 64, &wave_data, 1000);
+        let ratio = quieter.channels[0][0] as f32 / rendered.channels[0][0] as f32;
+        assert!((ratio - volume_ratio(64)).abs() < 0.01);
+
+        assert_eq!(render_wave_sound(&rwsd, sound, 127, &[], 1000).sample_count(), 0);
+    }
+}
@@ -0,0 +1,349 @@
+use std::io::{self, Read, Write};
+
+/// Minimal PCM16 RIFF/WAVE container, used for exporting decoded audio and importing new audio.
+pub struct Wav {
+    pub sample_rate: u32,
+    /// One Vec of samples per channel, all the same length.
+    pub channels: Vec<Vec<i16>>,
+    /// Stored in a `smpl` chunk so that samplers and editors pick it up.
+    pub loop_points: Option<LoopPoints>,
+}
+
+/// Sample indices, `end` is exclusive.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LoopPoints {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Wav {
+    pub fn new(sample_rate: u32, channels: Vec<Vec<i16>>) -> Wav {
+        Wav { sample_rate, channels, loop_points: None }
+    }
+
+    pub fn with_loop(mut self, start: u32, end: u32) -> Wav {
+        self.loop_points = Some(LoopPoints { start, end });
+        self
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.channels.iter().map(Vec::len).max().unwrap_or(0)
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let channel_count = self.channels.len() as u16;
+        let mut wav = WavWriter::new(writer, self.sample_rate, channel_count, self.sample_count(), self.loop_points)?;
+        wav.write_samples(&self.channels)?;
+        wav.finish()?;
+        Ok(())
+    }
+}
+
+/// Writes a WAV a chunk at a time, for audio that doesn't fit in memory.
+///
+/// The length has to be known up front since it goes in the header.
+pub struct WavWriter<W: Write> {
+    writer: W,
+    sample_rate: u32,
+    channel_count: u16,
+    remaining: usize,
+    loop_points: Option<LoopPoints>,
+}
+
+impl<W: Write> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32, channel_count: u16, sample_count: usize, loop_points: Option<LoopPoints>) -> io::Result<WavWriter<W>> {
+        let block_align = channel_count * 2;
+        let data_len = sample_count as u32 * block_align as u32;
+
+        let smpl_len = if loop_points.is_some() { 8 + SMPL_LEN } else { 0 };
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(4 + (8 + 16) + (8 + data_len) + smpl_len).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&channel_count.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&data_len.to_le_bytes())?;
+
+        Ok(WavWriter { writer, sample_rate, channel_count, remaining: sample_count, loop_points })
+    }
+
+    /// Appends one chunk, given as one Vec of samples per channel. Missing samples are silent,
+    /// anything past the length given to `new` is dropped.
+    pub fn write_samples(&mut self, channels: &[Vec<i16>]) -> io::Result<()> {
+        let count = channels.iter().map(Vec::len).max().unwrap_or(0).min(self.remaining);
+        let mut buf = Vec::with_capacity(count * self.channel_count as usize * 2);
+        for idx in 0..count {
+            for channel in 0..self.channel_count as usize {
+                let sample = channels.get(channel).and_then(|samples| samples.get(idx)).copied().unwrap_or(0);
+                buf.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        self.remaining -= count;
+        self.writer.write_all(&buf)
+    }
+
+    /// Pads the data out to the promised length and writes the loop.
+    pub fn finish(mut self) -> io::Result<W> {
+        let silence = vec![vec![0; self.remaining]; self.channel_count as usize];
+        self.write_samples(&silence)?;
+        if let Some(points) = self.loop_points {
+            write_smpl(&mut self.writer, self.sample_rate, points)?;
+        }
+        Ok(self.writer)
+    }
+}
+
+fn write_smpl<W: Write>(writer: &mut W, sample_rate: u32, points: LoopPoints) -> io::Result<()> {
+    let fields = [
+        0, // manufacturer
+        0, // product
+        1_000_000_000 / sample_rate.max(1), // sample period in ns
+        60, // unity note
+        0, // pitch fraction
+        0, // SMPTE format
+        0, // SMPTE offset
+        1, // loop count
+        0, // sampler data length
+        // loop
+        0, // cue point id
+        0, // forward loop
+        points.start,
+        points.end.saturating_sub(1), // smpl loop ends are inclusive
+        0, // fraction
+        0, // play count, 0 = infinite
+    ];
+
+    writer.write_all(b"smpl")?;
+    writer.write_all(&SMPL_LEN.to_le_bytes())?;
+    for field in &fields {
+        writer.write_all(&field.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+const SMPL_LEN: u32 = 9 * 4 + 6 * 4;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn u16_at(bytes: &[u8], pos: usize) -> io::Result<u16> {
+    bytes.get(pos..pos + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid("truncated chunk"))
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> io::Result<u32> {
+    bytes.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid("truncated chunk"))
+}
+
+struct Format {
+    tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    bits: u16,
+}
+
+impl Format {
+    fn parse(chunk: &[u8]) -> io::Result<Format> {
+        let mut tag = u16_at(chunk, 0)?;
+        if tag == FORMAT_EXTENSIBLE {
+            // the sub format GUID starts with the real format tag
+            tag = u16_at(chunk, 24)?;
+        }
+        Ok(Format {
+            tag,
+            channels: u16_at(chunk, 2)?,
+            sample_rate: u32_at(chunk, 4)?,
+            bits: u16_at(chunk, 14)?,
+        })
+    }
+
+    /// Converts a single sample to 16 bits.
+    fn convert(&self, bytes: &[u8]) -> i16 {
+        match (self.tag, self.bits) {
+            (FORMAT_PCM, 8) => ((bytes[0] as i16) - 128) << 8,
+            (FORMAT_PCM, 16) => i16::from_le_bytes([bytes[0], bytes[1]]),
+            (FORMAT_PCM, 24) => i16::from_le_bytes([bytes[1], bytes[2]]),
+            (FORMAT_PCM, 32) => i16::from_le_bytes([bytes[2], bytes[3]]),
+            (FORMAT_FLOAT, 32) => {
+                let value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                (value * 32767.0).round().clamp(-32768.0, 32767.0) as i16
+            }
+            (FORMAT_FLOAT, 64) => {
+                let mut b = [0u8; 8];
+                b.copy_from_slice(&bytes[..8]);
+                (f64::from_le_bytes(b) * 32767.0).round().clamp(-32768.0, 32767.0) as i16
+            }
+            _ => 0
+        }
+    }
+
+    fn is_supported(&self) -> bool {
+        matches!((self.tag, self.bits), (FORMAT_PCM, 8) | (FORMAT_PCM, 16) | (FORMAT_PCM, 24) | (FORMAT_PCM, 32)
+            | (FORMAT_FLOAT, 32) | (FORMAT_FLOAT, 64))
+    }
+}
+
+impl Wav {
+    /// Reads integer (8/16/24/32 bit) or float PCM, converting to 16 bits.
+    /// The first loop of a `smpl` chunk, if present, becomes `loop_points`.
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Wav> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(invalid("not a RIFF/WAVE file"));
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut loop_points = None;
+
+        let mut pos = 12;
+        while pos + 8 <= bytes.len() {
+            let id = &bytes[pos..pos + 4];
+            let len = u32_at(&bytes, pos + 4)? as usize;
+            let chunk = &bytes[pos + 8..(pos + 8 + len).min(bytes.len())];
+
+            match id {
+                b"fmt " => format = Some(Format::parse(chunk)?),
+                b"data" => data = Some(chunk),
+                b"smpl" if u32_at(chunk, 28)? > 0 => {
+                    let start = u32_at(chunk, 36 + 8)?;
+                    let end = u32_at(chunk, 36 + 12)?;
+                    loop_points = Some(LoopPoints { start, end: end.saturating_add(1) });
+                }
+                _ => {}
+            }
+
+            // chunks are padded to an even length
+            pos += 8 + len + (len & 1);
+        }
+
+        let format = format.ok_or_else(|| invalid("missing fmt chunk"))?;
+        let data = data.ok_or_else(|| invalid("missing data chunk"))?;
+        if !format.is_supported() {
+            return Err(invalid("unsupported sample format"));
+        }
+        if format.channels == 0 {
+            return Err(invalid("no channels"));
+        }
+
+        let sample_len = format.bits as usize / 8;
+        let mut channels = vec![Vec::with_capacity(data.len() / sample_len / format.channels as usize); format.channels as usize];
+        for frame in data.chunks_exact(sample_len * format.channels as usize) {
+            for (channel, sample) in channels.iter_mut().zip(frame.chunks_exact(sample_len)) {
+                channel.push(format.convert(sample));
+            }
+        }
+
+        Ok(Wav { sample_rate: format.sample_rate, channels, loop_points })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout() {
+        let wav = Wav::new(32000, vec![vec![1, 2], vec![-1, -2]]).with_loop(1, 2);
+        let mut out = Vec::new();
+        wav.write(&mut out).unwrap();
+
+        assert_eq!(&out[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes([out[4], out[5], out[6], out[7]]) as usize, out.len() - 8);
+        assert_eq!(&out[36..40], b"data");
+        assert_eq!(&out[44..52], &[1, 0, 0xFF, 0xFF, 2, 0, 0xFE, 0xFF]);
+        assert_eq!(&out[52..56], b"smpl");
+        // loop start and (inclusive) end
+        assert_eq!(&out[52 + 8 + 44..52 + 8 + 52], &[1, 0, 0, 0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let wav = Wav::new(44100, vec![vec![1, 2, 3, 4], vec![-1, -2, -3, -4]]).with_loop(1, 3);
+        let mut out = Vec::new();
+        wav.write(&mut out).unwrap();
+
+        let read = Wav::read(&mut &out[..]).unwrap();
+        assert_eq!(read.sample_rate, 44100);
+        assert_eq!(read.channels, wav.channels);
+        assert_eq!(read.loop_points, Some(LoopPoints { start: 1, end: 3 }));
+    }
+
+    #[test]
+    fn open_loop_end() {
+        let mut out = Vec::new();
+        Wav::new(44100, vec![vec![1, 2]]).with_loop(0, 2).write(&mut out).unwrap();
+        let smpl = out.windows(4).position(|id| id == b"smpl").unwrap();
+        out[smpl + 8 + 48..smpl + 8 + 52].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let read = Wav::read(&mut &out[..]).unwrap();
+        assert_eq!(read.loop_points, Some(LoopPoints { start: 0, end: u32::MAX }));
+    }
+
+    fn riff(fmt_tag: u16, channels: u16, bits: u16, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(4 + 24 + 8 + data.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&fmt_tag.to_le_bytes());
+        out.extend_from_slice(&channels.to_le_bytes());
+        out.extend_from_slice(&22050u32.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&bits.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    #[test]
+    fn chunked() {
+        let wav = Wav::new(22050, vec![vec![1, 2, 3], vec![4, 5, 6]]).with_loop(0, 3);
+        let mut whole = Vec::new();
+        wav.write(&mut whole).unwrap();
+
+        let mut writer = WavWriter::new(Vec::new(), 22050, 2, 3, wav.loop_points).unwrap();
+        writer.write_samples(&[vec![1], vec![4]]).unwrap();
+        writer.write_samples(&[vec![2, 3], vec![5, 6, 7]]).unwrap();
+        assert_eq!(writer.finish().unwrap(), whole);
+
+        let short = WavWriter::new(Vec::new(), 22050, 1, 2, None).unwrap().finish().unwrap();
+        assert_eq!(Wav::read(&mut &short[..]).unwrap().channels, vec![vec![0, 0]]);
+    }
+
+    #[test]
+    fn sample_widths() {
+        let wav = Wav::read(&mut &riff(1, 1, 8, &[0x80, 0xFF, 0x00])[..]).unwrap();
+        assert_eq!(wav.channels, vec![vec![0, 0x7F00, -0x8000]]);
+
+        let wav = Wav::read(&mut &riff(1, 2, 24, &[0x00, 0x34, 0x12, 0xFF, 0xFF, 0xFF])[..]).unwrap();
+        assert_eq!(wav.channels, vec![vec![0x1234], vec![-1]]);
+
+        let wav = Wav::read(&mut &riff(3, 1, 32, &0.5f32.to_le_bytes())[..]).unwrap();
+        assert_eq!(wav.channels, vec![vec![16384]]);
+        assert_eq!(wav.loop_points, None);
+
+        assert!(Wav::read(&mut &riff(2, 1, 4, &[0])[..]).is_err());
+    }
+}